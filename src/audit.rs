@@ -0,0 +1,161 @@
+use std::io::Cursor;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use chrono::{DateTime, Utc};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Method;
+use rocket::request::{FromRequest, Outcome};
+use rocket::tokio::fs;
+use rocket::tokio::io::AsyncWriteExt;
+use rocket::tokio::sync::Mutex as AsyncMutex;
+use rocket::{Request, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::auth::TokenIdentity;
+use crate::constants::{AUDIT_OUTCOME_MAX_LEN, DEFAULT_AUDIT_LOG_FILE};
+use crate::logging::request_id;
+
+/// Who made a request: the client address, and the name of the API token it
+/// used or, failing that, the user a trusted reverse proxy passed along.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Actor {
+    pub ip: Option<String>,
+    pub user: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time: DateTime<Utc>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+    pub actor: Actor,
+    pub method: String,
+    pub route: String,
+    pub uri: String,
+    /// Request details a handler chose to record, e.g. an uploaded file name.
+    pub params: Map<String, Value>,
+    pub status: u16,
+    /// The response body, truncated.
+    pub outcome: String,
+}
+
+#[derive(Default)]
+struct RecordedParams(Mutex<Map<String, Value>>);
+
+/// Lets a handler add parameters to its audit entry that aren't visible in
+/// the URI, such as form or JSON body fields.
+pub struct AuditParams<'r>(&'r RecordedParams);
+
+impl AuditParams<'_> {
+    pub fn set(&self, key: &str, value: impl Serialize) {
+        let value = serde_json::to_value(value).unwrap_or(Value::Null);
+        self.0.0.lock().unwrap().insert(key.to_string(), value);
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuditParams<'r> {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(AuditParams(req.local_cache(RecordedParams::default)))
+    }
+}
+
+pub fn audit_log_path() -> String {
+    std::env::var("AUDIT_LOG_FILE").unwrap_or_else(|_| DEFAULT_AUDIT_LOG_FILE.to_string())
+}
+
+/// Reverse proxies whose `X-Forwarded-User`, `Remote-User` and `X-Real-IP`
+/// headers are believed, from the comma-separated `TRUSTED_PROXIES`.
+pub fn trusted_proxies_from_env() -> Vec<IpAddr> {
+    std::env::var("TRUSTED_PROXIES").unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .filter_map(|ip| ip.parse().map_err(|_| tracing::error!("Ignoring invalid TRUSTED_PROXIES entry '{}'", ip)).ok())
+        .collect()
+}
+
+/// Appends one JSON line per mutating request (POST, PUT, PATCH, DELETE).
+pub struct AuditLog {
+    path: String,
+    trusted_proxies: Vec<IpAddr>,
+    write_lock: AsyncMutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(path: String, trusted_proxies: Vec<IpAddr>) -> Self {
+        AuditLog { path, trusted_proxies, write_lock: AsyncMutex::new(()) }
+    }
+
+    /// Headers can be set by any client, so they only count when the
+    /// connection comes from a configured proxy.
+    fn actor(&self, req: &Request<'_>) -> Actor {
+        let peer = req.remote().map(|addr| addr.ip());
+        let via_proxy = peer.is_some_and(|ip| self.trusted_proxies.contains(&ip));
+        let header = |name: &str| req.headers().get_one(name).map(|v| v.to_string());
+        let token = req.local_cache(|| TokenIdentity(None)).0.clone();
+        let proxy_user = header("X-Forwarded-User").or_else(|| header("Remote-User")).filter(|_| via_proxy);
+        Actor {
+            ip: if via_proxy { req.client_ip() } else { peer }.map(|ip| ip.to_string()),
+            user: token.or(proxy_user),
+        }
+    }
+
+    async fn append(&self, entry: &AuditEntry) -> Result<(), String> {
+        let line = serde_json::to_string(entry).map_err(|e| format!("Failed to serialize audit entry: {}", e))? + "\n";
+        let _guard = self.write_lock.lock().await;
+        if let Some(parent) = Path::new(&self.path).parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).await.map_err(|e| format!("Failed to create audit log directory: {}", e))?;
+        }
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(&self.path).await
+            .map_err(|e| format!("Failed to open audit log: {}", e))?;
+        file.write_all(line.as_bytes()).await.map_err(|e| format!("Failed to write audit log: {}", e))
+    }
+
+    /// Returns one page of entries, newest first, and the total count.
+    pub async fn page(&self, page: usize, per_page: usize) -> (Vec<AuditEntry>, usize) {
+        let contents = fs::read_to_string(&self.path).await.unwrap_or_default();
+        let entries: Vec<AuditEntry> = contents.lines().filter_map(|l| serde_json::from_str(l).ok()).collect();
+        let total = entries.len();
+        let page = entries.into_iter().rev()
+            .skip(page.saturating_sub(1) * per_page)
+            .take(per_page)
+            .collect();
+        (page, total)
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for AuditLog {
+    fn info(&self) -> Info {
+        Info { name: "Audit log", kind: Kind::Response }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        if !matches!(req.method(), Method::Post | Method::Put | Method::Patch | Method::Delete) {
+            return;
+        }
+        let Some(route) = req.route() else { return };
+        let body = res.body_mut().to_string().await.unwrap_or_default();
+        let outcome: String = body.chars().take(AUDIT_OUTCOME_MAX_LEN).collect();
+        res.set_sized_body(body.len(), Cursor::new(body));
+
+        let entry = AuditEntry {
+            time: Utc::now(),
+            request_id: Some(request_id(req).0.clone()),
+            actor: self.actor(req),
+            method: req.method().to_string(),
+            route: route.uri.to_string(),
+            uri: req.uri().to_string(),
+            params: req.local_cache(RecordedParams::default).0.lock().unwrap().clone(),
+            status: res.status().code,
+            outcome,
+        };
+        if let Err(e) = self.append(&entry).await {
+            tracing::error!("{}", e);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Method;
+use rocket::http::uri::Origin;
+use rocket::{Data, Request};
+use crate::constants::{DEFAULT_TOKEN_NAME, TOKEN_COOKIE, UNAUTHORIZED_PATH};
+
+/// The name of the token a request authenticated with, if any. Stored in
+/// the request's local cache for the audit log.
+pub struct TokenIdentity(pub Option<String>);
+
+/// Requires a token, when any is configured, on every route except the
+/// dashboard page and its assets. A token is accepted as
+/// `Authorization: Bearer <token>` (the CLI, Prometheus) or in the `mc_token`
+/// cookie (the dashboard).
+pub struct TokenAuth {
+    /// Token names and values.
+    tokens: Vec<(String, String)>,
+}
+
+impl TokenAuth {
+    /// Reads `API_TOKEN` (named `api`) and `API_TOKENS`, a comma-separated
+    /// list of `name=token` pairs.
+    pub fn from_env() -> Self {
+        let mut tokens = Vec::new();
+        if let Ok(token) = std::env::var("API_TOKEN") && !token.is_empty() {
+            tokens.push((DEFAULT_TOKEN_NAME.to_string(), token));
+        }
+        for pair in std::env::var("API_TOKENS").unwrap_or_default().split(',') {
+            match pair.trim().split_once('=') {
+                Some((name, token)) if !name.is_empty() && !token.is_empty() => tokens.push((name.to_string(), token.to_string())),
+                _ if pair.trim().is_empty() => {}
+                _ => tracing::error!("Ignoring malformed API_TOKENS entry; expected name=token"),
+            }
+        }
+        TokenAuth { tokens }
+    }
+
+    /// `Ok` with the matching token's name (`None` when no token is needed),
+    /// or `Err` if the request isn't authorized.
+    fn authenticate(&self, req: &Request<'_>) -> Result<Option<String>, ()> {
+        if self.tokens.is_empty() {
+            return Ok(None);
+        }
+        let bearer = req.headers().get_one("Authorization").and_then(|h| h.strip_prefix("Bearer "));
+        let cookie = req.cookies().get(TOKEN_COOKIE).map(|c| c.value());
+        let matched = bearer.into_iter().chain(cookie).find_map(|given| {
+            self.tokens.iter()
+                .find(|(_, token)| constant_time_eq(given.trim().as_bytes(), token.as_bytes()))
+                .map(|(name, _)| name.clone())
+        });
+        let path = req.uri().path();
+        match matched {
+            Some(name) => Ok(Some(name)),
+            None if path == "/" || path.starts_with("/static/") || path == UNAUTHORIZED_PATH => Ok(None),
+            None => Err(()),
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[rocket::async_trait]
+impl Fairing for TokenAuth {
+    fn info(&self) -> Info {
+        Info { name: "Token auth", kind: Kind::Request }
+    }
+
+    /// Fairings can't answer a request themselves, so unauthorized requests
+    /// are rerouted to a handler that returns 401.
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        match self.authenticate(req) {
+            Ok(name) => {
+                req.local_cache(|| TokenIdentity(name));
+            }
+            Err(()) => {
+                req.set_method(Method::Get);
+                req.set_uri(Origin::parse(UNAUTHORIZED_PATH).unwrap());
+            }
+        }
+    }
+}
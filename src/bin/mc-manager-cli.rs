@@ -0,0 +1,791 @@
+//! Command-line client for the mc-manager HTTP API. With `--local` it runs
+//! the same operations directly against the server directory and systemd
+//! unit, for when the web server itself is down.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use tokio::io::AsyncWriteExt;
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use serde_json::{Value, json};
+use mc_manager::constants::{DEFAULT_EXTRA_MODS_DIR, DEFAULT_SERVER_LOCATION, SYSTEMD_SERVICE};
+use mc_manager::events::EventBus;
+use mc_manager::jobs::JobHandle;
+use mc_manager::worlds::{active_world, install_world, list_worlds, read_level_info, run_reset_world, set_game_rules, switch_world, world_zip_stream};
+use mc_manager::provision::{ProvisionContext, ProvisionRequest, check_request, read_registered_servers, run_provision, servers_file_path};
+use mc_manager::jvm::{JvmUpdate, detect_javas, read_jvm_settings, system_memory, update_jvm_settings, validate as validate_jvm};
+use mc_manager::mods::{install_extra_mod, list_extra_mods, remove_extra_mod};
+use mc_manager::operations::{plan_update_extras, run_backup, run_restore, run_update_extras, write_mods_list};
+use mc_manager::serverctl::{ServerBackend, server_backend};
+use mc_manager::slp::ping_local_server;
+use mc_manager::unitfile::{UnitOptions, install_unit, plan_unit};
+
+#[derive(Parser)]
+#[command(name = "mc-manager-cli", version, about = "Script the mc-manager HTTP API")]
+struct Cli {
+    /// Base URL of the manager.
+    #[arg(long, env = "MC_MANAGER_URL", default_value = "http://localhost:8000")]
+    url: String,
+    /// API token, sent as `Authorization: Bearer <token>`.
+    #[arg(long, env = "MC_MANAGER_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    #[arg(long, short, value_enum, default_value_t = Output::Table)]
+    output: Output,
+    /// Skip the HTTP API and work on SERVER_LOCATION, EXTRA_MODS_DIR and the
+    /// systemd unit directly. Doesn't coordinate with a running manager.
+    #[arg(long)]
+    local: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Output {
+    Table,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Unit state, readiness and the operation in progress.
+    Status,
+    Start,
+    Stop,
+    Restart,
+    /// Print the server log.
+    Logs {
+        /// Number of lines to show.
+        #[arg(long, short = 'n', default_value_t = 100)]
+        lines: usize,
+        /// Keep printing new lines as they are logged.
+        #[arg(long, short)]
+        follow: bool,
+    },
+    /// Back up the server and wait for the job to finish.
+    Backup {
+        /// Print the job ID and return immediately.
+        #[arg(long)]
+        no_wait: bool,
+    },
+    /// Restore the last backup and wait for the job to finish.
+    Restore {
+        /// Stop the server first if it is running.
+        #[arg(long)]
+        stop: bool,
+        #[arg(long)]
+        no_wait: bool,
+    },
+    /// Manage extra mods.
+    Mods {
+        #[command(subcommand)]
+        command: ModsCommand,
+    },
+    /// Sync extra mods into the server and restart it.
+    UpdateExtras {
+        /// Show what would be removed and copied without changing anything.
+        #[arg(long)]
+        dry_run: bool,
+        #[arg(long)]
+        no_wait: bool,
+    },
+    /// Show a background job.
+    Job { id: u64 },
+    /// Regenerate mods.list from the jars in mods/ (local mode only).
+    WriteModsList,
+    /// Run a command on the server console.
+    Console {
+        #[arg(required = true, trailing_var_arg = true)]
+        command: Vec<String>,
+    },
+    /// Show the generated systemd user unit, or install and enable it.
+    Unit {
+        #[arg(long)]
+        install: bool,
+        /// Replace an existing unit that differs.
+        #[arg(long)]
+        overwrite: bool,
+        #[command(flatten)]
+        options: UnitArgs,
+    },
+    /// List, download, upload, switch, reset or inspect worlds.
+    World {
+        #[command(subcommand)]
+        command: WorldCommand,
+    },
+    /// Set up a new server directory from a CurseForge server pack.
+    Provision(ProvisionArgs),
+    /// Server directories set up by provision.
+    Servers,
+    /// Show or edit JVM memory and flags, or list installed Java versions.
+    Jvm {
+        #[command(subcommand)]
+        command: JvmCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum WorldCommand {
+    /// Worlds in the server directory, with sizes.
+    List,
+    /// Save a world as a zip.
+    Download {
+        name: String,
+        /// Defaults to <name>.zip.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Install a world zip under a new name.
+    Upload {
+        file: PathBuf,
+        /// Defaults to the zip's file name without .zip.
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Make a world the one the server loads (server must be stopped).
+    Switch { name: String },
+    /// Seed, spawn, game rules, time and data packs from level.dat.
+    Info {
+        /// Defaults to the active world.
+        name: Option<String>,
+    },
+    /// Change game rules in level.dat (server must be stopped).
+    Gamerule {
+        #[arg(required = true, value_name = "RULE=VALUE")]
+        rules: Vec<String>,
+        /// Defaults to the active world.
+        #[arg(long)]
+        world: Option<String>,
+    },
+    /// Archive the active world so a new one is generated on the next start.
+    Reset {
+        /// Seed for the new world; random if omitted.
+        #[arg(long)]
+        seed: Option<String>,
+        #[arg(long)]
+        no_wait: bool,
+    },
+}
+
+impl WorldCommand {
+    fn rule_changes(rules: &[String]) -> Result<HashMap<String, String>, String> {
+        rules.iter()
+            .map(|kv| kv.split_once('=').map(|(k, v)| (k.to_string(), v.to_string())).ok_or(format!("Expected RULE=VALUE, got '{}'", kv)))
+            .collect()
+    }
+
+    fn upload_name(file: &std::path::Path, name: &Option<String>) -> Result<String, String> {
+        match name {
+            Some(name) => Ok(name.clone()),
+            None => file.file_stem().and_then(|n| n.to_str()).map(|n| n.to_string()).ok_or("Invalid file name".to_string()),
+        }
+    }
+}
+
+#[derive(Args)]
+struct ProvisionArgs {
+    /// CurseForge project ID of the modpack.
+    project_id: u64,
+    /// Pack file to install; defaults to the newest with a server pack.
+    #[arg(long)]
+    file: Option<u64>,
+    /// Directory to create; defaults to SERVER_LOCATION. Must be empty.
+    #[arg(long)]
+    dir: Option<String>,
+    /// Accept the Minecraft EULA (https://aka.ms/MinecraftEULA).
+    #[arg(long)]
+    accept_eula: bool,
+    /// Initial server.properties value; repeatable.
+    #[arg(long = "property", value_name = "KEY=VALUE")]
+    properties: Vec<String>,
+    /// Java used to run the loader installer.
+    #[arg(long)]
+    java: Option<String>,
+    /// Also write and enable the systemd user unit.
+    #[arg(long)]
+    install_unit: bool,
+    #[arg(long)]
+    no_wait: bool,
+}
+
+impl ProvisionArgs {
+    fn request(&self) -> Result<ProvisionRequest, String> {
+        let properties = self.properties.iter()
+            .map(|kv| kv.split_once('=').map(|(k, v)| (k.to_string(), v.to_string())).ok_or(format!("Expected KEY=VALUE, got '{}'", kv)))
+            .collect::<Result<_, _>>()?;
+        Ok(ProvisionRequest {
+            project_id: self.project_id,
+            file_id: self.file,
+            server_location: self.dir.clone(),
+            accept_eula: self.accept_eula,
+            properties,
+            java_path: self.java.clone(),
+            install_unit: self.install_unit,
+        })
+    }
+}
+
+#[derive(Subcommand)]
+enum JvmCommand {
+    /// Current settings, system memory and warnings.
+    Show,
+    /// Change settings; they apply on the next server start.
+    Set(JvmArgs),
+    /// Installed Java versions and whether they suit the pack.
+    Javas,
+}
+
+#[derive(Args)]
+struct JvmArgs {
+    /// Initial heap, e.g. 4G; empty to remove.
+    #[arg(long)]
+    xms: Option<String>,
+    /// Maximum heap, e.g. 10G; empty to remove.
+    #[arg(long)]
+    xmx: Option<String>,
+    /// g1, zgc, shenandoah or parallel.
+    #[arg(long)]
+    gc: Option<String>,
+    /// aikar or none.
+    #[arg(long)]
+    preset: Option<String>,
+    /// Replace the other JVM options; repeatable.
+    #[arg(long = "arg", value_name = "OPTION", allow_hyphen_values = true)]
+    extra_args: Vec<String>,
+    /// Remove all other JVM options.
+    #[arg(long, conflicts_with = "extra_args")]
+    clear_args: bool,
+    /// Java executable for startserver.sh.
+    #[arg(long)]
+    java: Option<String>,
+}
+
+impl JvmArgs {
+    fn update(self) -> Value {
+        let extra_args = match (self.clear_args, self.extra_args.is_empty()) {
+            (true, _) => Some(Vec::new()),
+            (false, false) => Some(self.extra_args),
+            (false, true) => None,
+        };
+        json!({
+            "xms": self.xms,
+            "xmx": self.xmx,
+            "gc": self.gc,
+            "preset": self.preset,
+            "extra_args": extra_args,
+            "java_path": self.java,
+        })
+    }
+}
+
+#[derive(Args)]
+struct UnitArgs {
+    /// Defaults to startserver.sh in the server directory.
+    #[arg(long)]
+    exec_start: Option<String>,
+    #[arg(long)]
+    working_directory: Option<String>,
+    /// systemd Restart= value.
+    #[arg(long)]
+    restart: Option<String>,
+    #[arg(long)]
+    restart_sec: Option<u64>,
+    #[arg(long)]
+    stop_timeout: Option<u64>,
+    /// e.g. 12G
+    #[arg(long)]
+    memory_max: Option<String>,
+    #[arg(long)]
+    memory_high: Option<String>,
+    /// Environment variable for the server; repeatable.
+    #[arg(long = "env", value_name = "KEY=VALUE")]
+    env: Vec<String>,
+}
+
+impl UnitArgs {
+    fn options(self) -> Result<UnitOptions, String> {
+        let environment = self.env.iter()
+            .map(|kv| kv.split_once('=').map(|(k, v)| (k.to_string(), v.to_string())).ok_or(format!("Expected KEY=VALUE, got '{}'", kv)))
+            .collect::<Result<_, _>>()?;
+        Ok(UnitOptions {
+            exec_start: self.exec_start,
+            working_directory: self.working_directory,
+            restart: self.restart,
+            restart_sec: self.restart_sec,
+            stop_timeout_secs: self.stop_timeout,
+            memory_max: self.memory_max,
+            memory_high: self.memory_high,
+            environment,
+            ..Default::default()
+        })
+    }
+}
+
+impl Command {
+    /// Whether the command needs to see or control the server process.
+    fn controls_server(&self) -> bool {
+        !matches!(self, Command::Backup { .. } | Command::Mods { .. } | Command::WriteModsList | Command::Job { .. } | Command::Unit { .. } | Command::Jvm { .. })
+            && !matches!(self, Command::Provision(_) | Command::Servers)
+            && !matches!(self, Command::World { command: WorldCommand::List | WorldCommand::Download { .. } | WorldCommand::Upload { .. } | WorldCommand::Info { .. } })
+            && !matches!(self, Command::UpdateExtras { dry_run: true, .. })
+    }
+}
+
+#[derive(Subcommand)]
+enum ModsCommand {
+    List,
+    Upload { file: PathBuf },
+    Remove { name: String },
+}
+
+struct Api {
+    client: Client,
+    url: String,
+    token: Option<String>,
+}
+
+impl Api {
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let req = self.client.request(method, format!("{}{}", self.url.trim_end_matches('/'), path));
+        match &self.token {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        }
+    }
+
+    /// Sends the request and returns the body as JSON (or as a JSON string for
+    /// plain-text responses). Non-2xx responses become errors.
+    async fn send(&self, req: RequestBuilder) -> Result<Value, String> {
+        let resp = req.send().await.map_err(|e| format!("Request failed: {}", e))?;
+        let status = resp.status();
+        let text = resp.text().await.map_err(|e| format!("Failed to read response: {}", e))?;
+        let body = serde_json::from_str(&text).unwrap_or(Value::String(text));
+        if status.is_success() {
+            return Ok(body);
+        }
+        let message = body.get("error").and_then(|e| e.as_str()).map(|e| e.to_string())
+            .or_else(|| body.get("errors").and_then(|e| e.as_array())
+                .map(|errors| errors.iter().filter_map(|e| e.as_str()).collect::<Vec<_>>().join("; ")))
+            .or_else(|| body.as_str().map(|s| s.to_string()))
+            .unwrap_or_else(|| body.to_string());
+        Err(match status {
+            StatusCode::UNAUTHORIZED => format!("{} (set --token or MC_MANAGER_TOKEN)", message),
+            _ => format!("HTTP {}: {}", status.as_u16(), message),
+        })
+    }
+
+    async fn get(&self, path: &str) -> Result<Value, String> {
+        self.send(self.request(Method::GET, path)).await
+    }
+
+    async fn post(&self, path: &str) -> Result<Value, String> {
+        self.send(self.request(Method::POST, path)).await
+    }
+
+    /// Polls a job until it finishes, reporting progress on stderr.
+    async fn wait_for_job(&self, id: u64) -> Result<Value, String> {
+        let mut printed = 0;
+        loop {
+            let job = self.get(&format!("/jobs/{}", id)).await?;
+            let log = job["log"].as_array().cloned().unwrap_or_default();
+            for line in log.iter().skip(printed) {
+                eprintln!("{}", line.as_str().unwrap_or_default());
+            }
+            printed = log.len();
+            if !job["finished"].is_null() {
+                return match job["error"].as_str() {
+                    Some(e) => Err(e.to_string()),
+                    None => Ok(job["result"].clone()),
+                };
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    async fn run_job(&self, path: &str, no_wait: bool) -> Result<Value, String> {
+        self.start_job(self.request(Method::POST, path), no_wait).await
+    }
+
+    async fn start_job(&self, req: RequestBuilder, no_wait: bool) -> Result<Value, String> {
+        let started = self.send(req).await?;
+        let id = started["job_id"].as_u64().ok_or("Response has no job ID")?;
+        if no_wait {
+            Ok(started)
+        } else {
+            self.wait_for_job(id).await
+        }
+    }
+}
+
+/// Prints objects as aligned key/value rows, arrays of objects as a table
+/// and anything else one value per line.
+fn print_table(value: &Value) {
+    match value {
+        Value::Object(map) => {
+            let width = map.keys().map(|k| k.len()).max().unwrap_or(0);
+            for (key, value) in map {
+                println!("{:width$}  {}", key, cell(value), width = width);
+            }
+        }
+        Value::Array(rows) if rows.iter().all(|r| r.is_object()) && !rows.is_empty() => {
+            let columns: Vec<&String> = rows[0].as_object().unwrap().keys().collect();
+            let cells: Vec<Vec<String>> = rows.iter()
+                .map(|r| columns.iter().map(|c| cell(&r[c.as_str()])).collect())
+                .collect();
+            let widths: Vec<usize> = columns.iter().enumerate()
+                .map(|(i, c)| cells.iter().map(|r| r[i].len()).chain([c.len()]).max().unwrap_or(0))
+                .collect();
+            let line = |values: Vec<&str>| {
+                let padded: Vec<String> = values.iter().zip(&widths).map(|(v, w)| format!("{:w$}", v, w = w)).collect();
+                println!("{}", padded.join("  ").trim_end());
+            };
+            let header: Vec<String> = columns.iter().map(|c| c.to_uppercase()).collect();
+            line(header.iter().map(String::as_str).collect());
+            for row in &cells {
+                line(row.iter().map(|s| s.as_str()).collect());
+            }
+        }
+        Value::Array(items) => items.iter().for_each(|item| println!("{}", cell(item))),
+        other => println!("{}", cell(other)),
+    }
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => "-".to_string(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) if items.iter().all(|i| !i.is_object() && !i.is_array()) => {
+            items.iter().map(cell).collect::<Vec<_>>().join(", ")
+        }
+        other => other.to_string(),
+    }
+}
+
+async fn follow_logs(api: &Api, lines: usize, output: Output) -> Result<(), String> {
+    let mut cursor: Option<String> = None;
+    loop {
+        let path = match &cursor {
+            Some(c) => format!("/logs?cursor={}", urlencode(c)),
+            None => format!("/logs?lines={}", lines),
+        };
+        let page = api.get(&path).await?;
+        for line in page["lines"].as_array().into_iter().flatten() {
+            match output {
+                Output::Json => println!("{}", line),
+                Output::Table => println!("{}", line.as_str().unwrap_or_default()),
+            }
+        }
+        cursor = page["cursor"].as_str().map(|c| c.to_string()).or(cursor);
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+}
+
+fn urlencode(s: &str) -> String {
+    s.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+fn reply(action: &str, result: Result<(), String>) -> Result<Value, String> {
+    result.map(|_| Value::String(format!("Server {} requested.", action)))
+}
+
+/// Describes a unit plan for table output: the diff against an existing
+/// unit, or the whole unit if it's new.
+fn unit_report(plan: &Value, installed: bool) -> Value {
+    let path = plan["path"].as_str().unwrap_or_default();
+    let mut report = match (plan["exists"].as_bool(), plan["diff"].as_str()) {
+        (Some(true), Some(diff)) => format!("{} differs:\n{}", path, diff),
+        (Some(true), None) => format!("{} is up to date.\n", path),
+        _ => format!("{} (new):\n{}", path, plan["content"].as_str().unwrap_or_default()),
+    };
+    if installed {
+        report += &format!("Installed and enabled {}.", plan["unit"].as_str().unwrap_or_default());
+    }
+    Value::String(report.trim_end().to_string())
+}
+
+/// Prints the backend's log, polling with its cursor while `follow` is set.
+async fn local_logs(backend: &dyn ServerBackend, lines: usize, follow: bool) -> Result<(), String> {
+    let mut cursor: Option<String> = None;
+    loop {
+        let page = backend.logs(lines, cursor.as_deref()).await?;
+        page.lines.iter().for_each(|line| println!("{}", line));
+        if !follow {
+            return Ok(());
+        }
+        cursor = page.cursor.or(cursor);
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+}
+
+/// Runs a command without the web server. Operations run in the foreground
+/// and report progress on stderr; no notifications are sent.
+async fn run_local(command: Command) -> Result<Value, String> {
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let extra_mods_dir = std::env::var("EXTRA_MODS_DIR").unwrap_or_else(|_| DEFAULT_EXTRA_MODS_DIR.to_string());
+    let backend = server_backend();
+    if backend.manages_process() && command.controls_server() {
+        return Err(format!("The {} backend runs the server inside mc-manager; use the web server for this.", backend.name()));
+    }
+    match command {
+        Command::Status => {
+            let unit_active = backend.is_active().await;
+            Ok(match ping_local_server(&server_location).await {
+                Ok(status) => json!({"backend": backend.name(), "unit_active": unit_active, "ready": true, "ping": status}),
+                Err(e) => json!({"backend": backend.name(), "unit_active": unit_active, "ready": false, "error": e}),
+            })
+        }
+        Command::Start => reply("start", backend.start().await),
+        Command::Stop => reply("stop", backend.stop().await),
+        Command::Restart => reply("restart", backend.restart().await),
+        Command::Logs { lines, follow } => local_logs(backend, lines, follow).await.map(|_| Value::Null),
+        Command::Backup { .. } => {
+            run_backup(JobHandle::detached("backup"), EventBus::new(Vec::new()), server_location).await
+        }
+        Command::Restore { stop, .. } => {
+            if !stop && backend.is_active().await {
+                return Err("The server is running; stop it first or pass --stop.".to_string());
+            }
+            run_restore(JobHandle::detached("restore"), server_location, extra_mods_dir, stop).await
+        }
+        Command::Mods { command: ModsCommand::List } => Ok(json!(list_extra_mods(&extra_mods_dir).await?)),
+        Command::Mods { command: ModsCommand::Upload { file } } => {
+            let name = install_extra_mod(&extra_mods_dir, &file).await?;
+            Ok(Value::String(format!("Installed {}", name)))
+        }
+        Command::Mods { command: ModsCommand::Remove { name } } => {
+            remove_extra_mod(&extra_mods_dir, &name).await?;
+            Ok(Value::String(format!("Removed {}", name)))
+        }
+        Command::UpdateExtras { dry_run: true, .. } => {
+            let plan = plan_update_extras(&server_location, &extra_mods_dir).await?;
+            serde_json::to_value(plan).map_err(|e| e.to_string())
+        }
+        Command::UpdateExtras { dry_run: false, .. } => {
+            run_update_extras(JobHandle::detached("update_extras"), server_location, extra_mods_dir).await
+        }
+        Command::Job { .. } => Err("Jobs only exist on the web server; local operations run in the foreground.".to_string()),
+        Command::WriteModsList => match write_mods_list(&server_location).await? {
+            Some(count) => Ok(Value::String(format!("Wrote mods.list with {} mods", count))),
+            None => Err(format!("Could not read {}/mods", server_location)),
+        },
+        Command::Console { command } => Ok(Value::String(backend.send_command(&command.join(" ")).await?)),
+        Command::Unit { install, overwrite, options } => {
+            let unit = std::env::var("SERVER_UNIT").unwrap_or_else(|_| SYSTEMD_SERVICE.to_string());
+            let plan = plan_unit(&unit, &server_location, &options.options()?).await?;
+            if install {
+                if plan.overwrites() && !overwrite {
+                    return Err(format!("{} already exists and differs; rerun with --overwrite to replace it.\n{}", plan.path, plan.diff.unwrap_or_default()));
+                }
+                install_unit(&plan).await?;
+            }
+            serde_json::to_value(plan).map_err(|e| e.to_string())
+        }
+        Command::World { command: WorldCommand::List } => Ok(json!(list_worlds(&server_location).await?)),
+        Command::World { command: WorldCommand::Download { name, output } } => {
+            let output = output.unwrap_or_else(|| PathBuf::from(format!("{}.zip", name)));
+            let mut stream = world_zip_stream(&server_location, &name).await?;
+            let mut file = tokio::fs::File::create(&output).await.map_err(|e| format!("Failed to create {}: {}", output.display(), e))?;
+            let bytes = tokio::io::copy(&mut stream, &mut file).await.map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+            Ok(Value::String(format!("Saved {} ({} bytes)", output.display(), bytes)))
+        }
+        Command::World { command: WorldCommand::Upload { file, name } } => {
+            let name = WorldCommand::upload_name(&file, &name)?;
+            let files = install_world(&server_location, &name, &file).await?;
+            Ok(Value::String(format!("Installed world {} ({} files)", name, files)))
+        }
+        Command::World { command: WorldCommand::Switch { name } } => {
+            if backend.is_active().await {
+                return Err("Stop the server before switching worlds.".to_string());
+            }
+            switch_world(&server_location, &name).await?;
+            Ok(Value::String(format!("{} is now the active world", name)))
+        }
+        Command::World { command: WorldCommand::Info { name } } => {
+            let world = match name {
+                Some(name) => name,
+                None => active_world(&server_location).await,
+            };
+            serde_json::to_value(read_level_info(&server_location, &world).await?).map_err(|e| e.to_string())
+        }
+        Command::World { command: WorldCommand::Gamerule { rules, world } } => {
+            let active = active_world(&server_location).await;
+            let world = world.unwrap_or_else(|| active.clone());
+            if world == active && backend.is_active().await {
+                return Err("Stop the server before editing game rules.".to_string());
+            }
+            let game_rules = set_game_rules(&server_location, &world, &WorldCommand::rule_changes(&rules)?).await
+                .map_err(|errors| errors.join("\n"))?;
+            Ok(json!({"world": world, "game_rules": game_rules}))
+        }
+        Command::World { command: WorldCommand::Reset { seed, .. } } => {
+            if backend.is_active().await {
+                return Err("Stop the server before resetting the world.".to_string());
+            }
+            run_reset_world(JobHandle::detached("reset_world"), server_location, seed).await
+        }
+        Command::Provision(args) => {
+            let request = args.request()?;
+            let target = check_request(&request, &server_location).await.map_err(|errors| errors.join("\n"))?;
+            run_provision(JobHandle::detached("provision"), request, target, ProvisionContext::from_env()).await
+        }
+        Command::Servers => Ok(json!(read_registered_servers(&servers_file_path()).await?)),
+        Command::Jvm { command: JvmCommand::Show } => {
+            let settings = read_jvm_settings(&server_location).await?;
+            let memory = system_memory();
+            let warnings = validate_jvm(&settings, memory).unwrap_or_else(|errors| errors);
+            Ok(json!({"settings": settings, "memory": memory, "warnings": warnings}))
+        }
+        Command::Jvm { command: JvmCommand::Set(args) } => {
+            let update: JvmUpdate = serde_json::from_value(args.update()).map_err(|e| e.to_string())?;
+            let change = update_jvm_settings(&server_location, &update).await.map_err(|errors| errors.join("\n"))?;
+            serde_json::to_value(change).map_err(|e| e.to_string())
+        }
+        Command::Jvm { command: JvmCommand::Javas } => {
+            serde_json::to_value(detect_javas(&server_location).await).map_err(|e| e.to_string())
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<Value, String> {
+    let unit_install = match &cli.command {
+        Command::Unit { install, .. } if cli.output == Output::Table => Some(*install),
+        _ => None,
+    };
+    let result = if cli.local {
+        run_local(cli.command).await
+    } else {
+        run_remote(cli).await
+    };
+    match unit_install {
+        Some(installed) => result.map(|plan| unit_report(&plan, installed)),
+        None => result,
+    }
+}
+
+async fn run_remote(cli: Cli) -> Result<Value, String> {
+    let api = Api { client: Client::new(), url: cli.url, token: cli.token };
+    match cli.command {
+        Command::Status => api.get("/status").await,
+        Command::Start => api.post("/start").await,
+        Command::Stop => api.post("/stop").await,
+        Command::Restart => api.post("/restart").await,
+        Command::Logs { lines, follow: true } => follow_logs(&api, lines, cli.output).await.map(|_| Value::Null),
+        Command::Logs { lines, follow: false } => {
+            let page = api.get(&format!("/logs?lines={}", lines)).await?;
+            Ok(page["lines"].clone())
+        }
+        Command::Backup { no_wait } => api.run_job("/backup_server", no_wait).await,
+        Command::Restore { stop, no_wait } => {
+            let path = if stop { "/restore_server?stop=true" } else { "/restore_server" };
+            api.run_job(path, no_wait).await
+        }
+        Command::Mods { command: ModsCommand::List } => api.get("/extra_mods_list").await,
+        Command::Mods { command: ModsCommand::Upload { file } } => {
+            let name = file.file_name().and_then(|n| n.to_str()).ok_or("Invalid file name")?.to_string();
+            let bytes = std::fs::read(&file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+            let form = reqwest::multipart::Form::new()
+                .part("mod_file", reqwest::multipart::Part::bytes(bytes)
+                    .file_name(name.clone())
+                    .mime_str("application/java-archive")
+                    .map_err(|e| e.to_string())?);
+            api.send(api.request(Method::POST, "/extra_mods_upload").multipart(form)).await?;
+            Ok(Value::String(format!("Uploaded {}", name)))
+        }
+        Command::Mods { command: ModsCommand::Remove { name } } => {
+            api.send(api.request(Method::DELETE, &format!("/extra_mods/{}", urlencode(&name)))).await?;
+            Ok(Value::String(format!("Removed {}", name)))
+        }
+        Command::UpdateExtras { dry_run: true, .. } => api.post("/update_extras?dry_run=true").await,
+        Command::UpdateExtras { dry_run: false, no_wait } => api.run_job("/update_extras", no_wait).await,
+        Command::Job { id } => api.get(&format!("/jobs/{}", id)).await,
+        Command::WriteModsList => Err("write-mods-list is only available with --local".to_string()),
+        Command::Console { command } => {
+            let reply = api.send(api.request(Method::POST, "/console").body(command.join(" "))).await?;
+            Ok(reply["reply"].clone())
+        }
+        Command::Unit { install, overwrite, options } => {
+            let path = format!("/systemd_unit?dry_run={}&overwrite={}", !install, overwrite);
+            api.send(api.request(Method::POST, &path).json(&options.options()?)).await
+        }
+        Command::World { command: WorldCommand::List } => api.get("/worlds").await,
+        Command::World { command: WorldCommand::Download { name, output } } => {
+            let output = output.unwrap_or_else(|| PathBuf::from(format!("{}.zip", name)));
+            let req = api.request(Method::GET, &format!("/worlds/{}/download", urlencode(&name)));
+            let mut resp = req.send().await.map_err(|e| format!("Request failed: {}", e))?;
+            if !resp.status().is_success() {
+                let status = resp.status().as_u16();
+                return Err(format!("HTTP {}: {}", status, resp.text().await.unwrap_or_default()));
+            }
+            let mut file = tokio::fs::File::create(&output).await.map_err(|e| format!("Failed to create {}: {}", output.display(), e))?;
+            let mut bytes = 0;
+            while let Some(chunk) = resp.chunk().await.map_err(|e| format!("Download failed: {}", e))? {
+                file.write_all(&chunk).await.map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+                bytes += chunk.len();
+            }
+            Ok(Value::String(format!("Saved {} ({} bytes)", output.display(), bytes)))
+        }
+        Command::World { command: WorldCommand::Upload { file, name } } => {
+            let name = WorldCommand::upload_name(&file, &name)?;
+            let bytes = std::fs::read(&file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+            let req = api.request(Method::PUT, &format!("/worlds/{}", urlencode(&name)))
+                .header("Content-Type", "application/zip")
+                .body(bytes);
+            api.send(req).await
+        }
+        Command::World { command: WorldCommand::Switch { name } } => {
+            api.post(&format!("/worlds/{}/activate", urlencode(&name))).await
+        }
+        Command::World { command: WorldCommand::Info { name } } => {
+            let query = name.map(|n| format!("?name={}", urlencode(&n))).unwrap_or_default();
+            api.get(&format!("/world/info{}", query)).await
+        }
+        Command::World { command: WorldCommand::Gamerule { rules, world } } => {
+            let query = world.map(|n| format!("?name={}", urlencode(&n))).unwrap_or_default();
+            let req = api.request(Method::PATCH, &format!("/world/gamerules{}", query)).json(&WorldCommand::rule_changes(&rules)?);
+            api.send(req).await
+        }
+        Command::World { command: WorldCommand::Reset { seed, no_wait } } => {
+            api.start_job(api.request(Method::POST, "/worlds/reset").json(&json!({"seed": seed})), no_wait).await
+        }
+        Command::Provision(args) => {
+            api.start_job(api.request(Method::POST, "/provision").json(&args.request()?), args.no_wait).await
+        }
+        Command::Servers => api.get("/servers").await,
+        Command::Jvm { command: JvmCommand::Show } => api.get("/jvm").await,
+        Command::Jvm { command: JvmCommand::Set(args) } => api.send(api.request(Method::PATCH, "/jvm").json(&args.update())).await,
+        Command::Jvm { command: JvmCommand::Javas } => api.get("/jvm/javas").await,
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let output = cli.output;
+    if cli.local {
+        // Operations report progress through tracing; show it as plain lines.
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .without_time()
+            .with_level(false)
+            .with_target(false)
+            .init();
+    }
+    match run(cli).await {
+        Ok(Value::Null) => ExitCode::SUCCESS,
+        Ok(value) => {
+            match output {
+                Output::Json => println!("{}", serde_json::to_string_pretty(&value).unwrap_or_default()),
+                Output::Table => print_table(&value),
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
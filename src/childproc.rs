@@ -0,0 +1,588 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use chrono::Local;
+use regex::Regex;
+use rocket::tokio::{self, io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader}};
+use rocket::tokio::fs::OpenOptions;
+use rocket::tokio::process::{ChildStdin, Command};
+use rocket::tokio::sync::watch;
+use crate::constants::{CHILD_LOG_LINES, COMMAND_OUTPUT_QUIET_MS, COMMAND_OUTPUT_WAIT_MS, DEFAULT_CONSOLE_LOG_FILE, DEFAULT_RESTART_DELAY_SECS, DEFAULT_SERVER_COMMAND, DEFAULT_STOP_TIMEOUT_SECS};
+use crate::serverctl::{LogPage, ServerBackend, UnitState};
+
+/// The `[time] [thread/LEVEL] [logger]: ` prefix the server's logger puts on
+/// each line.
+static LOG_PREFIX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(?:\[[^\]]*\]\s*)+:\s").unwrap());
+
+/// When the child backend starts the server again after it exits on its own,
+/// named after systemd's `Restart=` values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RestartPolicy {
+    No,
+    OnFailure,
+    Always,
+}
+
+impl RestartPolicy {
+    fn applies(self, failed: bool) -> bool {
+        match self {
+            RestartPolicy::No => false,
+            RestartPolicy::OnFailure => failed,
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChildConfig {
+    /// Shell command run from the server directory.
+    pub command: String,
+    pub server_location: String,
+    /// File the server's output is appended to, relative to the server
+    /// directory.
+    pub console_log: String,
+    pub stop_timeout_secs: u64,
+    pub restart: RestartPolicy,
+    pub restart_delay_secs: u64,
+}
+
+impl ChildConfig {
+    /// Reads `SERVER_COMMAND`, `CONSOLE_LOG_FILE`, `SERVER_STOP_TIMEOUT_SECS`,
+    /// `RESTART_POLICY` and `RESTART_DELAY_SECS`.
+    pub fn from_env(server_location: &str) -> Self {
+        let restart = match std::env::var("RESTART_POLICY").as_deref() {
+            Ok("no") => RestartPolicy::No,
+            Ok("always") => RestartPolicy::Always,
+            Ok("on-failure") | Err(_) => RestartPolicy::OnFailure,
+            Ok(other) => {
+                tracing::error!("Unknown RESTART_POLICY '{}'; using on-failure", other);
+                RestartPolicy::OnFailure
+            }
+        };
+        ChildConfig {
+            command: std::env::var("SERVER_COMMAND").unwrap_or_else(|_| DEFAULT_SERVER_COMMAND.to_string()),
+            server_location: server_location.to_string(),
+            console_log: std::env::var("CONSOLE_LOG_FILE").unwrap_or_else(|_| DEFAULT_CONSOLE_LOG_FILE.to_string()),
+            stop_timeout_secs: std::env::var("SERVER_STOP_TIMEOUT_SECS").ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(DEFAULT_STOP_TIMEOUT_SECS),
+            restart,
+            restart_delay_secs: std::env::var("RESTART_DELAY_SECS").ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_RESTART_DELAY_SECS),
+        }
+    }
+}
+
+/// The most recent `CHILD_LOG_LINES` lines of server output. Each line has a
+/// sequence number, which doubles as the log cursor.
+#[derive(Default)]
+struct OutputBuffer {
+    next: u64,
+    lines: VecDeque<String>,
+}
+
+impl OutputBuffer {
+    fn push(&mut self, line: String) {
+        self.lines.push_back(line);
+        self.next += 1;
+        while self.lines.len() > CHILD_LOG_LINES {
+            self.lines.pop_front();
+        }
+    }
+
+    fn page(&self, lines: usize, after: Option<u64>) -> LogPage {
+        let first = self.next - self.lines.len() as u64;
+        let skip = match after {
+            Some(cursor) => cursor.saturating_sub(first) as usize,
+            None => self.lines.len().saturating_sub(lines),
+        };
+        LogPage { lines: self.lines.iter().skip(skip).cloned().collect(), cursor: Some(self.next.to_string()) }
+    }
+}
+
+struct ChildState {
+    /// PID (and process group) of the running server.
+    pid: Option<u32>,
+    started: Option<String>,
+    /// Cleared by `stop`, so a pending automatic restart is abandoned.
+    wanted: bool,
+    stopping: bool,
+    restart_pending: bool,
+    n_restarts: u64,
+    /// How the last process ended, reported while nothing is running.
+    exited: UnitState,
+}
+
+fn stopped() -> UnitState {
+    UnitState { active_state: "inactive".to_string(), result: "success".to_string(), ..Default::default() }
+}
+
+/// Maps an exit status onto systemd's terms so the crash watcher treats both
+/// backends alike.
+fn exit_state(status: ExitStatus) -> UnitState {
+    match (status.code(), status.signal()) {
+        (Some(0), _) => stopped(),
+        (Some(code), _) => UnitState { active_state: "failed".to_string(), result: "exit-code".to_string(), exec_main_status: code, ..Default::default() },
+        (None, signal) => UnitState { active_state: "failed".to_string(), result: "signal".to_string(), exec_main_status: signal.unwrap_or(0), ..Default::default() },
+    }
+}
+
+struct Inner {
+    config: ChildConfig,
+    state: Mutex<ChildState>,
+    stdin: tokio::sync::Mutex<Option<ChildStdin>>,
+    output: Arc<Mutex<OutputBuffer>>,
+    /// Bumped every time a server process exits.
+    exits: watch::Sender<u64>,
+}
+
+impl Inner {
+    /// Launches the server and a task that waits for it to exit, records how
+    /// it ended and applies the restart policy.
+    async fn spawn(self: &Arc<Self>) -> Result<(), String> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.config.command)
+            .current_dir(&self.config.server_location)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .spawn()
+            .map_err(|e| format!("Failed to run '{}': {}", self.config.command, e))?;
+        self.capture(child.stdout.take());
+        self.capture(child.stderr.take());
+        *self.stdin.lock().await = child.stdin.take();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.pid = child.id();
+            state.started = Some(Local::now().format("%a %Y-%m-%d %H:%M:%S %Z").to_string());
+        }
+
+        let inner = self.clone();
+        tokio::spawn(async move {
+            let status = child.wait().await;
+            *inner.stdin.lock().await = None;
+            let restart = {
+                let mut state = inner.state.lock().unwrap();
+                state.pid = None;
+                state.exited = match status {
+                    _ if state.stopping => stopped(),
+                    Ok(status) => exit_state(status),
+                    Err(e) => {
+                        tracing::warn!("Failed to wait for server process: {}", e);
+                        stopped()
+                    }
+                };
+                let failed = state.exited.active_state == "failed";
+                if failed {
+                    tracing::warn!("Server exited: {} {}", state.exited.result, state.exited.exec_main_status);
+                }
+                state.restart_pending = state.wanted && !state.stopping && inner.config.restart.applies(failed);
+                state.restart_pending
+            };
+            inner.exits.send_modify(|n| *n += 1);
+            if restart {
+                inner.restart_after_delay().await;
+            }
+        });
+        Ok(())
+    }
+
+    /// Boxed because it calls back into `spawn`, which spawns this.
+    fn restart_after_delay(self: Arc<Self>) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(async move {
+            tokio::time::sleep(Duration::from_secs(self.config.restart_delay_secs)).await;
+            {
+                let mut state = self.state.lock().unwrap();
+                let still_wanted = state.restart_pending && state.wanted && state.pid.is_none();
+                state.restart_pending = false;
+                if !still_wanted {
+                    return;
+                }
+                state.n_restarts += 1;
+            }
+            tracing::info!("Restarting server");
+            if let Err(e) = self.spawn().await {
+                tracing::error!("{}", e);
+            }
+        })
+    }
+
+    /// Copies each line of `stream` into the in-memory buffer and the console
+    /// log file. Bytes that aren't UTF-8 are replaced rather than ending the
+    /// capture.
+    fn capture(&self, stream: Option<impl AsyncRead + Unpin + Send + 'static>) {
+        let Some(stream) = stream else { return };
+        let output = self.output.clone();
+        let console_log = Path::new(&self.config.server_location).join(&self.config.console_log);
+        tokio::spawn(async move {
+            let mut file = match OpenOptions::new().create(true).append(true).open(&console_log).await {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    tracing::warn!("Failed to open {}: {}", console_log.display(), e);
+                    None
+                }
+            };
+            let mut reader = BufReader::new(stream);
+            let mut buf = Vec::new();
+            loop {
+                buf.clear();
+                match reader.read_until(b'\n', &mut buf).await {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(e) => {
+                        tracing::warn!("Failed to read server output: {}", e);
+                        break;
+                    }
+                }
+                let end = buf.strip_suffix(b"\n").unwrap_or(&buf);
+                let line = String::from_utf8_lossy(end.strip_suffix(b"\r").unwrap_or(end)).into_owned();
+                if let Some(f) = file.as_mut() && f.write_all(format!("{}\n", line).as_bytes()).await.is_err() {
+                    file = None;
+                }
+                output.lock().unwrap().push(line);
+            }
+        });
+    }
+
+    async fn wait_for_exit(&self, timeout: Duration) -> bool {
+        let mut exits = self.exits.subscribe();
+        let exited = async {
+            while self.state.lock().unwrap().pid.is_some() {
+                if exits.changed().await.is_err() {
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(timeout, exited).await.is_ok()
+    }
+}
+
+/// Runs the server as a child of the manager, in its own process group, with
+/// stdin piped for console commands and output kept in memory and appended
+/// to the console log.
+pub struct ChildBackend {
+    inner: Arc<Inner>,
+}
+
+impl ChildBackend {
+    pub fn new(config: ChildConfig) -> Self {
+        ChildBackend {
+            inner: Arc::new(Inner {
+                config,
+                state: Mutex::new(ChildState {
+                    pid: None,
+                    started: None,
+                    wanted: false,
+                    stopping: false,
+                    restart_pending: false,
+                    n_restarts: 0,
+                    exited: stopped(),
+                }),
+                stdin: tokio::sync::Mutex::new(None),
+                output: Arc::new(Mutex::new(OutputBuffer::default())),
+                exits: watch::Sender::new(0),
+            }),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl ServerBackend for ChildBackend {
+    fn name(&self) -> &'static str {
+        "child"
+    }
+
+    fn manages_process(&self) -> bool {
+        true
+    }
+
+    async fn start(&self) -> Result<(), String> {
+        {
+            let mut state = self.inner.state.lock().unwrap();
+            if state.pid.is_some() {
+                return Ok(());
+            }
+            state.wanted = true;
+            state.restart_pending = false;
+            state.exited = stopped();
+        }
+        self.inner.spawn().await
+    }
+
+    /// Sends `stop` on the console and waits for the process to exit, killing
+    /// its process group after the stop timeout.
+    async fn stop(&self) -> Result<(), String> {
+        let pid = {
+            let mut state = self.inner.state.lock().unwrap();
+            state.wanted = false;
+            state.restart_pending = false;
+            if state.pid.is_none() {
+                return Ok(());
+            }
+            state.stopping = true;
+            state.pid
+        };
+        if let Err(e) = self.send_command("stop").await {
+            tracing::warn!("Failed to send stop command: {}", e);
+        }
+        let timeout = self.inner.config.stop_timeout_secs;
+        if !self.inner.wait_for_exit(Duration::from_secs(timeout)).await {
+            tracing::warn!("Server did not stop within {}s; killing it", timeout);
+            if let Some(pid) = pid {
+                let _ = Command::new("kill").args(["-KILL", "--", &format!("-{}", pid)]).status().await;
+            }
+            self.inner.wait_for_exit(Duration::from_secs(timeout)).await;
+        }
+        self.inner.state.lock().unwrap().stopping = false;
+        Ok(())
+    }
+
+    async fn status(&self) -> Option<UnitState> {
+        let state = self.inner.state.lock().unwrap();
+        let active_state = if state.pid.is_some() && state.stopping {
+            "deactivating"
+        } else if state.pid.is_some() {
+            "active"
+        } else if state.restart_pending {
+            // Like systemd's auto-restart state, so the crash watcher counts
+            // the failure once, through `n_restarts`.
+            "activating"
+        } else {
+            return Some(UnitState { n_restarts: state.n_restarts, ..state.exited.clone() });
+        };
+        Some(UnitState {
+            active_state: active_state.to_string(),
+            n_restarts: state.n_restarts,
+            main_pid: state.pid,
+            active_since: state.started.clone().filter(|_| state.pid.is_some()),
+            ..state.exited.clone()
+        })
+    }
+
+    async fn logs(&self, lines: usize, cursor: Option<&str>) -> Result<LogPage, String> {
+        let after = match cursor {
+            Some(c) => Some(c.parse().map_err(|_| format!("Invalid cursor: {}", c))?),
+            None => None,
+        };
+        Ok(self.inner.output.lock().unwrap().page(lines, after))
+    }
+
+    /// Writes the command to the server's stdin. Replies only show up in the
+    /// log, so the returned reply is always empty.
+    async fn send_command(&self, command: &str) -> Result<String, String> {
+        let mut stdin = self.inner.stdin.lock().await;
+        let stdin = stdin.as_mut().ok_or("Server is not running.")?;
+        let write = async {
+            stdin.write_all(format!("{}\n", command).as_bytes()).await?;
+            stdin.flush().await
+        };
+        write.await.map_err(|e| format!("Failed to write to server console: {}", e))?;
+        Ok(String::new())
+    }
+
+    /// Sends the command and returns the lines logged until the output goes
+    /// quiet, without their log prefixes. Unrelated lines logged meanwhile
+    /// are included too.
+    async fn command_output(&self, command: &str) -> Result<String, String> {
+        let cursor = self.inner.output.lock().unwrap().next;
+        self.send_command(command).await?;
+        let mut seen = cursor;
+        let mut quiet_ms = 0;
+        let mut waited_ms = 0;
+        while waited_ms < COMMAND_OUTPUT_WAIT_MS && (seen == cursor || quiet_ms < COMMAND_OUTPUT_QUIET_MS) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            waited_ms += 50;
+            let next = self.inner.output.lock().unwrap().next;
+            if next == seen {
+                quiet_ms += 50;
+            } else {
+                seen = next;
+                quiet_ms = 0;
+            }
+        }
+        let page = self.inner.output.lock().unwrap().page(0, Some(cursor));
+        Ok(page.lines.iter().map(|line| LOG_PREFIX.replace(line, "")).collect::<Vec<_>>().join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::temp_dir;
+    use super::*;
+
+    /// A directory holding `script` as the server command.
+    fn fake_server(name: &str, script: &str) -> ChildConfig {
+        let dir = temp_dir(&format!("child-{}", name));
+        std::fs::write(dir.join("server.sh"), script).unwrap();
+        ChildConfig {
+            command: "sh server.sh".to_string(),
+            server_location: dir.to_string_lossy().to_string(),
+            console_log: DEFAULT_CONSOLE_LOG_FILE.to_string(),
+            stop_timeout_secs: 2,
+            restart: RestartPolicy::No,
+            restart_delay_secs: 0,
+        }
+    }
+
+    /// Waits up to a few seconds for the server to log `line`.
+    async fn wait_for_line(backend: &ChildBackend, line: &str) {
+        for _ in 0..100 {
+            if backend.logs(CHILD_LOG_LINES, None).await.unwrap().lines.iter().any(|l| l.contains(line)) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("server never logged '{}'", line);
+    }
+
+    /// Waits up to a few seconds for the backend's status to satisfy `done`.
+    async fn wait_for_state(backend: &ChildBackend, done: impl Fn(&UnitState) -> bool) -> UnitState {
+        for _ in 0..100 {
+            let state = backend.status().await.unwrap();
+            if done(&state) {
+                return state;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("server never reached the expected state: {:?}", backend.status().await);
+    }
+
+    #[tokio::test]
+    async fn command_output_collects_logged_reply() {
+        let config = fake_server("tps", r#"
+echo "[12:00:00] [Server thread/INFO]: Done (1.0s)!"
+while read -r cmd; do
+    case "$cmd" in
+        "neoforge tps")
+            echo "[12:00:01] [Server thread/INFO] [minecraft/MinecraftServer]: minecraft:overworld: 19.500 TPS (51.282 ms/tick)"
+            echo "[12:00:01] [Server thread/INFO] [minecraft/MinecraftServer]: Overall: 19.800 TPS (50.505 ms/tick)" ;;
+        stop) exit 0 ;;
+    esac
+done
+"#);
+        let dir = config.server_location.clone();
+        let backend = ChildBackend::new(config);
+        backend.start().await.unwrap();
+        wait_for_line(&backend, "Done").await;
+        let output = backend.command_output("neoforge tps").await.unwrap();
+        assert_eq!(output, "minecraft:overworld: 19.500 TPS (51.282 ms/tick)\nOverall: 19.800 TPS (50.505 ms/tick)");
+        let (overall, dims) = crate::tps::TpsParser::new().parse(&output);
+        assert_eq!(overall.unwrap().tps, 19.8);
+        assert_eq!(dims[0].dimension, "minecraft:overworld");
+        backend.stop().await.unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn console_commands_reach_stdin_and_output_is_logged() {
+        let config = fake_server("console", r#"
+echo "Done"
+printf 'bad \377\376 bytes\r\n'
+while read -r cmd; do
+    case "$cmd" in
+        stop) exit 0 ;;
+        *) echo "got $cmd" ;;
+    esac
+done
+"#);
+        let dir = config.server_location.clone();
+        let backend = ChildBackend::new(config);
+        backend.start().await.unwrap();
+        wait_for_line(&backend, "Done").await;
+        backend.send_command("say hello").await.unwrap();
+        wait_for_line(&backend, "got say hello").await;
+        backend.stop().await.unwrap();
+
+        let lines = backend.logs(CHILD_LOG_LINES, None).await.unwrap().lines;
+        assert_eq!(lines, ["Done", "bad \u{FFFD}\u{FFFD} bytes", "got say hello"]);
+        let logged = std::fs::read_to_string(Path::new(&dir).join(DEFAULT_CONSOLE_LOG_FILE)).unwrap();
+        assert_eq!(logged, "Done\nbad \u{FFFD}\u{FFFD} bytes\ngot say hello\n");
+        let state = backend.status().await.unwrap();
+        assert_eq!((state.active_state.as_str(), state.result.as_str()), ("inactive", "success"));
+        assert!(backend.send_command("list").await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn exit_code_is_reported_as_failure() {
+        let config = fake_server("crash", "echo Done\nread -r cmd\nexit 3\n");
+        let dir = config.server_location.clone();
+        let backend = ChildBackend::new(config);
+        backend.start().await.unwrap();
+        wait_for_line(&backend, "Done").await;
+        backend.send_command("crash").await.unwrap();
+        assert!(backend.inner.wait_for_exit(Duration::from_secs(5)).await);
+        let state = backend.status().await.unwrap();
+        assert_eq!((state.active_state.as_str(), state.result.as_str(), state.exec_main_status), ("failed", "exit-code", 3));
+        assert_eq!(state.main_pid, None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stop_kills_a_server_that_ignores_it() {
+        let config = fake_server("stuck", "trap '' TERM\necho Done\nwhile true; do read -r cmd || sleep 1; done\n");
+        let dir = config.server_location.clone();
+        let backend = ChildBackend::new(config);
+        backend.start().await.unwrap();
+        wait_for_line(&backend, "Done").await;
+        let started = std::time::Instant::now();
+        backend.stop().await.unwrap();
+        assert!(started.elapsed() >= Duration::from_secs(2));
+        let state = backend.status().await.unwrap();
+        assert_eq!((state.active_state.as_str(), state.main_pid), ("inactive", None));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn restarts_after_a_crash_on_failure() {
+        let mut config = fake_server("restart", r#"
+if [ ! -e crashed ]; then
+    touch crashed
+    echo "Crashing"
+    exit 3
+fi
+echo "Done"
+while read -r cmd; do
+    [ "$cmd" = stop ] && exit 0
+done
+"#);
+        config.restart = RestartPolicy::OnFailure;
+        let dir = config.server_location.clone();
+        let backend = ChildBackend::new(config);
+        backend.start().await.unwrap();
+        let state = wait_for_state(&backend, |s| s.n_restarts == 1 && s.active_state == "active").await;
+        assert!(state.main_pid.is_some());
+        wait_for_line(&backend, "Done").await;
+        backend.stop().await.unwrap();
+        let state = backend.status().await.unwrap();
+        assert_eq!((state.active_state.as_str(), state.n_restarts), ("inactive", 1));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stop_does_not_restart_under_always() {
+        let mut config = fake_server("always", "echo Done\nwhile read -r cmd; do [ \"$cmd\" = stop ] && exit 0; done\n");
+        config.restart = RestartPolicy::Always;
+        let dir = config.server_location.clone();
+        let backend = ChildBackend::new(config);
+        backend.start().await.unwrap();
+        wait_for_line(&backend, "Done").await;
+        backend.stop().await.unwrap();
+        // Give a restart with no delay time to happen, if it were going to.
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let state = backend.status().await.unwrap();
+        assert_eq!((state.active_state.as_str(), state.main_pid, state.n_restarts), ("inactive", None, 0));
+        let done = backend.logs(CHILD_LOG_LINES, None).await.unwrap().lines.iter().filter(|l| *l == "Done").count();
+        assert_eq!(done, 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
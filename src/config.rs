@@ -0,0 +1,20 @@
+use std::path::Path;
+use rocket::tokio::fs;
+use crate::constants::{BCC_COMMON_CONFIG, DEFAULT_MODPACK_PROJECT_ID};
+use crate::models::BccCommonToml;
+
+/// Reads and parses `config/bcc-common.toml` from the given server directory.
+pub async fn read_bcc_common(server_location: &str) -> Result<BccCommonToml, String> {
+    let path = Path::new(server_location).join(BCC_COMMON_CONFIG);
+    let contents = fs::read_to_string(&path).await
+        .map_err(|e| format!("Could not read bcc-common.toml: {}", e))?;
+    toml::from_str(&contents)
+        .map_err(|e| format!("Could not parse bcc-common.toml: {}", e))
+}
+
+impl BccCommonToml {
+    /// The CurseForge project to query for updates, falling back to ATM10.
+    pub fn project_id(&self) -> u64 {
+        self.general.modpack_project_id.unwrap_or(DEFAULT_MODPACK_PROJECT_ID)
+    }
+}
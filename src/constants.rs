@@ -2,10 +2,71 @@
 pub const DEFAULT_SERVER_LOCATION: &str = "atm10";
 pub const DEFAULT_EXTRA_MODS_DIR: &str = "extra_mods";
 pub const SYSTEMD_SERVICE: &str = "atm10.service";
-pub const FILES_TO_BACKUP: [&str; 5] = [
+pub const DEFAULT_SERVER_COMMAND: &str = "./startserver.sh";
+pub const CHILD_LOG_LINES: usize = 5000;
+pub const DEFAULT_STOP_TIMEOUT_SECS: u64 = 60;
+pub const DEFAULT_RESTART_DELAY_SECS: u64 = 10;
+pub const DEFAULT_CONSOLE_LOG_FILE: &str = "console.log";
+/// How long to collect a child server's output after a command, and how long
+/// it must stay quiet once lines start arriving.
+pub const COMMAND_OUTPUT_WAIT_MS: u64 = 2000;
+pub const COMMAND_OUTPUT_QUIET_MS: u64 = 300;
+pub const USER_JVM_ARGS: &str = "user_jvm_args.txt";
+/// Memory Xmx should leave for the OS and the JVM's own overhead.
+pub const JVM_HEADROOM_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+/// Backed up along with the active world (`level-name`).
+pub const FILES_TO_BACKUP: [&str; 4] = [
     "eula.txt",
     "ops.json",
     "server.properties",
     "config",
-    "world",
 ];
+pub const DEFAULT_LEVEL_NAME: &str = "world";
+pub const LEVEL_DAT: &str = "level.dat";
+pub const DEFAULT_WORLD_ARCHIVE_DIR: &str = "world-archives";
+pub const BCC_COMMON_CONFIG: &str = "config/bcc-common.toml";
+pub const DEFAULT_MODPACK_PROJECT_ID: u64 = 925200;
+pub const DEFAULT_CURSEFORGE_API_URL: &str = "https://www.curseforge.com/api/v1";
+pub const DEFAULT_SERVERS_FILE: &str = "servers.json";
+pub const EULA_FILE: &str = "eula.txt";
+pub const SERVER_PROPERTIES: &str = "server.properties";
+pub const DEFAULT_MOTD_TEMPLATE: &str = "V{pack_version} + extras";
+pub const DEFAULT_MOTD_TEMPLATE_FILE: &str = "motd_template.txt";
+pub const OPS_FILE: &str = "ops.json";
+pub const WHITELIST_FILE: &str = "whitelist.json";
+pub const BANNED_PLAYERS_FILE: &str = "banned-players.json";
+pub const BANNED_IPS_FILE: &str = "banned-ips.json";
+pub const DEFAULT_MOJANG_API_URL: &str = "https://api.mojang.com";
+pub const RCON_TIMEOUT_SECS: u64 = 5;
+pub const LATEST_LOG: &str = "logs/latest.log";
+pub const DEFAULT_SESSIONS_FILE: &str = "sessions.json";
+pub const SESSION_POLL_INTERVAL_SECS: u64 = 30;
+pub const DEFAULT_SERVER_PORT: u16 = 25565;
+pub const DEFAULT_METRICS_INTERVAL_SECS: u64 = 15;
+pub const DEFAULT_METRICS_HISTORY: usize = 1440;
+pub const DISK_SAMPLE_EVERY: u64 = 20;
+pub const DEFAULT_TPS_INTERVAL_SECS: u64 = 60;
+pub const DEFAULT_TPS_HISTORY: usize = 1440;
+pub const DEFAULT_TPS_WARN_BELOW: f64 = 18.0;
+pub const DEFAULT_MSPT_WARN_ABOVE: f64 = 50.0;
+pub const TPS_COMMANDS: [&str; 3] = ["neoforge tps", "forge tps", "spark tps"];
+pub const TPS_COMMAND_MAX_MISSES: u32 = 3;
+pub const CRASH_REPORTS_DIR: &str = "crash-reports";
+pub const CRASH_WATCH_INTERVAL_SECS: u64 = 10;
+pub const DEFAULT_CRASH_LOOP_MAX_FAILURES: usize = 3;
+pub const DEFAULT_CRASH_LOOP_WINDOW_SECS: i64 = 600;
+pub const DEFAULT_QUARANTINE_DIR: &str = "quarantine";
+pub const QUARANTINE_LOG: &str = "quarantine.jsonl";
+pub const RECENT_EXTRAS_LIST: &str = "extras.recent";
+pub const DEFAULT_NOTIFICATIONS_FILE: &str = "notifications.toml";
+pub const DEFAULT_SINK_RETRIES: u32 = 3;
+pub const EVENT_WATCH_INTERVAL_SECS: u64 = 5;
+pub const DEFAULT_AUDIT_LOG_FILE: &str = "audit.jsonl";
+pub const AUDIT_OUTCOME_MAX_LEN: usize = 500;
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+pub const JOB_HISTORY: usize = 50;
+pub const JOB_EVENT_INTERVAL_MS: u64 = 500;
+pub const TOKEN_COOKIE: &str = "mc_token";
+/// Audit log name for the token given in `API_TOKEN`.
+pub const DEFAULT_TOKEN_NAME: &str = "api";
+pub const UNAUTHORIZED_PATH: &str = "/unauthorized";
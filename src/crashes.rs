@@ -0,0 +1,378 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use rocket::tokio::{self, fs};
+use serde::Serialize;
+use crate::constants::{CRASH_REPORTS_DIR, CRASH_WATCH_INTERVAL_SECS};
+use crate::events::{Event, EventBus};
+use crate::serverctl::{UnitState, server_backend};
+
+/// Mods that show up in every stack trace and never deserve the blame.
+const PLATFORM_MODS: [&str; 5] = ["minecraft", "forge", "neoforge", "fml", "java.base"];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SuspectedMod {
+    pub name: String,
+    pub id: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CrashReport {
+    pub file: String,
+    pub modified: Option<DateTime<Utc>>,
+    pub description: Option<String>,
+    pub exception: Option<String>,
+    /// The "Suspected Mod(s)" section of the report.
+    pub suspected_mods: Vec<SuspectedMod>,
+    /// Mod ids from the stack trace, innermost frame first.
+    pub stack_mods: Vec<String>,
+    /// Jars named in the stack trace.
+    pub stack_jars: Vec<String>,
+    /// The extra mod jar the crash points at, if any.
+    pub extra_mod: Option<String>,
+}
+
+pub struct CrashParser {
+    frame_mod: Regex,
+    frame_jar: Regex,
+    suspected: Regex,
+}
+
+impl CrashParser {
+    pub fn new() -> Self {
+        CrashParser {
+            // at TRANSFORMER/create@0.5.1.f/com.simibubi.create.Foo.bar(Foo.java:12) ~[create-0.5.1.f.jar%23123!/:?]
+            frame_mod: Regex::new(r"^\s*at [A-Z_-]+/([a-z0-9_.\-]+)@").unwrap(),
+            frame_jar: Regex::new(r"~\[([^\]%!/]+\.jar)").unwrap(),
+            // 	Create (create), Version: 0.5.1.f
+            suspected: Regex::new(r"^\s+(.+?) \(([a-z0-9_.\-]+)\), Version: (.+)$").unwrap(),
+        }
+    }
+
+    pub fn parse(&self, file: &str, contents: &str) -> CrashReport {
+        let mut report = CrashReport {
+            file: file.to_string(),
+            modified: None,
+            description: None,
+            exception: None,
+            suspected_mods: Vec::new(),
+            stack_mods: Vec::new(),
+            stack_jars: Vec::new(),
+            extra_mod: None,
+        };
+        let mut lines = contents.lines().peekable();
+        while let Some(line) = lines.next() {
+            if let Some(desc) = line.strip_prefix("Description: ") {
+                report.description = Some(desc.trim().to_string());
+                // The exception is the first non-blank line after the description.
+                while let Some(next) = lines.peek() {
+                    if next.trim().is_empty() {
+                        lines.next();
+                    } else {
+                        report.exception = Some(next.trim().to_string());
+                        break;
+                    }
+                }
+            } else if line.starts_with("Suspected Mod") {
+                while let Some(next) = lines.peek() {
+                    if let Some(cap) = self.suspected.captures(next) {
+                        report.suspected_mods.push(SuspectedMod {
+                            name: cap[1].to_string(),
+                            id: cap[2].to_string(),
+                            version: cap[3].trim().to_string(),
+                        });
+                        lines.next();
+                    } else if next.starts_with('\t') || next.starts_with("  ") {
+                        lines.next();
+                    } else {
+                        break;
+                    }
+                }
+            } else {
+                if let Some(cap) = self.frame_mod.captures(line) {
+                    let id = cap[1].to_string();
+                    if !PLATFORM_MODS.contains(&id.as_str()) && !report.stack_mods.contains(&id) {
+                        report.stack_mods.push(id);
+                    }
+                }
+                if let Some(cap) = self.frame_jar.captures(line) {
+                    let jar = cap[1].to_string();
+                    if !report.stack_jars.contains(&jar) {
+                        report.stack_jars.push(jar);
+                    }
+                }
+            }
+        }
+        report
+    }
+}
+
+impl Default for CrashParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.to_lowercase().chars().filter(|c| c.is_ascii_alphanumeric()).collect()
+}
+
+/// Finds the extra mod a crash points at: a jar named in the stack trace,
+/// or one whose file name contains a suspected / stack-trace mod id. The
+/// platform is often a suspect too, but never matches.
+pub fn match_extra_mod(report: &CrashReport, extra_mods: &[String]) -> Option<String> {
+    if let Some(jar) = report.stack_jars.iter().find(|jar| extra_mods.contains(jar)) {
+        return Some(jar.clone());
+    }
+    let ids = report.suspected_mods.iter().map(|m| m.id.as_str())
+        .filter(|id| !PLATFORM_MODS.contains(id))
+        .chain(report.stack_mods.iter().map(|m| m.as_str()));
+    for id in ids {
+        let id = normalize(id);
+        if id.len() < 3 {
+            continue;
+        }
+        if let Some(jar) = extra_mods.iter().find(|jar| normalize(jar).contains(&id)) {
+            return Some(jar.clone());
+        }
+    }
+    None
+}
+
+async fn list_files(dir: &Path, suffix: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Ok(mut entries) = fs::read_dir(dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path.is_file() && let Some(name) = path.file_name().and_then(|n| n.to_str()) && name.ends_with(suffix) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// Parses every report in `crash-reports/`, newest first.
+pub async fn read_crash_reports(server_location: &str, extra_mods_dir: &str) -> Vec<CrashReport> {
+    let dir = Path::new(server_location).join(CRASH_REPORTS_DIR);
+    let extra_mods = list_files(Path::new(extra_mods_dir), ".jar").await;
+    let parser = CrashParser::new();
+    let mut reports = Vec::new();
+    for name in list_files(&dir, ".txt").await {
+        let path = dir.join(&name);
+        let Ok(contents) = fs::read_to_string(&path).await else { continue };
+        let mut report = parser.parse(&name, &contents);
+        report.modified = fs::metadata(&path).await.ok()
+            .and_then(|m| m.modified().ok())
+            .map(DateTime::<Utc>::from);
+        report.extra_mod = match_extra_mod(&report, &extra_mods);
+        reports.push(report);
+    }
+    reports.sort_by_key(|r| std::cmp::Reverse(r.modified));
+    reports
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CrashEvent {
+    /// A new file appeared in `crash-reports/`.
+    Report { time: DateTime<Utc>, file: String },
+    /// The unit stopped or restarted with a non-success result.
+    UnitFailure { time: DateTime<Utc>, result: String, exit_status: i32, restarts: u64 },
+}
+
+/// Crash events seen since the manager started, oldest first.
+#[derive(Default)]
+pub struct CrashLog {
+    events: Mutex<Vec<CrashEvent>>,
+}
+
+impl CrashLog {
+    pub fn record(&self, event: CrashEvent) {
+        tracing::warn!(?event, "crash");
+        self.events.lock().unwrap().push(event);
+    }
+
+    pub fn events(&self) -> Vec<CrashEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+/// Failures between two polls: each automatic restart systemd counted after
+/// a bad exit, plus a fresh transition into `failed`.
+fn unit_failures(prev: &UnitState, now: &UnitState) -> u64 {
+    if now.result == "success" {
+        return 0;
+    }
+    let restarts = now.n_restarts.saturating_sub(prev.n_restarts);
+    let failed = now.active_state == "failed" && prev.active_state != "failed";
+    restarts + failed as u64
+}
+
+/// Polls `crash-reports/` and the unit's exit status, recording anything new.
+pub fn spawn_crash_watcher(log: Arc<CrashLog>, bus: EventBus, server_location: String) {
+    tokio::spawn(async move {
+        let dir = Path::new(&server_location).join(CRASH_REPORTS_DIR);
+        let mut seen: HashSet<String> = list_files(&dir, ".txt").await.into_iter().collect();
+        let mut prev_unit = server_backend().status().await;
+        let mut interval = tokio::time::interval(Duration::from_secs(CRASH_WATCH_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            let mut new_reports: Vec<String> = list_files(&dir, ".txt").await.into_iter()
+                .filter(|name| !seen.contains(name))
+                .collect();
+            new_reports.sort();
+            for file in new_reports {
+                seen.insert(file.clone());
+                bus.emit(Event::Crash { detail: format!("new crash report {}", file) });
+                log.record(CrashEvent::Report { time: Utc::now(), file });
+            }
+
+            let unit = server_backend().status().await;
+            if let (Some(prev), Some(now)) = (&prev_unit, &unit) {
+                let failures = unit_failures(prev, now);
+                if failures > 0 {
+                    bus.emit(Event::Crash { detail: format!("unit result {} (exit status {})", now.result, now.exec_main_status) });
+                }
+                for _ in 0..failures {
+                    log.record(CrashEvent::UnitFailure {
+                        time: Utc::now(),
+                        result: now.result.clone(),
+                        exit_status: now.exec_main_status,
+                        restarts: now.n_restarts,
+                    });
+                }
+            }
+            if unit.is_some() {
+                prev_unit = unit;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::temp_dir;
+    use super::*;
+
+    /// Excerpt of a Forge 1.20.1 server crash report.
+    const FORGE_REPORT: &str = "---- Minecraft Crash Report ----
+// Ouch. That hurt :(
+
+Time: 2024-10-05 14:22:31
+Description: Exception in server tick loop
+
+java.lang.NullPointerException: Cannot invoke \"net.minecraft.world.level.Level.m_8055_(net.minecraft.core.BlockPos)\" because \"level\" is null
+	at TRANSFORMER/create@0.5.1.f/com.simibubi.create.content.kinetics.base.KineticBlockEntity.tick(KineticBlockEntity.java:93) ~[create-1.20.1-0.5.1.f.jar%23443!/:0.5.1.f] {re:mixin,re:classloading,pl:mixin:APP:create.mixins.json:KineticBlockEntityMixin,pl:mixin:A}
+	at TRANSFORMER/minecraft@1.20.1/net.minecraft.world.level.Level.m_46463_(Level.java:470) ~[server-1.20.1-20230612.114412-srg.jar%23451!/:?] {re:mixin,pl:accesstransformer:B,re:classloading}
+	at TRANSFORMER/forge@47.2.0/net.minecraftforge.server.ServerLifecycleHooks.handleServerStarting(ServerLifecycleHooks.java:107) ~[forge-1.20.1-47.2.0-universal.jar%23455!/:?] {re:classloading}
+	at java.base/java.lang.Thread.run(Thread.java:833) [?:?] {}
+
+
+A detailed walkthrough of the error, its code path and all known details is as follows:
+---------------------------------------------------------------------------------------
+
+-- Head --
+Thread: Server thread
+Suspected Mods: 
+	Create (create), Version: 0.5.1.f
+		Issue tracker URL: https://github.com/Creators-of-Create/Create/issues
+		at TRANSFORMER/create@0.5.1.f/com.simibubi.create.content.kinetics.base.KineticBlockEntity.tick(KineticBlockEntity.java:93)
+	Minecraft (minecraft), Version: 1.20.1
+		Issue tracker URL: https://aka.ms/snapshotbugs?ref=game
+Stacktrace:
+	at TRANSFORMER/create@0.5.1.f/com.simibubi.create.content.kinetics.base.KineticBlockEntity.tick(KineticBlockEntity.java:93) ~[create-1.20.1-0.5.1.f.jar%23443!/:0.5.1.f] {re:mixin}
+
+-- System Details --
+Details:
+	Minecraft Version: 1.20.1
+";
+
+    /// Excerpt of a NeoForge 1.21.1 server crash report, with no suspected
+    /// mods and the culprit below a platform frame.
+    const NEOFORGE_REPORT: &str = "---- Minecraft Crash Report ----
+// Who set us up the TNT?
+
+Time: 2025-02-11 03:14:07
+Description: Ticking entity
+
+java.lang.IllegalStateException: Missing key in ResourceKey[minecraft:root / minecraft:item]: ResourceKey[minecraft:item / sophisticatedcore:upgrade]
+	at TRANSFORMER/neoforge@21.1.77/net.neoforged.neoforge.registries.DeferredHolder.value(DeferredHolder.java:104) ~[neoforge-21.1.77-universal.jar%23150!/:?] {re:classloading}
+	at TRANSFORMER/sophisticatedbackpacks@3.21.2.1/net.p3pp3rf1y.sophisticatedbackpacks.backpack.BackpackItem.inventoryTick(BackpackItem.java:225) ~[sophisticatedbackpacks-1.21.1-3.21.2.1.jar%23702!/:3.21.2.1] {re:classloading}
+	at TRANSFORMER/minecraft@1.21.1/net.minecraft.world.entity.player.Inventory.tick(Inventory.java:395) ~[server-1.21.1-20240808.144430-srg.jar%23155!/:?] {re:mixin,re:classloading}
+	at TRANSFORMER/neoforge@21.1.77/net.neoforged.neoforge.event.EventHooks.onPlayerTick(EventHooks.java:920) ~[neoforge-21.1.77-universal.jar%23150!/:?] {re:classloading}
+
+-- System Details --
+Details:
+	Minecraft Version: 1.21.1
+";
+
+    #[test]
+    fn parses_forge_report() {
+        let report = CrashParser::new().parse("crash-2024-10-05_14.22.31-server.txt", FORGE_REPORT);
+        assert_eq!(report.description.as_deref(), Some("Exception in server tick loop"));
+        assert!(report.exception.as_deref().unwrap().starts_with("java.lang.NullPointerException: Cannot invoke"));
+        let suspected: Vec<_> = report.suspected_mods.iter().map(|m| (m.name.as_str(), m.id.as_str(), m.version.as_str())).collect();
+        assert_eq!(suspected, [("Create", "create", "0.5.1.f"), ("Minecraft", "minecraft", "1.20.1")]);
+        assert_eq!(report.stack_mods, ["create"]);
+        assert_eq!(report.stack_jars, ["create-1.20.1-0.5.1.f.jar", "server-1.20.1-20230612.114412-srg.jar", "forge-1.20.1-47.2.0-universal.jar"]);
+    }
+
+    #[test]
+    fn parses_neoforge_report() {
+        let report = CrashParser::new().parse("crash.txt", NEOFORGE_REPORT);
+        assert_eq!(report.description.as_deref(), Some("Ticking entity"));
+        assert!(report.suspected_mods.is_empty());
+        assert_eq!(report.stack_mods, ["sophisticatedbackpacks"]);
+        assert!(report.stack_jars.contains(&"sophisticatedbackpacks-1.21.1-3.21.2.1.jar".to_string()));
+    }
+
+    #[test]
+    fn blames_the_right_extra_mod() {
+        let parser = CrashParser::new();
+        let extras = ["minecraftcapes-1.2.jar".to_string(), "create-1.20.1-0.5.1.f.jar".to_string(), "sophisticatedbackpacks-3.21.jar".to_string()];
+        // A jar named in the trace wins.
+        let forge = parser.parse("a.txt", FORGE_REPORT);
+        assert_eq!(match_extra_mod(&forge, &extras).as_deref(), Some("create-1.20.1-0.5.1.f.jar"));
+        // Otherwise a mod id in the jar's name; "minecraft" never counts.
+        assert_eq!(match_extra_mod(&forge, &extras[..1]), None);
+        let neoforge = parser.parse("b.txt", NEOFORGE_REPORT);
+        assert_eq!(match_extra_mod(&neoforge, &extras).as_deref(), Some("sophisticatedbackpacks-3.21.jar"));
+        assert_eq!(match_extra_mod(&parser.parse("c.txt", "Description: Watching Server\n\njava.lang.Error: ServerHangWatchdog detected\n"), &extras), None);
+    }
+
+    #[tokio::test]
+    async fn reads_reports_from_the_server() {
+        let server = temp_dir("crashes-server");
+        let extras = temp_dir("crashes-extras");
+        std::fs::create_dir(server.join(CRASH_REPORTS_DIR)).unwrap();
+        std::fs::write(server.join(CRASH_REPORTS_DIR).join("crash-1.txt"), FORGE_REPORT).unwrap();
+        std::fs::write(server.join(CRASH_REPORTS_DIR).join("notes.md"), "not a report").unwrap();
+        std::fs::write(extras.join("create-1.20.1-0.5.1.f.jar"), "jar").unwrap();
+        let reports = read_crash_reports(&server.to_string_lossy(), &extras.to_string_lossy()).await;
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].file, "crash-1.txt");
+        assert!(reports[0].modified.is_some());
+        assert_eq!(reports[0].extra_mod.as_deref(), Some("create-1.20.1-0.5.1.f.jar"));
+        std::fs::remove_dir_all(server).unwrap();
+        std::fs::remove_dir_all(extras).unwrap();
+    }
+
+    #[test]
+    fn counts_unit_failures() {
+        let state = |active: &str, result: &str, restarts: u64| UnitState {
+            active_state: active.to_string(),
+            result: result.to_string(),
+            n_restarts: restarts,
+            ..Default::default()
+        };
+        assert_eq!(unit_failures(&state("active", "success", 0), &state("active", "success", 0)), 0);
+        assert_eq!(unit_failures(&state("active", "success", 0), &state("activating", "exit-code", 2)), 2);
+        assert_eq!(unit_failures(&state("activating", "exit-code", 2), &state("failed", "exit-code", 3)), 2);
+        assert_eq!(unit_failures(&state("failed", "exit-code", 3), &state("failed", "exit-code", 3)), 0);
+    }
+}
@@ -0,0 +1,236 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use rocket::tokio::{self, fs};
+use rocket::tokio::io::AsyncWriteExt;
+use serde::{Deserialize, Serialize};
+use crate::constants::{
+    CRASH_WATCH_INTERVAL_SECS, DEFAULT_CRASH_LOOP_MAX_FAILURES, DEFAULT_CRASH_LOOP_WINDOW_SECS,
+    DEFAULT_QUARANTINE_DIR, QUARANTINE_LOG, RECENT_EXTRAS_LIST,
+};
+use crate::crashes::{CrashEvent, CrashLog, CrashReport, read_crash_reports};
+use crate::oplock::OperationLock;
+use crate::serverctl::server_backend;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CrashLoopConfig {
+    pub max_failures: usize,
+    pub window_secs: i64,
+}
+
+impl CrashLoopConfig {
+    /// Reads `CRASH_LOOP_MAX_FAILURES` and `CRASH_LOOP_WINDOW_SECS`.
+    pub fn from_env() -> Self {
+        CrashLoopConfig {
+            max_failures: std::env::var("CRASH_LOOP_MAX_FAILURES").ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(DEFAULT_CRASH_LOOP_MAX_FAILURES),
+            window_secs: std::env::var("CRASH_LOOP_WINDOW_SECS").ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(DEFAULT_CRASH_LOOP_WINDOW_SECS),
+        }
+    }
+}
+
+/// One intervention by the guard, appended to the quarantine log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuardAction {
+    pub time: DateTime<Utc>,
+    pub failures: usize,
+    /// `crash_report` when a crash report named an extra mod,
+    /// `recently_added` when the last Update Extras run was blamed.
+    pub reason: String,
+    pub crash_report: Option<String>,
+    pub quarantined: Vec<String>,
+    pub errors: Vec<String>,
+    pub restarted: bool,
+}
+
+pub fn quarantine_dir() -> String {
+    std::env::var("QUARANTINE_DIR").unwrap_or_else(|_| DEFAULT_QUARANTINE_DIR.to_string())
+}
+
+/// Records which extra mods an Update Extras run newly copied into `mods/`.
+pub async fn write_recent_extras(server_location: &str, added: &[String]) -> Result<(), String> {
+    let path = Path::new(server_location).join(RECENT_EXTRAS_LIST);
+    fs::write(&path, added.join("\n")).await
+        .map_err(|e| format!("Failed to write {}: {}", RECENT_EXTRAS_LIST, e))
+}
+
+async fn read_recent_extras(server_location: &str) -> Vec<String> {
+    let path = Path::new(server_location).join(RECENT_EXTRAS_LIST);
+    fs::read_to_string(&path).await
+        .map(|c| c.lines().map(|l| l.trim().to_string()).filter(|l| !l.is_empty()).collect())
+        .unwrap_or_default()
+}
+
+pub async fn read_guard_actions() -> Vec<GuardAction> {
+    let path = Path::new(&quarantine_dir()).join(QUARANTINE_LOG);
+    fs::read_to_string(&path).await
+        .map(|c| c.lines().filter_map(|l| serde_json::from_str(l).ok()).collect())
+        .unwrap_or_default()
+}
+
+async fn append_guard_action(action: &GuardAction) -> Result<(), String> {
+    let dir = quarantine_dir();
+    fs::create_dir_all(&dir).await.map_err(|e| format!("Failed to create quarantine directory: {}", e))?;
+    let line = serde_json::to_string(action).map_err(|e| format!("Failed to serialize guard action: {}", e))? + "\n";
+    let mut file = fs::OpenOptions::new().create(true).append(true)
+        .open(Path::new(&dir).join(QUARANTINE_LOG)).await
+        .map_err(|e| format!("Failed to open quarantine log: {}", e))?;
+    file.write_all(line.as_bytes()).await.map_err(|e| format!("Failed to write quarantine log: {}", e))
+}
+
+/// Moves `jar` out of both the extra mods directory (so Update Extras won't
+/// copy it back) and the server's `mods/` directory, into `dir`.
+async fn quarantine_jar(jar: &str, dir: &str, server_location: &str, extra_mods_dir: &str) -> Result<(), String> {
+    fs::create_dir_all(dir).await.map_err(|e| format!("Failed to create quarantine directory: {}", e))?;
+    let dest = Path::new(dir).join(jar);
+    let extra = Path::new(extra_mods_dir).join(jar);
+    let installed = Path::new(server_location).join("mods").join(jar);
+    if extra.exists() {
+        // rename fails across filesystems, so fall back to copy + remove.
+        if fs::rename(&extra, &dest).await.is_err() {
+            fs::copy(&extra, &dest).await.map_err(|e| format!("Failed to quarantine '{}': {}", jar, e))?;
+            fs::remove_file(&extra).await.map_err(|e| format!("Failed to remove '{}' from extra mods: {}", jar, e))?;
+        }
+    }
+    if installed.exists() {
+        fs::remove_file(&installed).await.map_err(|e| format!("Failed to remove '{}' from mods: {}", jar, e))?;
+    }
+    Ok(())
+}
+
+/// What to quarantine, as (reason, crash report, jars): an extra mod named
+/// by a crash report from this crash loop if there is one, otherwise the
+/// extras the last Update Extras run added. `reports` are newest first.
+fn blame(reports: Vec<CrashReport>, window_start: DateTime<Utc>, recent: Vec<String>) -> (&'static str, Option<String>, Vec<String>) {
+    let blamed = reports.into_iter()
+        .filter(|r| r.modified.is_some_and(|m| m >= window_start))
+        .find_map(|r| r.extra_mod.clone().map(|jar| (r.file, jar)));
+    match blamed {
+        Some((file, jar)) => ("crash_report", Some(file), vec![jar]),
+        None => ("recently_added", None, recent),
+    }
+}
+
+async fn intervene(failures: usize, window_start: DateTime<Utc>, server_location: &str, extra_mods_dir: &str) -> GuardAction {
+    let mut errors = Vec::new();
+    if let Err(e) = server_backend().stop().await {
+        errors.push(format!("Failed to stop server: {}", e));
+    }
+
+    let reports = read_crash_reports(server_location, extra_mods_dir).await;
+    let (reason, crash_report, candidates) = blame(reports, window_start, read_recent_extras(server_location).await);
+
+    let dir = quarantine_dir();
+    let mut quarantined = Vec::new();
+    for jar in candidates {
+        match quarantine_jar(&jar, &dir, server_location, extra_mods_dir).await {
+            Ok(()) => quarantined.push(jar),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    // Without anything to quarantine a restart would just loop again, so the
+    // server is left stopped for a human to look at.
+    let restarted = !quarantined.is_empty()
+        && server_backend().start().await.is_ok();
+    GuardAction {
+        time: Utc::now(),
+        failures,
+        reason: reason.to_string(),
+        crash_report,
+        quarantined,
+        errors,
+        restarted,
+    }
+}
+
+/// Watches the crash log for `max_failures` unit failures within the window
+/// and intervenes once per crash loop.
+pub fn spawn_crash_guard(
+    crash_log: Arc<CrashLog>,
+    lock: Arc<OperationLock>,
+    config: CrashLoopConfig,
+    server_location: String,
+    extra_mods_dir: String,
+) {
+    tokio::spawn(async move {
+        let mut last_action: Option<DateTime<Utc>> = None;
+        let mut interval = tokio::time::interval(Duration::from_secs(CRASH_WATCH_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            let window_start = Utc::now() - chrono::Duration::seconds(config.window_secs);
+            let since = last_action.map_or(window_start, |t| t.max(window_start));
+            let failures = crash_log.events().iter()
+                .filter(|e| matches!(e, CrashEvent::UnitFailure { time, .. } if *time > since))
+                .count();
+            if failures < config.max_failures {
+                continue;
+            }
+            // If someone is already working on the server, check again next tick.
+            let Ok(_guard) = lock.try_acquire("crash_guard") else { continue };
+            let action = intervene(failures, window_start, &server_location, &extra_mods_dir).await;
+            tracing::warn!(?action, "crash loop detected");
+            if let Err(e) = append_guard_action(&action).await {
+                tracing::error!("{}", e);
+            }
+            last_action = Some(action.time);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::temp_dir;
+    use super::*;
+
+    fn report(file: &str, minutes_ago: i64, extra_mod: Option<&str>) -> CrashReport {
+        CrashReport {
+            file: file.to_string(),
+            modified: Some(Utc::now() - chrono::Duration::minutes(minutes_ago)),
+            description: None,
+            exception: None,
+            suspected_mods: Vec::new(),
+            stack_mods: Vec::new(),
+            stack_jars: Vec::new(),
+            extra_mod: extra_mod.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn blames_a_recent_report_before_recent_extras() {
+        let window_start = Utc::now() - chrono::Duration::minutes(10);
+        let recent = vec!["new-a.jar".to_string(), "new-b.jar".to_string()];
+        let reports = vec![report("crash-3.txt", 1, None), report("crash-2.txt", 5, Some("bad.jar")), report("crash-1.txt", 60, Some("old.jar"))];
+        assert_eq!(blame(reports, window_start, recent.clone()), ("crash_report", Some("crash-2.txt".to_string()), vec!["bad.jar".to_string()]));
+        // A report from before the crash loop doesn't count.
+        let reports = vec![report("crash-3.txt", 1, None), report("crash-1.txt", 60, Some("old.jar"))];
+        assert_eq!(blame(reports, window_start, recent.clone()), ("recently_added", None, recent));
+    }
+
+    #[tokio::test]
+    async fn quarantines_from_extras_and_mods() {
+        let root = temp_dir("crashguard");
+        let (server, extras, quarantine) = (root.join("server"), root.join("extras"), root.join("quarantine"));
+        std::fs::create_dir_all(server.join("mods")).unwrap();
+        std::fs::create_dir_all(&extras).unwrap();
+        std::fs::write(extras.join("bad.jar"), "jar").unwrap();
+        std::fs::write(server.join("mods/bad.jar"), "jar").unwrap();
+        std::fs::write(server.join("mods/good.jar"), "jar").unwrap();
+        let (server, extras, quarantine) = (server.to_string_lossy(), extras.to_string_lossy(), quarantine.to_string_lossy());
+
+        write_recent_extras(&server, &["bad.jar".to_string(), "good.jar".to_string()]).await.unwrap();
+        assert_eq!(read_recent_extras(&server).await, ["bad.jar", "good.jar"]);
+        quarantine_jar("bad.jar", &quarantine, &server, &extras).await.unwrap();
+        assert!(Path::new(&*quarantine).join("bad.jar").exists());
+        assert!(!Path::new(&*extras).join("bad.jar").exists());
+        assert!(!Path::new(&*server).join("mods/bad.jar").exists());
+        assert!(Path::new(&*server).join("mods/good.jar").exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
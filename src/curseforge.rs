@@ -1,6 +1,10 @@
+use std::sync::Mutex;
 use serde::{Deserialize};
 use reqwest::Client;
 use semver::Version;
+use crate::constants::DEFAULT_CURSEFORGE_API_URL;
+
+const USER_AGENT: &str = "mc-manager/1.0 (https://github.com/xela/mc-manager)";
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Deserialize)]
@@ -48,6 +52,9 @@ pub struct CurseForgeFile {
     pub is_early_access_content: bool,
     #[serde(rename = "isCompatibleWithClient")]
     pub is_compatible_with_client: bool,
+    /// Set on the server pack among a file's additional files.
+    #[serde(rename = "isServerPack", default)]
+    pub is_server_pack: bool,
 }
 #[allow(dead_code)]
 #[derive(Debug, Clone, Deserialize)]
@@ -65,31 +72,94 @@ pub struct CurseForgeFilesResponse {
     pub pagination: CurseForgePagination,
 }
 #[derive(Debug, Clone, Deserialize)]
+struct CurseForgeFileResponse {
+    data: CurseForgeFile,
+}
+#[derive(Debug, Clone, Deserialize)]
+struct CurseForgeFileList {
+    data: Vec<CurseForgeFile>,
+}
+#[derive(Debug, Clone, Deserialize)]
 pub struct ServerPackInfo {
     pub version: String,
 }
 
-pub async fn fetch_latest_server_pack(client: &Client) -> Result<ServerPackInfo, String> {
-    let api_url = "https://www.curseforge.com/api/v1/mods/925200/files/";
-    let resp = client.get(api_url)
-        .header("User-Agent", "mc-manager/1.0 (https://github.com/xela/mc-manager)")
+/// The CurseForge API root, overridable with `CURSEFORGE_API_URL` (e.g. to
+/// point at a mirror or a local fixture server).
+pub fn api_base() -> String {
+    std::env::var("CURSEFORGE_API_URL").unwrap_or_else(|_| DEFAULT_CURSEFORGE_API_URL.to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(client: &Client, api_url: &str, path: &str) -> Result<T, String> {
+    let resp = client.get(format!("{}{}", api_url, path))
+        .header("User-Agent", USER_AGENT)
         .send().await.map_err(|_| "Failed to fetch CurseForge API".to_string())?;
-    let api_json: CurseForgeFilesResponse = resp.json().await.map_err(|_| "Failed to parse CurseForge API response".to_string())?;
-    let mut server_packs: Vec<_> = api_json.data.into_iter()
+    if !resp.status().is_success() {
+        return Err(format!("CurseForge API returned {} for {}", resp.status(), path));
+    }
+    resp.json().await.map_err(|_| "Failed to parse CurseForge API response".to_string())
+}
+
+/// The newest file of `project_id` that has a server pack.
+pub async fn fetch_latest_pack_file(client: &Client, api_url: &str, project_id: u64) -> Result<CurseForgeFile, String> {
+    let api_json: CurseForgeFilesResponse = get_json(client, api_url, &format!("/mods/{}/files/", project_id)).await?;
+    api_json.data.into_iter()
         .filter(|file| file.has_server_pack)
-        .collect();
-    server_packs.sort_by_key(|file| -file.id);
-    let latest = server_packs.first().ok_or("No server pack found")?;
+        .max_by_key(|file| file.id)
+        .ok_or("No server pack found".to_string())
+}
+
+pub async fn fetch_file(client: &Client, api_url: &str, project_id: u64, file_id: u64) -> Result<CurseForgeFile, String> {
+    let resp: CurseForgeFileResponse = get_json(client, api_url, &format!("/mods/{}/files/{}", project_id, file_id)).await?;
+    Ok(resp.data)
+}
+
+/// The server pack attached to a client pack file.
+pub async fn fetch_server_pack_file(client: &Client, api_url: &str, file: &CurseForgeFile) -> Result<CurseForgeFile, String> {
+    let path = format!("/mods/{}/files/{}/additional-files", file.project_id, file.id);
+    let files: CurseForgeFileList = get_json(client, api_url, &path).await?;
+    files.data.into_iter()
+        .find(|f| f.is_server_pack)
+        .ok_or_else(|| format!("{} has no server pack", file.display_name))
+}
+
+/// Starts downloading a file; the caller reads the body in chunks.
+pub async fn download_file(client: &Client, api_url: &str, project_id: u64, file_id: i64) -> Result<reqwest::Response, String> {
+    let url = format!("{}/mods/{}/files/{}/download", api_url, project_id, file_id);
+    let resp = client.get(&url)
+        .header("User-Agent", USER_AGENT)
+        .send().await.map_err(|e| format!("Failed to download {}: {}", url, e))?;
+    if !resp.status().is_success() {
+        return Err(format!("Download of {} failed with {}", url, resp.status()));
+    }
+    Ok(resp)
+}
+
+/// The pack version from a file's display name, e.g. `All the Mods 10-2.41`.
+pub fn pack_version(file: &CurseForgeFile) -> String {
     // Extract version by splitting on the last '-' character
-    let version_str = match latest.display_name.rsplit_once('-') {
+    let version_str = match file.display_name.rsplit_once('-') {
         Some((_, v)) => v.trim(),
         None => "unknown",
     };
     // Try to parse as semver, fallback to string if not possible
-    let version = Version::parse(version_str)
+    Version::parse(version_str)
         .map(|v| v.to_string())
-        .unwrap_or_else(|_| version_str.to_string());
+        .unwrap_or_else(|_| version_str.to_string())
+}
+
+pub async fn fetch_latest_server_pack(client: &Client, project_id: u64) -> Result<ServerPackInfo, String> {
+    let latest = fetch_latest_pack_file(client, &api_base(), project_id).await?;
     Ok(ServerPackInfo {
-        version,
+        version: pack_version(&latest),
     })
 }
+
+/// Result of the most recent update check, so it can be reported without
+/// hitting CurseForge again.
+#[derive(Default)]
+pub struct UpdateCheckCache {
+    pub update_available: Mutex<Option<bool>>,
+}
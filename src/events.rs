@@ -0,0 +1,296 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use rocket::tokio;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use crate::constants::{DEFAULT_NOTIFICATIONS_FILE, DEFAULT_SINK_RETRIES, EVENT_WATCH_INTERVAL_SECS, LATEST_LOG};
+use crate::serverctl::server_backend;
+use crate::sessions::{LogEvent, LogParser, LogPosition, read_new_lines};
+
+pub const EVENT_KINDS: [&str; 6] = ["start", "stop", "crash", "backup_complete", "update_available", "player_join"];
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Start,
+    Stop,
+    Crash { detail: String },
+    BackupComplete { backup_dir: String },
+    UpdateAvailable { local_version: String, latest_version: String },
+    PlayerJoin { player: String },
+}
+
+impl Event {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Start => "start",
+            Event::Stop => "stop",
+            Event::Crash { .. } => "crash",
+            Event::BackupComplete { .. } => "backup_complete",
+            Event::UpdateAvailable { .. } => "update_available",
+            Event::PlayerJoin { .. } => "player_join",
+        }
+    }
+
+    /// One-line human-readable summary, used by the Discord and ntfy sinks.
+    pub fn message(&self) -> String {
+        match self {
+            Event::Start => "Server started.".to_string(),
+            Event::Stop => "Server stopped.".to_string(),
+            Event::Crash { detail } => format!("Server crashed: {}", detail),
+            Event::BackupComplete { backup_dir } => format!("Backup complete: {}", backup_dir),
+            Event::UpdateAvailable { local_version, latest_version } => {
+                format!("Modpack update available: {} -> {}", local_version, latest_version)
+            }
+            Event::PlayerJoin { player } => format!("{} joined the game.", player),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkKind {
+    /// POSTs the event as JSON.
+    Webhook,
+    /// POSTs `{"content": ...}` to a Discord webhook URL.
+    Discord,
+    /// POSTs the message as plain text, with an ntfy `Title` header.
+    Ntfy,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SinkConfig {
+    pub kind: SinkKind,
+    pub url: String,
+    /// Event kinds to deliver; empty means all of them.
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+fn default_retries() -> u32 {
+    DEFAULT_SINK_RETRIES
+}
+
+impl SinkConfig {
+    pub fn accepts(&self, event: &Event) -> bool {
+        self.events.is_empty() || self.events.iter().any(|e| e == event.kind())
+    }
+
+    fn request(&self, client: &Client, event: &Event, time: DateTime<Utc>) -> reqwest::RequestBuilder {
+        match self.kind {
+            SinkKind::Webhook => {
+                let mut body = serde_json::to_value(event).unwrap_or_else(|_| json!({}));
+                body["time"] = json!(time);
+                body["message"] = json!(event.message());
+                client.post(&self.url).json(&body)
+            }
+            SinkKind::Discord => client.post(&self.url).json(&json!({"content": event.message()})),
+            SinkKind::Ntfy => client.post(&self.url)
+                .header("Title", format!("mc-manager: {}", event.kind()))
+                .header("Content-Type", "text/plain")
+                .body(event.message()),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct NotificationsFile {
+    #[serde(default)]
+    sinks: Vec<SinkConfig>,
+}
+
+pub fn notifications_path() -> String {
+    std::env::var("NOTIFICATIONS_FILE").unwrap_or_else(|_| DEFAULT_NOTIFICATIONS_FILE.to_string())
+}
+
+/// Reads the `[[sinks]]` tables from the notifications file. A missing file
+/// means no sinks.
+pub fn load_sinks(path: &str) -> Result<Vec<SinkConfig>, String> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read {}: {}", path, e)),
+    };
+    let file: NotificationsFile = toml::from_str(&contents).map_err(|e| format!("Failed to parse {}: {}", path, e))?;
+    for sink in &file.sinks {
+        if let Some(unknown) = sink.events.iter().find(|e| !EVENT_KINDS.contains(&e.as_str())) {
+            return Err(format!("Unknown event '{}' for sink {}", unknown, sink.url));
+        }
+    }
+    Ok(file.sinks)
+}
+
+/// Fans events out to the configured sinks. Delivery happens in the
+/// background so emitting never blocks a request.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    sinks: Arc<Vec<SinkConfig>>,
+    client: Client,
+}
+
+impl EventBus {
+    pub fn new(sinks: Vec<SinkConfig>) -> Self {
+        EventBus { sinks: Arc::new(sinks), client: Client::new() }
+    }
+
+    pub fn from_env() -> Self {
+        match load_sinks(&notifications_path()) {
+            Ok(sinks) => EventBus::new(sinks),
+            Err(e) => {
+                tracing::warn!("{}", e);
+                EventBus::default()
+            }
+        }
+    }
+
+    pub fn emit(&self, event: Event) {
+        let time = Utc::now();
+        for sink in self.sinks.iter().filter(|s| s.accepts(&event)) {
+            let sink = sink.clone();
+            let client = self.client.clone();
+            let event = event.clone();
+            tokio::spawn(async move {
+                if let Err(e) = deliver(&client, &sink, &event, time).await {
+                    tracing::warn!("{}", e);
+                }
+            });
+        }
+    }
+}
+
+/// Sends one event, retrying failed attempts with exponential backoff.
+async fn deliver(client: &Client, sink: &SinkConfig, event: &Event, time: DateTime<Utc>) -> Result<(), String> {
+    let mut last_error = String::new();
+    for attempt in 0..=sink.retries {
+        if attempt > 0 {
+            tokio::time::sleep(Duration::from_secs(1 << (attempt - 1).min(6))).await;
+        }
+        match sink.request(client, event, time).send().await {
+            Ok(resp) if resp.status().is_success() => return Ok(()),
+            Ok(resp) => last_error = format!("HTTP {}", resp.status()),
+            Err(e) => last_error = e.to_string(),
+        }
+    }
+    Err(format!("Giving up on '{}' event for {} after {} attempts: {}", event.kind(), sink.url, sink.retries + 1, last_error))
+}
+
+/// Emits start/stop on unit state changes and player joins from the log.
+/// Crash, backup and update events are emitted where they are detected.
+pub fn spawn_event_watcher(bus: EventBus, server_location: String) {
+    tokio::spawn(async move {
+        let parser = LogParser::new();
+        let log_path = Path::new(&server_location).join(LATEST_LOG);
+        let mut pos = LogPosition::at_end(&log_path).await;
+        let mut was_active = server_backend().is_active().await;
+        let mut interval = tokio::time::interval(Duration::from_secs(EVENT_WATCH_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            let active = server_backend().is_active().await;
+            if active != was_active {
+                bus.emit(if active { Event::Start } else { Event::Stop });
+                was_active = active;
+            }
+            for line in read_new_lines(&log_path, &mut pos).await {
+                if let Some(LogEvent::Join(player)) = parser.event(&line) {
+                    bus.emit(Event::PlayerJoin { player });
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::tokio::sync::mpsc;
+    use crate::testutil::{Request, http_stand_in, temp_dir};
+    use super::*;
+
+    /// A stand-in answering with `statuses` in turn (200 once they run out).
+    fn stand_in(statuses: Vec<u16>) -> (String, mpsc::UnboundedReceiver<Request>) {
+        let mut statuses = statuses.into_iter();
+        http_stand_in(move |_| (statuses.next().unwrap_or(200), Vec::new()))
+    }
+
+    async fn next(rx: &mut mpsc::UnboundedReceiver<Request>) -> Request {
+        tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.expect("no request arrived").unwrap()
+    }
+
+    /// Nothing else arrives within a short grace period.
+    async fn assert_idle(rx: &mut mpsc::UnboundedReceiver<Request>) {
+        if let Ok(Some(extra)) = tokio::time::timeout(Duration::from_millis(300), rx.recv()).await {
+            panic!("unexpected request to {}: {}", extra.path, extra.text());
+        }
+    }
+
+    fn sink(kind: SinkKind, url: String, events: &[&str]) -> SinkConfig {
+        SinkConfig { kind, url, events: events.iter().map(|e| e.to_string()).collect(), retries: 0 }
+    }
+
+    #[tokio::test]
+    async fn sinks_get_their_format_and_only_their_events() {
+        let (url, mut rx) = stand_in(Vec::new());
+        let bus = EventBus::new(vec![
+            sink(SinkKind::Webhook, format!("{}/hook", url), &["backup_complete"]),
+            sink(SinkKind::Discord, format!("{}/discord", url), &["player_join"]),
+            sink(SinkKind::Ntfy, format!("{}/ntfy", url), &[]),
+        ]);
+
+        bus.emit(Event::BackupComplete { backup_dir: "backup/2026-10-19".to_string() });
+        let mut got = [next(&mut rx).await, next(&mut rx).await];
+        got.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_idle(&mut rx).await;
+        let (hook, ntfy) = (&got[0], &got[1]);
+        assert_eq!((hook.method.as_str(), hook.path.as_str()), ("POST", "/hook"));
+        let body = hook.json();
+        assert_eq!(body["event"], "backup_complete");
+        assert_eq!(body["backup_dir"], "backup/2026-10-19");
+        assert_eq!(body["message"], "Backup complete: backup/2026-10-19");
+        assert!(body["time"].as_str().unwrap().parse::<DateTime<Utc>>().is_ok());
+        assert_eq!(ntfy.path, "/ntfy");
+        assert_eq!(ntfy.text(), "Backup complete: backup/2026-10-19");
+        assert_eq!(ntfy.header("Title"), Some("mc-manager: backup_complete"));
+        assert_eq!(ntfy.header("Content-Type"), Some("text/plain"));
+
+        bus.emit(Event::PlayerJoin { player: "Steve".to_string() });
+        let mut got = [next(&mut rx).await, next(&mut rx).await];
+        got.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_idle(&mut rx).await;
+        assert_eq!(got[0].path, "/discord");
+        assert_eq!(got[0].json(), json!({"content": "Steve joined the game."}));
+        assert_eq!(got[1].path, "/ntfy");
+    }
+
+    #[tokio::test]
+    async fn failed_deliveries_are_retried() {
+        let (url, mut rx) = stand_in(vec![500]);
+        let mut retrying = sink(SinkKind::Webhook, url, &[]);
+        retrying.retries = 1;
+        EventBus::new(vec![retrying]).emit(Event::Start);
+        assert_eq!(next(&mut rx).await.json()["event"], "start");
+        assert_eq!(next(&mut rx).await.json()["event"], "start");
+        assert_idle(&mut rx).await;
+    }
+
+    #[test]
+    fn loads_sinks_and_rejects_unknown_events() {
+        let dir = temp_dir("notifications");
+        let path = dir.join("notifications.toml");
+        std::fs::write(&path, "[[sinks]]\nkind = \"discord\"\nurl = \"http://x\"\nevents = [\"crash\"]\n").unwrap();
+        let sinks = load_sinks(path.to_str().unwrap()).unwrap();
+        assert_eq!(sinks[0].kind, SinkKind::Discord);
+        assert_eq!(sinks[0].retries, DEFAULT_SINK_RETRIES);
+        assert!(sinks[0].accepts(&Event::Crash { detail: String::new() }));
+        assert!(!sinks[0].accepts(&Event::Start));
+
+        std::fs::write(&path, "[[sinks]]\nkind = \"webhook\"\nurl = \"http://x\"\nevents = [\"explode\"]\n").unwrap();
+        assert_eq!(load_sinks(path.to_str().unwrap()).unwrap_err(), "Unknown event 'explode' for sink http://x");
+        std::fs::remove_file(&path).unwrap();
+        assert!(load_sinks(path.to_str().unwrap()).unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
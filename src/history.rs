@@ -0,0 +1,55 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use chrono::{DateTime, Utc};
+
+/// Anything kept in a [`History`] carries the time it was taken.
+pub trait Sample: Clone {
+    fn time(&self) -> DateTime<Utc>;
+}
+
+/// Fixed-size in-memory time series, oldest first.
+pub struct History<T> {
+    pub interval_secs: u64,
+    capacity: usize,
+    samples: Mutex<VecDeque<T>>,
+}
+
+impl<T: Sample> History<T> {
+    pub fn new(interval_secs: u64, capacity: usize) -> Self {
+        History { interval_secs, capacity, samples: Mutex::new(VecDeque::with_capacity(capacity)) }
+    }
+
+    /// Reads the interval and capacity from the given environment variables,
+    /// falling back to the defaults when unset or invalid.
+    pub fn from_env(interval_var: &str, default_interval: u64, capacity_var: &str, default_capacity: usize) -> Self {
+        let interval = std::env::var(interval_var).ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(default_interval);
+        let capacity = std::env::var(capacity_var).ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(default_capacity);
+        Self::new(interval, capacity)
+    }
+
+    pub fn push(&self, sample: T) {
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() == self.capacity {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    pub fn since(&self, since: Option<DateTime<Utc>>) -> Vec<T> {
+        let samples = self.samples.lock().unwrap();
+        samples.iter()
+            .filter(|s| since.is_none_or(|t| s.time() > t))
+            .cloned()
+            .collect()
+    }
+
+    pub fn latest(&self) -> Option<T> {
+        self.samples.lock().unwrap().back().cloned()
+    }
+}
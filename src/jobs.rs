@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use chrono::{DateTime, Utc};
+use rocket::tokio;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::Instrument;
+use crate::constants::JOB_HISTORY;
+use crate::oplock::OperationGuard;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Progress {
+    pub files_done: u64,
+    pub files_total: Option<u64>,
+    pub bytes_done: u64,
+    pub bytes_total: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobSnapshot {
+    pub id: u64,
+    pub kind: String,
+    pub status: JobStatus,
+    pub started: DateTime<Utc>,
+    pub finished: Option<DateTime<Utc>>,
+    pub progress: Progress,
+    pub log: Vec<String>,
+    pub result: Option<Value>,
+    pub error: Option<String>,
+}
+
+/// A running operation's view of its own job, used to report progress.
+#[derive(Clone)]
+pub struct JobHandle {
+    state: Arc<Mutex<JobSnapshot>>,
+}
+
+impl JobHandle {
+    fn new(id: u64, kind: &str) -> Self {
+        JobHandle {
+            state: Arc::new(Mutex::new(JobSnapshot {
+                id,
+                kind: kind.to_string(),
+                status: JobStatus::Running,
+                started: Utc::now(),
+                finished: None,
+                progress: Progress::default(),
+                log: Vec::new(),
+                result: None,
+                error: None,
+            })),
+        }
+    }
+
+    /// A handle that isn't tracked by any `JobManager`, for running an
+    /// operation in the foreground (e.g. from the CLI's local mode).
+    pub fn detached(kind: &str) -> Self {
+        JobHandle::new(0, kind)
+    }
+
+    pub fn id(&self) -> u64 {
+        self.state.lock().unwrap().id
+    }
+
+    /// Appends a line to the job log and to the tracing output.
+    pub fn log(&self, line: impl Into<String>) {
+        let line = line.into();
+        tracing::info!("{}", line);
+        self.state.lock().unwrap().log.push(line);
+    }
+
+    pub fn set_totals(&self, files: u64, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.progress.files_total = Some(files);
+        state.progress.bytes_total = Some(bytes);
+    }
+
+    pub fn advance(&self, files: u64, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.progress.files_done += files;
+        state.progress.bytes_done += bytes;
+    }
+
+    pub fn snapshot(&self) -> JobSnapshot {
+        self.state.lock().unwrap().clone()
+    }
+
+    fn finish(&self, outcome: Result<Value, String>) {
+        let mut state = self.state.lock().unwrap();
+        state.finished = Some(Utc::now());
+        match outcome {
+            Ok(result) => {
+                state.status = JobStatus::Succeeded;
+                state.result = Some(result);
+            }
+            Err(e) => {
+                tracing::error!("{}", e);
+                state.status = JobStatus::Failed;
+                state.error = Some(e);
+            }
+        }
+    }
+}
+
+fn panic_message(e: tokio::task::JoinError) -> String {
+    let Ok(panic) = e.try_into_panic() else { return "Job was cancelled".to_string() };
+    let detail = panic.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown error".to_string());
+    format!("Job panicked: {}", detail)
+}
+
+/// Runs long operations in the background and keeps the most recent
+/// `JOB_HISTORY` of them around for `/jobs`.
+#[derive(Default)]
+pub struct JobManager {
+    next_id: AtomicU64,
+    jobs: Mutex<BTreeMap<u64, JobHandle>>,
+}
+
+impl JobManager {
+    /// Starts `op` on its own task, inside the caller's tracing span, and
+    /// returns the new job's ID straight away.
+    pub fn spawn<F, Fut>(&self, kind: &str, op: F) -> u64
+    where
+        F: FnOnce(JobHandle) -> Fut,
+        Fut: Future<Output = Result<Value, String>> + Send + 'static,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let handle = JobHandle::new(id, kind);
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            jobs.insert(id, handle.clone());
+            while jobs.len() > JOB_HISTORY {
+                jobs.pop_first();
+            }
+        }
+        let fut = op(handle.clone());
+        let span = tracing::info_span!("job", job_id = id);
+        tokio::spawn(async move {
+            // On its own task so a panic fails the job instead of leaving it
+            // running forever.
+            let outcome = match tokio::spawn(fut.in_current_span()).await {
+                Ok(outcome) => outcome,
+                Err(e) => Err(panic_message(e)),
+            };
+            handle.finish(outcome);
+        }.instrument(span));
+        id
+    }
+
+    /// Like `spawn`, but holds `guard` until the job finishes.
+    pub fn spawn_exclusive<F, Fut>(&self, guard: OperationGuard, kind: &str, op: F) -> u64
+    where
+        F: FnOnce(JobHandle) -> Fut,
+        Fut: Future<Output = Result<Value, String>> + Send + 'static,
+    {
+        self.spawn(kind, |job| {
+            guard.set_job(job.id());
+            let fut = op(job);
+            async move {
+                let _guard = guard;
+                fut.await
+            }
+        })
+    }
+
+    pub fn get(&self, id: u64) -> Option<JobHandle> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+
+    /// All retained jobs, newest first.
+    pub fn list(&self) -> Vec<JobSnapshot> {
+        self.jobs.lock().unwrap().values().rev().map(|j| j.snapshot()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use serde_json::json;
+    use crate::oplock::OperationLock;
+    use super::*;
+
+    async fn wait_until_done(jobs: &JobManager, id: u64) -> JobSnapshot {
+        for _ in 0..100 {
+            let job = jobs.get(id).unwrap().snapshot();
+            if job.status != JobStatus::Running {
+                return job;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("job {} never finished", id);
+    }
+
+    #[tokio::test]
+    async fn records_results_and_errors() {
+        let jobs = JobManager::default();
+        let ok = jobs.spawn("ok", |job| async move {
+            job.advance(2, 10);
+            Ok(json!({"done": true}))
+        });
+        let failed = jobs.spawn("failed", |_| async { Err("disk full".to_string()) });
+        let ok = wait_until_done(&jobs, ok).await;
+        assert_eq!(ok.status, JobStatus::Succeeded);
+        assert_eq!(ok.result, Some(json!({"done": true})));
+        assert_eq!(ok.progress.files_done, 2);
+        let failed = wait_until_done(&jobs, failed).await;
+        assert_eq!(failed.status, JobStatus::Failed);
+        assert_eq!(failed.error.as_deref(), Some("disk full"));
+        assert!(failed.finished.is_some());
+    }
+
+    #[tokio::test]
+    async fn a_panicking_job_fails_and_releases_the_lock() {
+        let jobs = JobManager::default();
+        let lock = Arc::new(OperationLock::default());
+        let guard = lock.try_acquire("backup").unwrap();
+        let id = jobs.spawn_exclusive(guard, "backup", |_| async {
+            if true {
+                panic!("copy went wrong");
+            }
+            Ok(Value::Null)
+        });
+        let job = wait_until_done(&jobs, id).await;
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.error.as_deref(), Some("Job panicked: copy went wrong"));
+        assert!(lock.current().is_none());
+    }
+}
@@ -0,0 +1,611 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use regex::Regex;
+use rocket::tokio::fs;
+use rocket::tokio::process::Command;
+use serde::{Deserialize, Serialize};
+use crate::constants::{JVM_HEADROOM_BYTES, USER_JVM_ARGS};
+
+/// Aikar's G1 flags for heaps up to 12 GB. Larger heaps get bigger young
+/// generation and region sizes, see `aikar_flags`.
+const AIKAR_FLAGS: [&str; 18] = [
+    "-XX:+UseG1GC",
+    "-XX:+ParallelRefProcEnabled",
+    "-XX:MaxGCPauseMillis=200",
+    "-XX:+UnlockExperimentalVMOptions",
+    "-XX:+DisableExplicitGC",
+    "-XX:+AlwaysPreTouch",
+    "-XX:G1NewSizePercent=30",
+    "-XX:G1MaxNewSizePercent=40",
+    "-XX:G1HeapRegionSize=8M",
+    "-XX:G1ReservePercent=20",
+    "-XX:G1HeapWastePercent=5",
+    "-XX:G1MixedGCCountTarget=4",
+    "-XX:InitiatingHeapOccupancyPercent=15",
+    "-XX:G1MixedGCLiveThresholdPercent=90",
+    "-XX:G1RSetUpdatingPauseTimePercent=5",
+    "-XX:SurvivorRatio=32",
+    "-XX:+PerfDisableSharedMem",
+    "-XX:MaxTenuringThreshold=1",
+];
+
+const AIKAR_LARGE_HEAP: [(&str, &str); 5] = [
+    ("-XX:G1NewSizePercent=30", "-XX:G1NewSizePercent=40"),
+    ("-XX:G1MaxNewSizePercent=40", "-XX:G1MaxNewSizePercent=50"),
+    ("-XX:G1HeapRegionSize=8M", "-XX:G1HeapRegionSize=16M"),
+    ("-XX:G1ReservePercent=20", "-XX:G1ReservePercent=15"),
+    ("-XX:InitiatingHeapOccupancyPercent=15", "-XX:InitiatingHeapOccupancyPercent=20"),
+];
+
+const LARGE_HEAP_BYTES: u64 = 12 * 1024 * 1024 * 1024;
+
+fn aikar_flags(xmx_bytes: Option<u64>) -> Vec<String> {
+    let large = xmx_bytes.is_some_and(|b| b > LARGE_HEAP_BYTES);
+    AIKAR_FLAGS.iter()
+        .map(|flag| match AIKAR_LARGE_HEAP.iter().find(|(small, _)| small == flag) {
+            Some((_, big)) if large => big.to_string(),
+            _ => flag.to_string(),
+        })
+        .collect()
+}
+
+fn is_aikar_flag(arg: &str) -> bool {
+    AIKAR_FLAGS.contains(&arg) || AIKAR_LARGE_HEAP.iter().any(|(_, big)| *big == arg)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GarbageCollector {
+    G1,
+    Zgc,
+    Shenandoah,
+    Parallel,
+}
+
+impl GarbageCollector {
+    const ALL: [GarbageCollector; 4] = [GarbageCollector::G1, GarbageCollector::Zgc, GarbageCollector::Shenandoah, GarbageCollector::Parallel];
+
+    fn flag(self) -> &'static str {
+        match self {
+            GarbageCollector::G1 => "-XX:+UseG1GC",
+            GarbageCollector::Zgc => "-XX:+UseZGC",
+            GarbageCollector::Shenandoah => "-XX:+UseShenandoahGC",
+            GarbageCollector::Parallel => "-XX:+UseParallelGC",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlagPreset {
+    /// Aikar's G1 tuning, sized for the heap.
+    Aikar,
+}
+
+/// The JVM options the manager understands, parsed out of
+/// `user_jvm_args.txt`. Anything else is kept as-is in `extra_args`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct JvmSettings {
+    pub xms: Option<String>,
+    pub xmx: Option<String>,
+    pub gc: Option<GarbageCollector>,
+    pub preset: Option<FlagPreset>,
+    pub extra_args: Vec<String>,
+    /// Value of the `JAVA`-style variable in `startserver.sh`, if it has one.
+    pub java_path: Option<String>,
+}
+
+/// Fields to change; missing fields are left alone. An empty string clears
+/// `xms`, `xmx` or `java_path`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JvmUpdate {
+    pub xms: Option<String>,
+    pub xmx: Option<String>,
+    pub gc: Option<GarbageCollector>,
+    /// `"none"` removes a preset.
+    pub preset: Option<String>,
+    pub extra_args: Option<Vec<String>>,
+    pub java_path: Option<String>,
+}
+
+/// Parses a JVM memory size like `512M` or `10G` into bytes.
+pub fn parse_size(size: &str) -> Option<u64> {
+    let (digits, unit) = match size.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => (&size[..i], c.to_ascii_lowercase()),
+        _ => (size, 'b'),
+    };
+    let value: u64 = digits.parse().ok()?;
+    let multiplier = match unit {
+        'b' => 1,
+        'k' => 1024,
+        'm' => 1024 * 1024,
+        'g' => 1024 * 1024 * 1024,
+        't' => 1024 * 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    value.checked_mul(multiplier)
+}
+
+fn jvm_args_path(server_location: &str) -> PathBuf {
+    Path::new(server_location).join(USER_JVM_ARGS)
+}
+
+fn start_script_path(server_location: &str) -> PathBuf {
+    Path::new(server_location).join("startserver.sh")
+}
+
+fn java_var_regex() -> Regex {
+    Regex::new(r#"^(\s*(?:export\s+)?)([A-Za-z0-9_]*JAVA)=(.*)$"#).unwrap()
+}
+
+/// The value of a shell assignment: the first word of `raw` with its quotes
+/// and backslash escapes removed. Expansions like `$HOME` are left as text.
+fn shell_unquote(raw: &str) -> String {
+    let mut value = String::new();
+    let mut chars = raw.trim_start().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => value.extend(chars.by_ref().take_while(|c| *c != '\'')),
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some(next @ ('$' | '`' | '"' | '\\')) => value.push(next),
+                            Some(next) => value.extend(['\\', next]),
+                            None => value.push('\\'),
+                        },
+                        _ => value.push(c),
+                    }
+                }
+            }
+            '\\' => value.extend(chars.next()),
+            c if c.is_whitespace() => break,
+            _ => value.push(c),
+        }
+    }
+    value
+}
+
+/// Single-quotes `value` so the shell takes it literally.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// The first `JAVA=`/`ATM10_JAVA=`-style assignment in `startserver.sh`, as
+/// (line index, prefix, variable name, unquoted value).
+fn find_java_var(script: &str) -> Option<(usize, String, String, String)> {
+    let re = java_var_regex();
+    script.lines().enumerate().find_map(|(i, line)| {
+        let caps = re.captures(line)?;
+        Some((i, caps[1].to_string(), caps[2].to_string(), shell_unquote(&caps[3])))
+    })
+}
+
+/// Splits `user_jvm_args.txt` into its comment lines and its arguments.
+fn split_args_file(contents: &str) -> (Vec<String>, Vec<String>) {
+    let mut comments = Vec::new();
+    let mut args = Vec::new();
+    for line in contents.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('#') {
+            comments.push(line.to_string());
+        } else {
+            args.extend(trimmed.split_whitespace().map(|s| s.to_string()));
+        }
+    }
+    (comments, args)
+}
+
+fn parse_settings(args: &[String]) -> JvmSettings {
+    let mut settings = JvmSettings::default();
+    let has_aikar = AIKAR_FLAGS.iter().all(|flag| {
+        args.iter().any(|a| a == flag || AIKAR_LARGE_HEAP.iter().any(|(small, big)| small == flag && a == big))
+    });
+    if has_aikar {
+        settings.preset = Some(FlagPreset::Aikar);
+    }
+    for arg in args {
+        if let Some(size) = arg.strip_prefix("-Xms") {
+            settings.xms = Some(size.to_string());
+        } else if let Some(size) = arg.strip_prefix("-Xmx") {
+            settings.xmx = Some(size.to_string());
+        } else if let Some(gc) = GarbageCollector::ALL.into_iter().find(|gc| gc.flag() == arg) {
+            settings.gc = Some(gc);
+        } else if !(has_aikar && is_aikar_flag(arg)) {
+            settings.extra_args.push(arg.clone());
+        }
+    }
+    settings
+}
+
+fn render_args(comments: &[String], settings: &JvmSettings) -> String {
+    let mut lines: Vec<String> = comments.to_vec();
+    if let Some(xms) = &settings.xms {
+        lines.push(format!("-Xms{}", xms));
+    }
+    if let Some(xmx) = &settings.xmx {
+        lines.push(format!("-Xmx{}", xmx));
+    }
+    let preset_flags = match settings.preset {
+        Some(FlagPreset::Aikar) => aikar_flags(settings.xmx.as_deref().and_then(parse_size)),
+        None => Vec::new(),
+    };
+    if let Some(gc) = settings.gc && !preset_flags.iter().any(|f| f == gc.flag()) {
+        lines.push(gc.flag().to_string());
+    }
+    lines.extend(preset_flags);
+    lines.extend(settings.extra_args.iter().cloned());
+    lines.join("\n") + "\n"
+}
+
+pub async fn read_jvm_settings(server_location: &str) -> Result<JvmSettings, String> {
+    let path = jvm_args_path(server_location);
+    let contents = match fs::read_to_string(&path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    let mut settings = parse_settings(&split_args_file(&contents).1);
+    if let Ok(script) = fs::read_to_string(start_script_path(server_location)).await {
+        settings.java_path = find_java_var(&script).map(|(_, _, _, value)| value);
+    }
+    Ok(settings)
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct SystemMemory {
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+}
+
+/// `MemTotal` and `MemAvailable` from `/proc/meminfo`.
+pub fn system_memory() -> Option<SystemMemory> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let field = |name: &str| -> Option<u64> {
+        let line = meminfo.lines().find(|l| l.starts_with(name))?;
+        line.split_whitespace().nth(1)?.parse::<u64>().ok().map(|kb| kb * 1024)
+    };
+    Some(SystemMemory { total_bytes: field("MemTotal:")?, available_bytes: field("MemAvailable:")? })
+}
+
+/// Checks `settings` against each other and against system memory. Returns
+/// warnings, or the errors that make the settings unusable.
+pub fn validate(settings: &JvmSettings, memory: Option<SystemMemory>) -> Result<Vec<String>, Vec<String>> {
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    let mut size = |name: &str, value: &Option<String>| -> Option<u64> {
+        let value = value.as_ref()?;
+        let bytes = parse_size(value);
+        if bytes.is_none() {
+            errors.push(format!("{} '{}' is not a size like 4096M or 8G.", name, value));
+        }
+        bytes
+    };
+    let xms = size("Xms", &settings.xms);
+    let xmx = size("Xmx", &settings.xmx);
+    if let (Some(xms), Some(xmx)) = (xms, xmx) && xms > xmx {
+        errors.push("Xms is larger than Xmx.".to_string());
+    }
+    if let (Some(xmx), Some(memory)) = (xmx, memory) {
+        if xmx >= memory.total_bytes {
+            errors.push(format!("Xmx is larger than system memory ({} MiB).", memory.total_bytes / 1024 / 1024));
+        } else if xmx + JVM_HEADROOM_BYTES > memory.total_bytes {
+            warnings.push(format!("Xmx leaves less than {} MiB of system memory for the OS and JVM overhead.", JVM_HEADROOM_BYTES / 1024 / 1024));
+        }
+        if xmx > memory.available_bytes {
+            warnings.push(format!("Xmx is more than the memory available right now ({} MiB).", memory.available_bytes / 1024 / 1024));
+        }
+    }
+    if settings.preset == Some(FlagPreset::Aikar) && settings.gc.is_some_and(|gc| gc != GarbageCollector::G1) {
+        errors.push("The aikar preset is tuned for G1 and can't be combined with another collector.".to_string());
+    }
+    for arg in &settings.extra_args {
+        if arg.starts_with("-Xms") || arg.starts_with("-Xmx") || GarbageCollector::ALL.iter().any(|gc| gc.flag() == arg) {
+            errors.push(format!("Set '{}' through xms, xmx or gc instead of extra_args.", arg));
+        } else if !arg.starts_with('-') {
+            errors.push(format!("'{}' is not a JVM option.", arg));
+        }
+    }
+    if errors.is_empty() { Ok(warnings) } else { Err(errors) }
+}
+
+fn apply_update(settings: &mut JvmSettings, update: &JvmUpdate) -> Result<(), Vec<String>> {
+    let non_empty = |v: &String| Some(v.trim().to_string()).filter(|v| !v.is_empty());
+    if let Some(xms) = &update.xms {
+        settings.xms = non_empty(xms);
+    }
+    if let Some(xmx) = &update.xmx {
+        settings.xmx = non_empty(xmx);
+    }
+    if let Some(gc) = update.gc {
+        settings.gc = Some(gc);
+    }
+    match update.preset.as_deref() {
+        None => {}
+        Some("none") => settings.preset = None,
+        Some("aikar") => {
+            settings.preset = Some(FlagPreset::Aikar);
+            settings.gc = Some(GarbageCollector::G1);
+        }
+        Some(other) => return Err(vec![format!("Unknown preset '{}'; use aikar or none.", other)]),
+    }
+    if let Some(extra) = &update.extra_args {
+        settings.extra_args = extra.iter().filter(|a| !a.trim().is_empty()).map(|a| a.trim().to_string()).collect();
+    }
+    if let Some(java) = &update.java_path {
+        settings.java_path = non_empty(java);
+    }
+    Ok(())
+}
+
+/// Rewrites `startserver.sh`'s Java variable to `java_path`, single-quoted
+/// so `$`, backticks and backslashes in the path aren't expanded.
+async fn write_java_path(server_location: &str, java_path: &str) -> Result<(), String> {
+    let path = start_script_path(server_location);
+    let script = fs::read_to_string(&path).await.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let (index, prefix, name, _) = find_java_var(&script)
+        .ok_or_else(|| "startserver.sh has no JAVA= variable to set; put the Java path in the start command instead.".to_string())?;
+    let mut lines: Vec<String> = script.lines().map(|l| l.to_string()).collect();
+    lines[index] = format!("{}{}={}", prefix, name, shell_quote(java_path));
+    let trailing = if script.ends_with('\n') { "\n" } else { "" };
+    fs::write(&path, lines.join("\n") + trailing).await.map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JvmChange {
+    pub settings: JvmSettings,
+    pub warnings: Vec<String>,
+}
+
+/// Applies `update` to `user_jvm_args.txt` (and the Java path to
+/// `startserver.sh`), keeping its comments and any unknown options. Takes
+/// effect on the next server start.
+pub async fn update_jvm_settings(server_location: &str, update: &JvmUpdate) -> Result<JvmChange, Vec<String>> {
+    let mut settings = read_jvm_settings(server_location).await.map_err(|e| vec![e])?;
+    let old_java = settings.java_path.clone();
+    apply_update(&mut settings, update)?;
+    let mut warnings = validate(&settings, system_memory())?;
+
+    if settings.java_path != old_java {
+        let java = settings.java_path.clone().unwrap_or_else(|| "java".to_string());
+        match java_version(Path::new(&java)).await {
+            Some(version) => {
+                let need = required_java(detect_minecraft_version(server_location).await.as_deref());
+                if let Some(need) = need && !need.accepts(version.major) {
+                    warnings.push(format!("Java {} doesn't meet the pack's requirement ({}).", version.major, need));
+                }
+            }
+            None => return Err(vec![format!("'{}' is not a working Java executable.", java)]),
+        }
+        write_java_path(server_location, &java).await.map_err(|e| vec![e])?;
+    }
+
+    let path = jvm_args_path(server_location);
+    let existing = fs::read_to_string(&path).await.unwrap_or_default();
+    let contents = render_args(&split_args_file(&existing).0, &settings);
+    fs::write(&path, contents).await.map_err(|e| vec![format!("Failed to write {}: {}", path.display(), e)])?;
+    Ok(JvmChange { settings, warnings })
+}
+
+/// The Minecraft version the server is built for, from the vanilla server
+/// jar under `libraries/` (Forge/NeoForge) or a `minecraft_server.<ver>.jar`.
+pub async fn detect_minecraft_version(server_location: &str) -> Option<String> {
+    let libraries = Path::new(server_location).join("libraries/net/minecraft/server");
+    if let Ok(mut entries) = fs::read_dir(&libraries).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some(version) = name.split('-').next() && version.starts_with("1.") {
+                return Some(version.to_string());
+            }
+        }
+    }
+    let re = Regex::new(r"^minecraft_server\.(1\.[0-9.]+)\.jar$").unwrap();
+    let mut entries = fs::read_dir(server_location).await.ok()?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if let Some(caps) = re.captures(&entry.file_name().to_string_lossy()) {
+            return Some(caps[1].to_string());
+        }
+    }
+    None
+}
+
+/// Java versions a Minecraft version runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct JavaRequirement {
+    pub min: u32,
+    /// Older modloaders break on newer Java.
+    pub max: Option<u32>,
+}
+
+impl JavaRequirement {
+    pub fn accepts(&self, major: u32) -> bool {
+        major >= self.min && self.max.is_none_or(|max| major <= max)
+    }
+}
+
+impl std::fmt::Display for JavaRequirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.max {
+            Some(max) => write!(f, "Java {} to {}", self.min, max),
+            None => write!(f, "Java {}+", self.min),
+        }
+    }
+}
+
+pub fn required_java(minecraft_version: Option<&str>) -> Option<JavaRequirement> {
+    let mut parts = minecraft_version?.split('.').skip(1).map(|p| p.parse::<u32>().unwrap_or(0));
+    let (minor, patch) = (parts.next()?, parts.next().unwrap_or(0));
+    Some(match (minor, patch) {
+        (21.., _) | (20, 5..) => JavaRequirement { min: 21, max: None },
+        (18.., _) => JavaRequirement { min: 17, max: None },
+        (17, _) => JavaRequirement { min: 16, max: None },
+        _ => JavaRequirement { min: 8, max: Some(11) },
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JavaVersion {
+    pub version: String,
+    pub major: u32,
+}
+
+/// Runs `<java> -version` and parses the quoted version string.
+pub async fn java_version(java: &Path) -> Option<JavaVersion> {
+    let output = Command::new(java).arg("-version").output().await.ok()?;
+    let text = String::from_utf8_lossy(&output.stderr).to_string() + &String::from_utf8_lossy(&output.stdout);
+    let version = Regex::new(r#"version "([^"]+)""#).unwrap().captures(&text)?[1].to_string();
+    let mut parts = version.split(['.', '_', '-', '+']);
+    let first: u32 = parts.next()?.parse().ok()?;
+    let major = if first == 1 { parts.next()?.parse().ok()? } else { first };
+    Some(JavaVersion { version, major })
+}
+
+async fn java_candidates() -> Vec<PathBuf> {
+    let mut candidates = Vec::new();
+    if let Ok(home) = std::env::var("JAVA_HOME") {
+        candidates.push(Path::new(&home).join("bin/java"));
+    }
+    if let Ok(path) = std::env::var("PATH") {
+        candidates.extend(std::env::split_paths(&path).map(|dir| dir.join("java")));
+    }
+    let home = std::env::var("HOME").unwrap_or_default();
+    let roots = [
+        PathBuf::from("/usr/lib/jvm"),
+        PathBuf::from("/usr/lib64/jvm"),
+        PathBuf::from("/usr/java"),
+        PathBuf::from("/opt"),
+        Path::new(&home).join(".sdkman/candidates/java"),
+        Path::new(&home).join(".jdks"),
+    ];
+    for root in roots {
+        let Ok(mut entries) = fs::read_dir(&root).await else { continue };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            candidates.push(entry.path().join("bin/java"));
+        }
+    }
+    candidates
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InstalledJava {
+    pub path: String,
+    pub version: String,
+    pub major: u32,
+    /// Whether it meets the pack's requirement; unknown without a detected
+    /// Minecraft version.
+    pub compatible: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JavaReport {
+    pub minecraft_version: Option<String>,
+    pub required: Option<JavaRequirement>,
+    pub installed: Vec<InstalledJava>,
+}
+
+/// Finds JDKs in `JAVA_HOME`, `PATH` and the usual install directories, and
+/// checks each against the Java version the server's Minecraft needs.
+pub async fn detect_javas(server_location: &str) -> JavaReport {
+    let minecraft_version = detect_minecraft_version(server_location).await;
+    let required = required_java(minecraft_version.as_deref());
+    let mut seen = BTreeSet::new();
+    let mut installed = Vec::new();
+    for candidate in java_candidates().await {
+        let Ok(real) = fs::canonicalize(&candidate).await else { continue };
+        if !seen.insert(real.clone()) {
+            continue;
+        }
+        if let Some(v) = java_version(&real).await {
+            installed.push(InstalledJava {
+                path: real.to_string_lossy().to_string(),
+                compatible: required.map(|r| r.accepts(v.major)),
+                version: v.version,
+                major: v.major,
+            });
+        }
+    }
+    installed.sort_by_key(|java| std::cmp::Reverse(java.major));
+    JavaReport { minecraft_version, required, installed }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::temp_dir;
+    use super::*;
+
+    #[test]
+    fn finds_the_java_variable() {
+        let script = "#!/bin/bash\n# JAVA=commented\nexport ATM10_JAVA=\"/usr/lib/jvm/java 21/bin/java\" # pinned\nJAVA=java\n";
+        let (index, prefix, name, value) = find_java_var(script).unwrap();
+        assert_eq!((index, prefix.as_str(), name.as_str(), value.as_str()), (2, "export ", "ATM10_JAVA", "/usr/lib/jvm/java 21/bin/java"));
+        assert_eq!(find_java_var("JAVA=$HOME/jdk/bin/java").unwrap().3, "$HOME/jdk/bin/java");
+        assert_eq!(find_java_var(r#"JAVA="/opt/\$x/\"q\"/java""#).unwrap().3, r#"/opt/$x/"q"/java"#);
+        assert_eq!(find_java_var(r"JAVA='/opt/it'\''s/java'").unwrap().3, "/opt/it's/java");
+        assert!(find_java_var("java -jar server.jar\n").is_none());
+    }
+
+    #[tokio::test]
+    async fn java_path_is_written_literally() {
+        let dir = temp_dir("jvm-java");
+        let location = dir.to_string_lossy().to_string();
+        let script = dir.join("startserver.sh");
+        std::fs::write(&script, "#!/bin/sh\n  export JAVA=\"java\"\nexec \"$JAVA\" @user_jvm_args.txt\n").unwrap();
+        let java = r#"/opt/$HOME/`id`/back\slash/it's "q"/java"#;
+        write_java_path(&location, java).await.unwrap();
+
+        let written = std::fs::read_to_string(&script).unwrap();
+        assert!(written.starts_with("#!/bin/sh\n  export JAVA='"));
+        assert!(written.ends_with("\nexec \"$JAVA\" @user_jvm_args.txt\n"));
+        assert_eq!(read_jvm_settings(&location).await.unwrap().java_path.as_deref(), Some(java));
+        // The shell sees the same path.
+        let line = written.lines().nth(1).unwrap();
+        let output = std::process::Command::new("sh").arg("-c").arg(format!("{}\nprintf %s \"$JAVA\"", line)).output().unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), java);
+
+        std::fs::write(&script, "#!/bin/sh\njava -jar server.jar\n").unwrap();
+        assert!(write_java_path(&location, java).await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn jvm_args_round_trip() {
+        let mut contents = "# Xmx and Xms set the memory\n-Xms4G -Xmx16G\n".to_string();
+        contents += &aikar_flags(parse_size("16G")).join("\n");
+        contents += "\n-Dlog4j2.formatMsgNoLookups=true\n";
+        let (comments, args) = split_args_file(&contents);
+        assert_eq!(comments, ["# Xmx and Xms set the memory"]);
+        let settings = parse_settings(&args);
+        assert_eq!((settings.xms.as_deref(), settings.xmx.as_deref()), (Some("4G"), Some("16G")));
+        assert_eq!((settings.gc, settings.preset), (Some(GarbageCollector::G1), Some(FlagPreset::Aikar)));
+        assert_eq!(settings.extra_args, ["-Dlog4j2.formatMsgNoLookups=true"]);
+        assert!(aikar_flags(parse_size("16G")).contains(&"-XX:G1HeapRegionSize=16M".to_string()));
+
+        let rendered = render_args(&comments, &settings);
+        assert!(rendered.starts_with("# Xmx and Xms set the memory\n-Xms4G\n-Xmx16G\n-XX:+UseG1GC\n"));
+        assert!(rendered.ends_with("\n-Dlog4j2.formatMsgNoLookups=true\n"));
+        assert_eq!(rendered.matches("-XX:+UseG1GC").count(), 1);
+        let (comments, args) = split_args_file(&rendered);
+        assert_eq!(render_args(&comments, &parse_settings(&args)), rendered);
+    }
+
+    #[test]
+    fn updates_and_validates_settings() {
+        let mut settings = parse_settings(&["-Xmx8G".to_string(), "-XX:+UseZGC".to_string()]);
+        let update = JvmUpdate { xms: Some("2G".to_string()), preset: Some("aikar".to_string()), ..Default::default() };
+        apply_update(&mut settings, &update).unwrap();
+        assert_eq!((settings.gc, settings.preset), (Some(GarbageCollector::G1), Some(FlagPreset::Aikar)));
+        let memory = SystemMemory { total_bytes: parse_size("16G").unwrap(), available_bytes: parse_size("4G").unwrap() };
+        assert_eq!(validate(&settings, Some(memory)).unwrap(), ["Xmx is more than the memory available right now (4096 MiB)."]);
+
+        settings.xms = Some("12G".to_string());
+        settings.extra_args = vec!["-Xmx1G".to_string(), "nogui".to_string()];
+        let errors = validate(&settings, Some(memory)).unwrap_err();
+        assert_eq!(errors, [
+            "Xms is larger than Xmx.",
+            "Set '-Xmx1G' through xms, xmx or gc instead of extra_args.",
+            "'nogui' is not a JVM option.",
+        ]);
+    }
+}
@@ -0,0 +1,37 @@
+//! Server management logic shared by the web server and `mc-manager-cli`.
+//! Nothing here needs a running Rocket instance, so the CLI can call it
+//! directly when the web server is down.
+
+pub mod constants;
+pub mod serverctl;
+pub mod childproc;
+pub mod unitfile;
+pub mod provision;
+pub mod worlds;
+pub mod nbt;
+pub mod jvm;
+pub mod curseforge;
+pub mod models;
+pub mod config;
+pub mod properties;
+pub mod motd;
+pub mod rcon;
+pub mod players;
+pub mod sessions;
+pub mod slp;
+pub mod history;
+pub mod metrics;
+pub mod tps;
+pub mod prometheus;
+pub mod mods;
+pub mod crashes;
+pub mod crashguard;
+pub mod events;
+pub mod audit;
+pub mod logging;
+pub mod jobs;
+pub mod operations;
+pub mod oplock;
+pub mod auth;
+#[cfg(test)]
+mod testutil;
@@ -0,0 +1,107 @@
+use std::fs::OpenOptions;
+use std::io::IsTerminal;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use chrono::Utc;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Data, Request, Response};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{EnvFilter, fmt};
+use crate::constants::DEFAULT_LOG_LEVEL;
+
+/// Sets up the global subscriber. `LOG_LEVEL` takes `RUST_LOG`-style
+/// directives, `LOG_FORMAT=json` switches stderr to JSON lines and `LOG_FILE`
+/// additionally appends JSON lines to a file. Rocket's own `log` output is
+/// routed through the same subscriber.
+pub fn init_logging() {
+    let filter = EnvFilter::try_from_env("LOG_LEVEL").unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_LEVEL));
+    let json = std::env::var("LOG_FORMAT").is_ok_and(|f| f.eq_ignore_ascii_case("json"));
+    let (stderr_text, stderr_json) = if json {
+        (None, Some(fmt::layer().json().with_writer(std::io::stderr)))
+    } else {
+        (Some(fmt::layer().with_ansi(std::io::stderr().is_terminal()).with_writer(std::io::stderr)), None)
+    };
+    let mut file_error = None;
+    let file = std::env::var("LOG_FILE").ok().and_then(|path| {
+        match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => Some(fmt::layer().json().with_ansi(false).with_writer(Mutex::new(file))),
+            Err(e) => {
+                file_error = Some(format!("Failed to open log file {}: {}", path, e));
+                None
+            }
+        }
+    });
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(stderr_text)
+        .with(stderr_json)
+        .with(file)
+        .init();
+    if let Some(e) = file_error {
+        tracing::error!("{}", e);
+    }
+}
+
+/// Identifies one request in logs, audit entries and the `X-Request-Id`
+/// response header. A well-formed incoming `X-Request-Id` is reused.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+static NEXT_REQUEST: AtomicU64 = AtomicU64::new(0);
+
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+pub fn request_id<'r>(req: &'r Request<'_>) -> &'r RequestId {
+    req.local_cache(|| {
+        let incoming = req.headers().get_one("X-Request-Id").filter(|id| valid_request_id(id));
+        RequestId(incoming.map(|id| id.to_string()).unwrap_or_else(|| {
+            let n = NEXT_REQUEST.fetch_add(1, Ordering::Relaxed);
+            format!("{:08x}-{:06x}", Utc::now().timestamp() as u32, n)
+        }))
+    })
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestId {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(request_id(req).clone())
+    }
+}
+
+/// Assigns request IDs and logs one line per response.
+pub struct RequestLogger;
+
+#[rocket::async_trait]
+impl Fairing for RequestLogger {
+    fn info(&self) -> Info {
+        Info { name: "Request logger", kind: Kind::Request | Kind::Response }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        request_id(req);
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let id = request_id(req);
+        res.set_header(Header::new("X-Request-Id", id.0.clone()));
+        tracing::info!(
+            request_id = %id,
+            method = %req.method(),
+            uri = %req.uri(),
+            status = res.status().code,
+            "request"
+        );
+    }
+}
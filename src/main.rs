@@ -1,14 +1,15 @@
 #[macro_use] extern crate rocket;
 #[macro_use] extern crate rocket_include_static_resources;
 
+use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr};
-use std::process::Command;
 use rocket::tokio::fs::{self, File};
 use rocket::tokio::io::AsyncReadExt;
 use rocket::Config;
 use zip::write::{FileOptions, ZipWriter, ExtendedFileOptions};
 use rocket::response::content::RawJson;
-use rocket::tokio::fs::remove_file;
+use rocket::response::status::{Accepted, Conflict, Custom};
+use rocket::response::stream::{Event as SseEvent, EventStream, One, ReaderStream};
 use rocket::http::Status;
 use std::io::Write; // Needed for ZipWriter::write_all
 use rocket::serde::json::{Json, json};
@@ -16,45 +17,88 @@ use rocket::data::ByteUnit;
 use semver::Version;
 use rocket::Request;
 use rocket::form::Form;
-use rocket::fs::TempFile;
+use rocket::fs::{NamedFile, TempFile};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use rocket::State;
+use rocket::fairing::AdHoc;
+use tracing::{error, info, warn};
 
-mod constants;
-mod serverctl;
-mod curseforge;
-
-use crate::constants::*;
-use crate::serverctl::{ServerAction, systemctl_server};
-use crate::curseforge::fetch_latest_server_pack;
+use mc_manager::constants::*;
+use mc_manager::serverctl::{LogPage, server_backend};
+use mc_manager::curseforge::{UpdateCheckCache, fetch_latest_server_pack};
+use mc_manager::config::read_bcc_common;
+use mc_manager::properties::{KNOWN_PROPERTIES, apply_changes, read_server_properties, write_server_properties};
+use mc_manager::players::{PlayerChange, PlayerLists, apply_player_change, read_player_lists};
+use mc_manager::sessions::{Session, SessionTracker, playtime_totals, spawn_session_tracker};
+use mc_manager::slp::ping_local_server;
+use mc_manager::metrics::{MetricsHistory, metrics_history_from_env, spawn_sampler};
+use mc_manager::tps::{LagThresholds, TpsHistory, spawn_tps_sampler, tps_history_from_env};
+use mc_manager::prometheus::{Exposition, RequestCounter};
+use mc_manager::mods::{count_jars, list_extra_mods, remove_extra_mod, sanitize_mod_filename};
+use mc_manager::crashes::{CrashLog, read_crash_reports, spawn_crash_watcher};
+use mc_manager::crashguard::{CrashLoopConfig, quarantine_dir, read_guard_actions, spawn_crash_guard};
+use mc_manager::events::{Event, EventBus, spawn_event_watcher};
+use mc_manager::audit::{AuditLog, AuditParams, audit_log_path, trusted_proxies_from_env};
+use mc_manager::logging::{RequestId, RequestLogger, init_logging};
+use mc_manager::jobs::{JobManager, JobSnapshot};
+use mc_manager::operations::{plan_update_extras, run_backup, run_restore, run_update_extras};
+use mc_manager::oplock::{Operation, OperationLock};
+use mc_manager::auth::TokenAuth;
+use mc_manager::unitfile::{UnitOptions, UnitPlan, install_unit, plan_unit, render_unit};
+use mc_manager::jvm::{JavaReport, JvmUpdate, detect_javas, read_jvm_settings, system_memory, update_jvm_settings, validate as validate_jvm};
+use mc_manager::worlds::{LevelInfo, WorldInfo, WorldReset, active_world, check_world_name, install_world, list_worlds, read_level_info, run_reset_world, set_game_rules, switch_world, world_zip_stream};
+use mc_manager::provision::{ProvisionContext, ProvisionRequest, RegisteredServer, check_request, read_registered_servers, run_provision, servers_file_path};
+use mc_manager::motd::{MotdContext, apply_motd, load_template, render as render_motd, save_template, strip_codes};
 
 static_response_handler! {
     "/" => index_html => "index-html",
     "/static/style.css" => style_css => "style-css",
 }
 
+fn busy_message(op: &Operation) -> String {
+    let job = op.job_id.map(|id| format!(" (job {})", id)).unwrap_or_default();
+    format!("Another operation is in progress: {}{}.", op.kind, job)
+}
+
+/// 409 response for a request that conflicts with a running operation.
+fn busy(op: Operation) -> Conflict<Json<serde_json::Value>> {
+    Conflict(Json(json!({"error": busy_message(&op), "operation": op})))
+}
+
 #[post("/start")]
-fn start() -> &'static str {
-    if systemctl_server(ServerAction::Start) {
-        "Server start requested."
-    } else {
-        "Failed to start server."
+async fn start(lock: &State<Arc<OperationLock>>) -> Result<&'static str, Conflict<Json<serde_json::Value>>> {
+    let _guard = lock.try_acquire("start").map_err(busy)?;
+    match server_backend().start().await {
+        Ok(()) => Ok("Server start requested."),
+        Err(e) => {
+            error!("{}", e);
+            Ok("Failed to start server.")
+        }
     }
 }
 
 #[post("/stop")]
-fn stop() -> &'static str {
-    if systemctl_server(ServerAction::Stop) {
-        "Server stop requested."
-    } else {
-        "Failed to stop server."
+async fn stop(lock: &State<Arc<OperationLock>>) -> Result<&'static str, Conflict<Json<serde_json::Value>>> {
+    let _guard = lock.try_acquire("stop").map_err(busy)?;
+    match server_backend().stop().await {
+        Ok(()) => Ok("Server stop requested."),
+        Err(e) => {
+            error!("{}", e);
+            Ok("Failed to stop server.")
+        }
     }
 }
 
 #[post("/restart")]
-fn restart() -> &'static str {
-    if systemctl_server(ServerAction::Restart) {
-        "Server restart requested."
-    } else {
-        "Failed to restart server."
+async fn restart(lock: &State<Arc<OperationLock>>) -> Result<&'static str, Conflict<Json<serde_json::Value>>> {
+    let _guard = lock.try_acquire("restart").map_err(busy)?;
+    match server_backend().restart().await {
+        Ok(()) => Ok("Server restart requested."),
+        Err(e) => {
+            error!("{}", e);
+            Ok("Failed to restart server.")
+        }
     }
 }
 
@@ -69,41 +113,39 @@ async fn download_mods() -> Result<(rocket::http::ContentType, Vec<u8>), (Status
         let mut entries = match fs::read_dir(&mods_dir).await {
             Ok(entries) => entries,
             Err(e) => {
-                eprintln!("Failed to read mods directory '{}': {:?}", mods_dir, e);
+                error!("Failed to read mods directory '{}': {:?}", mods_dir, e);
                 return Err((Status::InternalServerError, "Failed to read mods directory.".to_string()));
             }
         };
 
         while let Ok(Some(entry)) = entries.next_entry().await {
             let path = entry.path();
-            if path.is_file() {
-                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                    let mut f = match File::open(&path).await {
-                        Ok(f) => f,
-                        Err(e) => {
-                            eprintln!("Failed to open file '{}': {:?}", path.display(), e);
-                            continue;
-                        }
-                    };
-                    let mut file_buf = Vec::new();
-                    if let Err(e) = f.read_to_end(&mut file_buf).await {
-                        eprintln!("Failed to read file '{}': {:?}", path.display(), e);
-                        continue;
-                    }
-                    if let Err(e) = writer.start_file(name, options.clone()) {
-                        eprintln!("Failed to start zip entry for '{}': {:?}", name, e);
-                        continue;
-                    }
-                    if let Err(e) = writer.write_all(&file_buf) {
-                        eprintln!("Failed to write to zip entry for '{}': {:?}", name, e);
+            if path.is_file() && let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                let mut f = match File::open(&path).await {
+                    Ok(f) => f,
+                    Err(e) => {
+                        error!("Failed to open file '{}': {:?}", path.display(), e);
                         continue;
                     }
+                };
+                let mut file_buf = Vec::new();
+                if let Err(e) = f.read_to_end(&mut file_buf).await {
+                    error!("Failed to read file '{}': {:?}", path.display(), e);
+                    continue;
+                }
+                if let Err(e) = writer.start_file(name, options.clone()) {
+                    error!("Failed to start zip entry for '{}': {:?}", name, e);
+                    continue;
+                }
+                if let Err(e) = writer.write_all(&file_buf) {
+                    error!("Failed to write to zip entry for '{}': {:?}", name, e);
+                    continue;
                 }
             }
         }
 
         if let Err(e) = writer.finish() {
-            eprintln!("Failed to finalize zip archive: {:?}", e);
+            error!("Failed to finalize zip archive: {:?}", e);
             return Err((Status::InternalServerError, "Failed to create zip archive.".to_string()));
         }
     }
@@ -113,45 +155,28 @@ async fn download_mods() -> Result<(rocket::http::ContentType, Vec<u8>), (Status
 #[get("/extra_mods_list")]
 async fn extra_mods_list() -> Result<RawJson<String>, (Status, String)> {
     let mods_dir = std::env::var("EXTRA_MODS_DIR").unwrap_or_else(|_| DEFAULT_EXTRA_MODS_DIR.to_string());
-    let mut mods = Vec::new();
-
-    let mut entries = match fs::read_dir(&mods_dir).await {
-        Ok(entries) => entries,
-        Err(e) => {
-            eprintln!("Failed to read mods directory '{}': {:?}", mods_dir, e);
-            return Err((Status::InternalServerError, "Failed to read mods directory.".to_string()));
-        }
-    };
-
-    while let Ok(Some(entry)) = entries.next_entry().await {
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                mods.push(name.to_string());
-            }
-        }
-    }
+    let mods = list_extra_mods(&mods_dir).await.map_err(|e| {
+        error!("{}", e);
+        (Status::InternalServerError, "Failed to read mods directory.".to_string())
+    })?;
 
     match serde_json::to_string(&mods) {
         Ok(json) => Ok(RawJson(json)),
         Err(e) => {
-            eprintln!("Failed to serialize mod list: {:?}", e);
+            error!("Failed to serialize mod list: {:?}", e);
             Err((Status::InternalServerError, "Failed to serialize mod list.".to_string()))
         }
     }
 }
 
 #[delete("/extra_mods/<modname>")]
-async fn delete_mod(modname: &str) -> Result<Status, (Status, String)> {
+async fn delete_mod(modname: &str, lock: &State<Arc<OperationLock>>) -> Result<Status, (Status, String)> {
+    let _guard = lock.try_acquire("delete_mod").map_err(|op| (Status::Conflict, busy_message(&op)))?;
     let mods_dir = std::env::var("EXTRA_MODS_DIR").unwrap_or_else(|_| DEFAULT_EXTRA_MODS_DIR.to_string());
-    let path = std::path::Path::new(&mods_dir).join(modname);
-    match remove_file(&path).await {
-        Ok(_) => Ok(Status::Ok),
-        Err(e) => {
-            eprintln!("Failed to delete mod '{}': {:?}", modname, e);
-            Err((Status::InternalServerError, format!("Failed to delete mod: {}", e)))
-        }
-    }
+    remove_extra_mod(&mods_dir, modname).await.map(|_| Status::Ok).map_err(|e| {
+        error!("Failed to delete mod '{}': {}", modname, e);
+        (Status::InternalServerError, e)
+    })
 }
 
 #[derive(FromForm)]
@@ -160,34 +185,27 @@ pub struct ModUpload<'r> {
 }
 
 #[post("/extra_mods_upload", data = "<form>")]
-async fn extra_mods_upload(mut form: Form<ModUpload<'_>>) -> Result<Status, (Status, String)> {
+async fn extra_mods_upload(mut form: Form<ModUpload<'_>>, audit: AuditParams<'_>, lock: &State<Arc<OperationLock>>) -> Result<Status, (Status, String)> {
+    let _guard = lock.try_acquire("upload_mod").map_err(|op| (Status::Conflict, busy_message(&op)))?;
     let mods_dir = std::env::var("EXTRA_MODS_DIR").unwrap_or_else(|_| DEFAULT_EXTRA_MODS_DIR.to_string());
     let mod_file = &mut form.mod_file;
+    audit.set("size", mod_file.len());
 
-    let filename = match mod_file.name() {
-        Some(name) => name.to_string(),
+    // `name()` drops the extension, which `sanitize_mod_filename` checks;
+    // the raw name is sanitized right after.
+    let filename = match mod_file.raw_name() {
+        Some(name) => name.dangerous_unsafe_unsanitized_raw().to_string(),
         None => {
             return Err((Status::BadRequest, "File is missing a filename.".to_string()));
         }
     };
+    audit.set("file", &filename);
 
     // Sanitize the filename to prevent path traversal attacks.
-    let sanitized_filename = std::path::Path::new(&filename)
-        .file_name()
-        .and_then(|s| s.to_str())
-        .map(|s| s.to_string())
-        .unwrap_or_default();
-
-    if sanitized_filename.is_empty() {
-        return Err((Status::BadRequest, "Filename is empty or invalid.".to_string()));
-    }
-
-    if !sanitized_filename.ends_with(".jar") {
-        return Err((Status::BadRequest, "Invalid file type. Only .jar files are allowed.".to_string()));
-    }
+    let sanitized_filename = sanitize_mod_filename(&filename).map_err(|e| (Status::BadRequest, e))?;
 
     if let Err(e) = fs::create_dir_all(&mods_dir).await {
-        eprintln!("Failed to create mods directory '{}': {:?}", mods_dir, e);
+        error!("Failed to create mods directory '{}': {:?}", mods_dir, e);
         return Err((Status::InternalServerError, "Failed to create mods directory.".to_string()));
     }
 
@@ -195,276 +213,723 @@ async fn extra_mods_upload(mut form: Form<ModUpload<'_>>) -> Result<Status, (Sta
 
     match mod_file.copy_to(&dest_path).await {
         Ok(_) => {
-            println!("Successfully saved mod to: {}", dest_path.display());
+            info!("Successfully saved mod to: {}", dest_path.display());
             Ok(Status::Ok)
         }
         Err(e) => {
-            eprintln!("Failed to write uploaded file '{}' to '{}': {:?}", sanitized_filename, dest_path.display(), e);
+            error!("Failed to write uploaded file '{}' to '{}': {:?}", sanitized_filename, dest_path.display(), e);
             Err((Status::InternalServerError, "Failed to save uploaded file.".to_string()))
         }
     }
 }
 
 #[post("/backup_server")]
-async fn backup_server() -> Result<Json<serde_json::Value>, (Status, String)> {
+#[tracing::instrument(name = "backup", skip_all, fields(request_id = %request_id))]
+fn backup_server(
+    jobs: &State<Arc<JobManager>>,
+    lock: &State<Arc<OperationLock>>,
+    bus: &State<EventBus>,
+    request_id: RequestId,
+) -> Result<Accepted<Json<serde_json::Value>>, Conflict<Json<serde_json::Value>>> {
+    let guard = lock.try_acquire("backup").map_err(busy)?;
     let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
-    let backup_dir = format!("{}_backup", server_location);
-    let files_to_backup = FILES_TO_BACKUP;
+    let bus = bus.inner().clone();
+    let id = jobs.spawn_exclusive(guard, "backup", |job| run_backup(job, bus, server_location));
+    Ok(Accepted(Json(json!({"job_id": id}))))
+}
 
-    if let Err(e) = fs::remove_dir_all(&backup_dir).await {
-        if e.kind() != std::io::ErrorKind::NotFound {
-            eprintln!("Failed to remove existing backup directory: {:?}", e);
-            return Err((Status::InternalServerError, "Failed to remove existing backup directory.".to_string()));
-        }
+/// Restoring over a running server corrupts the world, so a running server
+/// is refused unless `stop=true` confirms it should be stopped first.
+#[post("/restore_server?<stop>")]
+#[tracing::instrument(name = "restore", skip_all, fields(request_id = %request_id))]
+async fn restore_server(
+    jobs: &State<Arc<JobManager>>,
+    lock: &State<Arc<OperationLock>>,
+    request_id: RequestId,
+    stop: Option<bool>,
+) -> Result<Accepted<Json<serde_json::Value>>, Conflict<Json<serde_json::Value>>> {
+    let guard = lock.try_acquire("restore").map_err(busy)?;
+    let stop_first = stop.unwrap_or(false);
+    if !stop_first && server_backend().is_active().await {
+        return Err(Conflict(Json(json!({
+            "error": "The server is running. Stop it first, or confirm with stop=true to stop it before restoring.",
+            "server_running": true
+        }))));
     }
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let extra_mods_dir = std::env::var("EXTRA_MODS_DIR").unwrap_or_else(|_| DEFAULT_EXTRA_MODS_DIR.to_string());
+    let id = jobs.spawn_exclusive(guard, "restore", |job| run_restore(job, server_location, extra_mods_dir, stop_first));
+    Ok(Accepted(Json(json!({"job_id": id}))))
+}
 
-    if let Err(e) = fs::create_dir_all(&backup_dir).await {
-        eprintln!("Failed to create backup directory: {:?}", e);
-        return Err((Status::InternalServerError, "Failed to create backup directory.".to_string()));
-    }
+#[get("/log_tail")]
+async fn log_tail() -> Option<String> {
+    let page = server_backend().logs(1000, None).await.ok()?;
+    Some(page.lines.join("\n"))
+}
 
-    let mods_dir = std::path::Path::new(&server_location).join("mods");
-    let mods_list_path = std::path::Path::new(&server_location).join("mods.list");
+/// Server output after `cursor` (or the last `lines` lines), with the
+/// cursor to pass next time. Used by `mc-manager-cli logs --follow`.
+#[get("/logs?<lines>&<cursor>")]
+async fn logs(lines: Option<usize>, cursor: Option<&str>) -> Result<Json<LogPage>, (Status, String)> {
+    server_backend().logs(lines.unwrap_or(100), cursor).await
+        .map(Json)
+        .map_err(|e| (Status::InternalServerError, e))
+}
 
-    if let Ok(mut entries) = fs::read_dir(&mods_dir).await {
-        let mut mod_names = Vec::new();
-        while let Ok(Some(entry)) = entries.next_entry().await {
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                    if name.ends_with(".jar") {
-                        mod_names.push(name.to_string());
-                    }
-                }
-            }
-        }
-        let mods_list_content = mod_names.join("\n");
-        if let Err(e) = fs::write(&mods_list_path, mods_list_content).await {
-            eprintln!("Failed to write mods.list: {:?}", e);
-            return Err((Status::InternalServerError, "Failed to write mods.list.".to_string()));
-        }
+/// Runs a console command through the backend: RCON for systemd units,
+/// stdin for a child process.
+#[post("/console", data = "<command>")]
+async fn console(command: String, audit: AuditParams<'_>, lock: &State<Arc<OperationLock>>) -> Result<Json<serde_json::Value>, (Status, String)> {
+    let command = command.trim();
+    audit.set("command", command);
+    if command.is_empty() {
+        return Err((Status::BadRequest, "Command is empty.".to_string()));
+    }
+    let _guard = lock.try_acquire("console").map_err(|op| (Status::Conflict, busy_message(&op)))?;
+    match server_backend().send_command(command).await {
+        Ok(reply) => Ok(Json(json!({"reply": reply}))),
+        Err(e) => Err((Status::ServiceUnavailable, e)),
     }
+}
 
-    for item in files_to_backup.iter() {
-        let src = std::path::Path::new(&server_location).join(item);
-        let dst = std::path::Path::new(&backup_dir).join(item);
-        if src.exists() {
-            if src.is_dir() {
-                let status = Command::new("cp").args(["-r", src.to_str().unwrap(), dst.to_str().unwrap()]).status();
-                if !status.map_or(false, |s| s.success()) {
-                    eprintln!("Failed to copy directory from {} to {}", src.display(), dst.display());
-                    return Err((Status::InternalServerError, "Failed to copy directory.".to_string()));
-                }
-            } else {
-                if let Err(e) = fs::copy(&src, &dst).await {
-                    eprintln!("Failed to copy file from {} to {}: {:?}", src.display(), dst.display(), e);
-                    return Err((Status::InternalServerError, "Failed to copy file.".to_string()));
-                }
-            }
-        }
+#[get("/check_server_update")]
+async fn check_server_update(update_check: &State<UpdateCheckCache>, bus: &State<EventBus>) -> Json<serde_json::Value> {
+    // 1. Read the local modpack version and project from $SERVER/config/bcc-common.toml
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let bcc = match read_bcc_common(&server_location).await {
+        Ok(cfg) => cfg,
+        Err(e) => return Json(json!({"error": e})),
+    };
+    let local_version = match bcc.general.modpack_version.clone() {
+        Some(v) => v,
+        None => return Json(json!({"error": "Could not find modpackVersion in bcc-common.toml"})),
+    };
+    // 2. Fetch the latest modpack version from CurseForge API using the curseforge module
+    let client = reqwest::Client::new();
+    let latest = match fetch_latest_server_pack(&client, bcc.project_id()).await {
+        Ok(info) => info,
+        Err(e) => return Json(json!({"error": e})),
+    };
+    // 3. Compare using semver if possible
+    let up_to_date = match (Version::parse(&local_version), Version::parse(&latest.version)) {
+        (Ok(local), Ok(latest)) => local == latest,
+        _ => local_version == latest.version,
+    };
+    // Only notify when an update first shows up, not on every check.
+    let previous = update_check.update_available.lock().unwrap().replace(!up_to_date);
+    if !up_to_date && previous != Some(true) {
+        bus.emit(Event::UpdateAvailable { local_version: local_version.clone(), latest_version: latest.version.clone() });
+    }
+    Json(json!({
+        "local_version": local_version,
+        "latest_version": latest.version,
+        "up_to_date": up_to_date
+    }))
+}
+
+#[get("/status")]
+async fn server_status(lock: &State<Arc<OperationLock>>) -> Json<serde_json::Value> {
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let unit_active = server_backend().is_active().await;
+    let operation = lock.current();
+    let backend = server_backend().name();
+    // The unit can be active long before the game port accepts players, so
+    // readiness comes from an actual Server List Ping.
+    match ping_local_server(&server_location).await {
+        Ok(status) => Json(json!({"backend": backend, "unit_active": unit_active, "operation": operation, "ready": true, "ping": status})),
+        Err(e) => Json(json!({"backend": backend, "unit_active": unit_active, "operation": operation, "ready": false, "error": e})),
     }
+}
 
-    Ok(Json(json!({"status": "Backup complete"})))
+#[get("/metrics/history?<since>")]
+fn metrics_history(history: &State<Arc<MetricsHistory>>, since: Option<&str>) -> Result<Json<serde_json::Value>, (Status, String)> {
+    let since = match since.map(chrono::DateTime::parse_from_rfc3339) {
+        Some(Ok(t)) => Some(t.with_timezone(&chrono::Utc)),
+        Some(Err(_)) => return Err((Status::BadRequest, "`since` must be an RFC 3339 timestamp.".to_string())),
+        None => None,
+    };
+    Ok(Json(json!({
+        "interval_secs": history.interval_secs,
+        "samples": history.since(since)
+    })))
+}
+
+#[get("/tps/history?<since>")]
+fn tps_history(history: &State<Arc<TpsHistory>>, thresholds: &State<LagThresholds>, since: Option<&str>) -> Result<Json<serde_json::Value>, (Status, String)> {
+    let since = match since.map(chrono::DateTime::parse_from_rfc3339) {
+        Some(Ok(t)) => Some(t.with_timezone(&chrono::Utc)),
+        Some(Err(_)) => return Err((Status::BadRequest, "`since` must be an RFC 3339 timestamp.".to_string())),
+        None => None,
+    };
+    Ok(Json(json!({
+        "interval_secs": history.interval_secs,
+        "thresholds": thresholds.inner(),
+        "samples": history.since(since)
+    })))
 }
 
-#[post("/restore_server")]
-async fn restore_server() -> Result<Json<serde_json::Value>, (Status, String)> {
+#[get("/metrics")]
+async fn prometheus_metrics(
+    metrics: &State<Arc<MetricsHistory>>,
+    tps: &State<Arc<TpsHistory>>,
+    requests: &State<RequestCounter>,
+    update_check: &State<UpdateCheckCache>,
+) -> (rocket::http::ContentType, String) {
     let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let extra_mods_dir = std::env::var("EXTRA_MODS_DIR").unwrap_or_else(|_| DEFAULT_EXTRA_MODS_DIR.to_string());
+    let unit = server_backend().status().await;
+    let ping = ping_local_server(&server_location).await.ok();
+    let sample = metrics.latest();
+    let tps_sample = tps.latest();
     let backup_dir = format!("{}_backup", server_location);
-    let files_to_backup = FILES_TO_BACKUP;
-
-    for item in files_to_backup.iter() {
-        let src = std::path::Path::new(&backup_dir).join(item);
-        let dst = std::path::Path::new(&server_location).join(item);
-        if src.exists() {
-            if src.is_dir() {
-                let status = Command::new("cp").args(["-r", src.to_str().unwrap(), dst.to_str().unwrap()]).status();
-                if !status.map_or(false, |s| s.success()) {
-                    eprintln!("Failed to copy directory from {} to {}", src.display(), dst.display());
-                    return Err((Status::InternalServerError, "Failed to copy directory.".to_string()));
-                }
-            } else {
-                if let Err(e) = fs::copy(&src, &dst).await {
-                    eprintln!("Failed to copy file from {} to {}: {:?}", src.display(), dst.display(), e);
-                    return Err((Status::InternalServerError, "Failed to copy file.".to_string()));
-                }
-            }
+    let backup_age = fs::metadata(&backup_dir).await.ok()
+        .and_then(|m| m.modified().ok())
+        .and_then(|t| t.elapsed().ok())
+        .map(|d| d.as_secs_f64());
+
+    let mut exp = Exposition::default();
+    if let Some(unit) = &unit {
+        let state = &unit.active_state;
+        exp.family("mc_unit_active", "gauge", "Whether the server is active, labelled with its ActiveState.")
+            .sample("mc_unit_active", &[("state", state)], if state == "active" { 1.0 } else { 0.0 });
+        exp.family("mc_unit_restarts_total", "counter", "Automatic restarts of the server counted by the backend.")
+            .sample("mc_unit_restarts_total", &[], unit.n_restarts as f64);
+    }
+    exp.gauge("mc_server_ready", "Whether the game port answers a Server List Ping.", Some(if ping.is_some() { 1.0 } else { 0.0 }));
+    exp.gauge("mc_players_online", "Players online according to Server List Ping.", ping.as_ref().map(|p| p.online as f64));
+    exp.gauge("mc_players_max", "Player slots according to Server List Ping.", ping.as_ref().map(|p| p.max as f64));
+    if let Some(tps_sample) = &tps_sample {
+        let dims: Vec<_> = tps_sample.overall.iter().chain(tps_sample.dimensions.iter()).collect();
+        exp.family("mc_tps", "gauge", "Mean ticks per second per dimension.");
+        for dim in &dims {
+            exp.sample("mc_tps", &[("dimension", &dim.dimension)], dim.tps);
         }
+        exp.family("mc_mspt", "gauge", "Mean milliseconds per tick per dimension.");
+        for dim in &dims {
+            exp.sample("mc_mspt", &[("dimension", &dim.dimension)], dim.mspt);
+        }
+    }
+    if let Some(sample) = &sample {
+        exp.gauge("mc_process_cpu_percent", "CPU usage of the server process tree.", sample.cpu_percent);
+        exp.gauge("mc_process_resident_memory_bytes", "Resident memory of the server process tree.", sample.rss_bytes.map(|v| v as f64));
+        exp.gauge("mc_process_threads", "Threads in the server process tree.", sample.threads.map(|v| v as f64));
+        exp.gauge("mc_process_open_files", "Open file descriptors in the server process tree.", sample.open_files.map(|v| v as f64));
+        exp.gauge("mc_server_disk_bytes", "Disk usage of the server directory.", sample.disk_bytes.map(|v| v as f64));
+        exp.gauge("mc_backup_size_bytes", "Disk usage of the backup directory.", sample.backup_bytes.map(|v| v as f64));
+    }
+    exp.gauge("mc_backup_age_seconds", "Seconds since the backup directory was last written.", backup_age);
+    exp.gauge("mc_extra_mods", "Number of extra mod jars.", Some(count_jars(&extra_mods_dir).await as f64));
+    let update_available = *update_check.update_available.lock().unwrap();
+    exp.gauge("mc_update_available", "Whether the last CurseForge check found a newer server pack.", update_available.map(|v| if v { 1.0 } else { 0.0 }));
+    exp.family("mc_manager_http_requests_total", "counter", "HTTP responses served by mc-manager per route.");
+    for ((method, route, status), count) in requests.snapshot() {
+        exp.sample("mc_manager_http_requests_total", &[("method", &method), ("route", &route), ("status", &status.to_string())], count as f64);
     }
 
-    let config_path = std::path::Path::new(&server_location).join("config/bcc-common.toml");
-    let version = if let Ok(mut file) = File::open(&config_path).await {
-        let mut contents = String::new();
-        if file.read_to_string(&mut contents).await.is_ok() {
-            let re = regex::Regex::new(r#"modpackVersion\s*=\s*\"([^\"]*)\""#).unwrap();
-            re.captures(&contents).and_then(|cap| cap.get(1)).map(|m| m.as_str().to_string())
-        } else { None }
-    } else { None };
-
-    let server_properties_path = std::path::Path::new(&server_location).join("server.properties");
-    if let Some(version_val) = version {
-        let motd_val = format!("V{} + extras", version_val);
-        if let Ok(mut file) = File::open(&server_properties_path).await {
-            let mut contents = String::new();
-            if file.read_to_string(&mut contents).await.is_ok() {
-                let motd_re = regex::Regex::new(r"(?m)^motd\s*=.*$").unwrap();
-                let new_contents = if motd_re.is_match(&contents) {
-                    motd_re.replace(&contents, format!("motd={}", motd_val)).to_string()
-                } else {
-                    format!("{}\nmotd={}", contents.trim_end(), motd_val)
-                };
-                if let Err(e) = fs::write(&server_properties_path, new_contents).await {
-                    eprintln!("Failed to write server.properties: {:?}", e);
-                    return Err((Status::InternalServerError, "Failed to write server.properties.".to_string()));
-                }
-            }
-        }
+    (rocket::http::ContentType::new("text", "plain").with_params(("version", "0.0.4")), exp.finish())
+}
+
+#[get("/crashes")]
+async fn list_crashes(crash_log: &State<Arc<CrashLog>>) -> Json<serde_json::Value> {
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let extra_mods_dir = std::env::var("EXTRA_MODS_DIR").unwrap_or_else(|_| DEFAULT_EXTRA_MODS_DIR.to_string());
+    let reports = read_crash_reports(&server_location, &extra_mods_dir).await;
+    Json(json!({
+        "reports": reports,
+        "events": crash_log.events()
+    }))
+}
+
+#[get("/crashes/<file>")]
+async fn download_crash_report(file: &str) -> Result<NamedFile, (Status, String)> {
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    // Only plain file names inside crash-reports/ may be served.
+    if file.contains(['/', '\\']) || file.starts_with('.') || !file.ends_with(".txt") {
+        return Err((Status::BadRequest, "Invalid crash report name.".to_string()));
     }
+    let path = std::path::Path::new(&server_location).join(CRASH_REPORTS_DIR).join(file);
+    NamedFile::open(&path).await.map_err(|_| (Status::NotFound, "Crash report not found.".to_string()))
+}
 
-    let start_script = std::path::Path::new(&server_location).join("startserver.sh");
-    let status = Command::new("chmod").arg("+x").arg(start_script).status();
-    if !status.map_or(false, |s| s.success()) {
-        eprintln!("Failed to chmod startserver.sh");
-        return Err((Status::InternalServerError, "Failed to chmod startserver.sh.".to_string()));
-    }
-
-    let mods_list_dst = std::path::Path::new(&server_location).join("mods.list");
-    if !mods_list_dst.exists() {
-        let mods_dir = std::path::Path::new(&server_location).join("mods");
-        if let Ok(mut entries) = fs::read_dir(&mods_dir).await {
-            let mut mod_names = Vec::new();
-            while let Ok(Some(entry)) = entries.next_entry().await {
-                let path = entry.path();
-                if path.is_file() {
-                    if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                        if name.ends_with(".jar") {
-                            mod_names.push(name.to_string());
-                        }
-                    }
-                }
-            }
-            let mods_list_content = mod_names.join("\n");
-            if let Err(e) = fs::write(&mods_list_dst, mods_list_content).await {
-                eprintln!("Failed to write mods.list: {:?}", e);
-                return Err((Status::InternalServerError, "Failed to write mods.list.".to_string()));
+#[get("/crash_guard")]
+async fn crash_guard(config: &State<CrashLoopConfig>) -> Json<serde_json::Value> {
+    let quarantine = quarantine_dir();
+    let mut quarantined = Vec::new();
+    if let Ok(mut entries) = fs::read_dir(&quarantine).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.ends_with(".jar") {
+                quarantined.push(name);
             }
         }
     }
+    Json(json!({
+        "config": config.inner(),
+        "quarantine_dir": quarantine,
+        "quarantined": quarantined,
+        "actions": read_guard_actions().await
+    }))
+}
 
-    Ok(Json(json!({"status": "Restore complete"})))
+#[get("/audit?<page>&<per_page>")]
+async fn audit_entries(audit_log: &State<Arc<AuditLog>>, page: Option<usize>, per_page: Option<usize>) -> Json<serde_json::Value> {
+    let page = page.unwrap_or(1).max(1);
+    let per_page = per_page.unwrap_or(50).clamp(1, 500);
+    let (entries, total) = audit_log.page(page, per_page).await;
+    Json(json!({
+        "page": page,
+        "per_page": per_page,
+        "total": total,
+        "entries": entries
+    }))
 }
 
-#[get("/log_tail")]
-async fn log_tail() -> Option<String> {
-    // Use journalctl to get the last 1000 lines for the atm10.service (user scope), clean output
-    let output = Command::new("journalctl")
-        .args(["--user", "-u", "atm10.service", "-n", "1000", "--no-pager", "--output=cat"])
-        .output()
-        .ok()?;
-    if output.status.success() {
-        Some(String::from_utf8_lossy(&output.stdout).to_string())
-    } else {
-        None
+#[get("/pack_info")]
+async fn pack_info() -> Result<Json<serde_json::Value>, (Status, String)> {
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    match read_bcc_common(&server_location).await {
+        Ok(cfg) => Ok(Json(json!({
+            "project_id": cfg.project_id(),
+            "name": cfg.general.modpack_name,
+            "version": cfg.general.modpack_version
+        }))),
+        Err(e) => {
+            error!("Failed to load pack info: {}", e);
+            Err((Status::InternalServerError, e))
+        }
     }
 }
 
-#[get("/check_server_update")]
-async fn check_server_update() -> Json<serde_json::Value> {
-    // 1. Read the local modpack version from $SERVER/config/bcc-common.toml
+#[get("/server_properties")]
+async fn get_server_properties() -> Result<Json<serde_json::Value>, (Status, String)> {
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let props = match read_server_properties(&server_location).await {
+        Ok(props) => props,
+        Err(e) => {
+            error!("Failed to load server.properties: {}", e);
+            return Err((Status::InternalServerError, e));
+        }
+    };
+    let values: serde_json::Map<String, serde_json::Value> = props.entries()
+        .map(|(k, v)| (k.to_string(), json!(v)))
+        .collect();
+    Ok(Json(json!({
+        "properties": values,
+        "schema": KNOWN_PROPERTIES
+    })))
+}
+
+#[patch("/server_properties", data = "<changes>")]
+async fn patch_server_properties(
+    changes: Json<HashMap<String, String>>,
+    audit: AuditParams<'_>,
+    lock: &State<Arc<OperationLock>>,
+) -> Result<Json<serde_json::Value>, (Status, Json<serde_json::Value>)> {
+    audit.set("changes", &*changes);
+    let _guard = lock.try_acquire("edit_server_properties").map_err(|op| (Status::Conflict, Json(json!({"errors": [busy_message(&op)]}))))?;
     let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
-    let config_path = std::path::Path::new(&server_location).join("config/bcc-common.toml");
-    let mut file = match File::open(&config_path).await {
-        Ok(f) => f,
-        Err(_) => return Json(json!({"error": "Could not open bcc-common.toml"})),
+    let mut props = match read_server_properties(&server_location).await {
+        Ok(props) => props,
+        Err(e) => {
+            error!("Failed to load server.properties: {}", e);
+            return Err((Status::InternalServerError, Json(json!({"errors": [e]}))));
+        }
     };
-    let mut contents = String::new();
-    if file.read_to_string(&mut contents).await.is_err() {
-        return Json(json!({"error": "Could not read bcc-common.toml"}));
-    }
-    let re = regex::Regex::new(r#"modpackVersion\s*=\s*\"([^\"]+)\""#).unwrap();
-    let local_version = re.captures(&contents).and_then(|cap| cap.get(1)).map(|m| m.as_str().to_string());
-    if local_version.is_none() {
-        return Json(json!({"error": "Could not find modpackVersion in bcc-common.toml"}));
-    }
-    let local_version = local_version.unwrap();
-    // 2. Fetch the latest modpack version from CurseForge API using the curseforge module
-    let client = reqwest::Client::new();
-    let latest = match fetch_latest_server_pack(&client).await {
-        Ok(info) => info,
-        Err(e) => return Json(json!({"error": e})),
+    let restart_required = match apply_changes(&mut props, &changes) {
+        Ok(keys) => keys,
+        Err(errors) => return Err((Status::UnprocessableEntity, Json(json!({"errors": errors})))),
     };
-    // 3. Compare using semver if possible
-    let up_to_date = match (Version::parse(&local_version), Version::parse(&latest.version)) {
-        (Ok(local), Ok(latest)) => local == latest,
-        _ => local_version == latest.version,
+    if let Err(e) = write_server_properties(&server_location, &props).await {
+        error!("Failed to write server.properties: {}", e);
+        return Err((Status::InternalServerError, Json(json!({"errors": [e]}))));
+    }
+    Ok(Json(json!({"restart_required": restart_required})))
+}
+
+#[get("/motd/preview?<template>")]
+async fn motd_preview(template: Option<String>) -> Json<serde_json::Value> {
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let extra_mods_dir = std::env::var("EXTRA_MODS_DIR").unwrap_or_else(|_| DEFAULT_EXTRA_MODS_DIR.to_string());
+    let template = match template {
+        Some(t) => t,
+        None => load_template().await,
     };
+    let ctx = MotdContext::gather(&server_location, &extra_mods_dir).await;
+    let motd = render_motd(&template, &ctx);
     Json(json!({
-        "local_version": local_version,
-        "latest_version": latest.version,
-        "up_to_date": up_to_date
+        "template": template,
+        "motd": motd,
+        "plain": strip_codes(&motd)
     }))
 }
 
-#[post("/update_extras")]
-async fn update_extras() -> Result<Status, (Status, String)> {
-    if !systemctl_server(ServerAction::Stop) {
-        return Err((Status::InternalServerError, "Failed to stop server.".to_string()));
+#[put("/motd/template", data = "<template>")]
+async fn put_motd_template(template: String, audit: AuditParams<'_>, lock: &State<Arc<OperationLock>>) -> Result<Status, (Status, String)> {
+    audit.set("template", &template);
+    let _guard = lock.try_acquire("save_motd_template").map_err(|op| (Status::Conflict, busy_message(&op)))?;
+    match save_template(&template).await {
+        Ok(_) => Ok(Status::Ok),
+        Err(e) => {
+            error!("Failed to save MOTD template: {}", e);
+            Err((Status::BadRequest, e))
+        }
     }
+}
 
+#[post("/motd/apply")]
+async fn motd_apply(lock: &State<Arc<OperationLock>>) -> Result<Json<serde_json::Value>, (Status, String)> {
+    let _guard = lock.try_acquire("apply_motd").map_err(|op| (Status::Conflict, busy_message(&op)))?;
     let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
-    let mods_dir = std::path::Path::new(&server_location).join("mods");
     let extra_mods_dir = std::env::var("EXTRA_MODS_DIR").unwrap_or_else(|_| DEFAULT_EXTRA_MODS_DIR.to_string());
-
-    let mods_list_path = std::path::Path::new(&server_location).join("mods.list");
-    let allowed_mods: Vec<String> = match fs::read_to_string(&mods_list_path).await {
-        Ok(contents) => contents.lines().map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+    match apply_motd(&server_location, &extra_mods_dir).await {
+        Ok(motd) => Ok(Json(json!({"motd": motd}))),
         Err(e) => {
-            let err_msg = format!("Failed to read mods.list: {}", e);
-            eprintln!("[update_extras] {}", err_msg);
-            return Err((Status::InternalServerError, err_msg));
+            error!("Failed to apply MOTD: {}", e);
+            Err((Status::InternalServerError, e))
         }
-    };
+    }
+}
 
-    if let Ok(mut entries) = fs::read_dir(&mods_dir).await {
-        while let Ok(Some(entry)) = entries.next_entry().await {
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                    if name.ends_with(".jar") && !allowed_mods.contains(&name.to_string()) {
-                        if let Err(e) = fs::remove_file(&path).await {
-                            eprintln!("[update_extras] Failed to remove disallowed mod '{}': {:?}", name, e);
-                        }
-                    }
-                }
-            }
+#[get("/players")]
+async fn player_lists() -> Result<Json<PlayerLists>, (Status, String)> {
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    match read_player_lists(&server_location).await {
+        Ok(lists) => Ok(Json(lists)),
+        Err(e) => {
+            error!("Failed to read player lists: {}", e);
+            Err((Status::InternalServerError, e))
         }
-    } else {
-        let err_msg = format!("Failed to read mods directory: {}", mods_dir.display());
-        eprintln!("[update_extras] {}", err_msg);
-        return Err((Status::InternalServerError, err_msg));
     }
+}
 
-    if let Ok(mut entries) = fs::read_dir(&extra_mods_dir).await {
-        while let Ok(Some(entry)) = entries.next_entry().await {
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                    if name.ends_with(".jar") {
-                        let dest = mods_dir.join(name);
-                        if let Err(e) = fs::copy(&path, &dest).await {
-                            eprintln!("[update_extras] Failed to copy extra mod '{}': {:?}", name, e);
-                        }
-                    }
-                }
-            }
+#[derive(Deserialize, Serialize)]
+pub struct PlayerRequest {
+    name: Option<String>,
+    ip: Option<String>,
+    level: Option<u8>,
+    reason: Option<String>,
+}
+
+async fn run_player_change(change: PlayerChange, lock: &Arc<OperationLock>) -> Result<Json<serde_json::Value>, (Status, String)> {
+    if let Err(e) = change.validate() {
+        return Err((Status::BadRequest, e));
+    }
+    let _guard = lock.try_acquire("edit_players").map_err(|op| (Status::Conflict, busy_message(&op)))?;
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    match apply_player_change(&server_location, change).await {
+        Ok(msg) => Ok(Json(json!({"status": msg}))),
+        Err(e) => {
+            error!("Failed to apply player change: {}", e);
+            Err((Status::InternalServerError, e))
         }
     }
+}
+
+/// Catches the session table up with the log before answering.
+async fn current_sessions(tracker: &SessionTracker) -> Vec<Session> {
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    if let Err(e) = tracker.update(&server_location).await {
+        warn!("Failed to update sessions: {}", e);
+    }
+    tracker.sessions().await
+}
+
+#[get("/players/online")]
+async fn players_online(tracker: &State<Arc<SessionTracker>>) -> Json<serde_json::Value> {
+    let online: Vec<Session> = current_sessions(tracker).await
+        .into_iter()
+        .filter(|s| s.left.is_none())
+        .collect();
+    Json(json!({"count": online.len(), "players": online}))
+}
+
+#[get("/players/history?<player>&<limit>")]
+async fn players_history(player: Option<&str>, limit: Option<usize>, tracker: &State<Arc<SessionTracker>>) -> Json<serde_json::Value> {
+    let mut sessions = current_sessions(tracker).await;
+    if let Some(player) = player {
+        sessions.retain(|s| s.player.eq_ignore_ascii_case(player));
+    }
+    let playtime = playtime_totals(&sessions);
+    // Most recent first, so `limit` keeps the latest sessions.
+    sessions.reverse();
+    sessions.truncate(limit.unwrap_or(100));
+    Json(json!({"sessions": sessions, "playtime": playtime}))
+}
+
+#[post("/players/<list>", data = "<req>")]
+async fn add_player(list: &str, req: Json<PlayerRequest>, audit: AuditParams<'_>, lock: &State<Arc<OperationLock>>) -> Result<Json<serde_json::Value>, (Status, String)> {
+    let req = req.into_inner();
+    audit.set("request", &req);
+    let name = req.name.unwrap_or_default();
+    let change = match list {
+        "ops" => PlayerChange::Op { name, level: req.level.unwrap_or(4) },
+        "whitelist" => PlayerChange::WhitelistAdd { name },
+        "bans" => PlayerChange::Ban { name, reason: req.reason },
+        "ip_bans" => PlayerChange::BanIp { ip: req.ip.unwrap_or_default(), reason: req.reason },
+        _ => return Err((Status::NotFound, format!("Unknown player list '{}'", list))),
+    };
+    run_player_change(change, lock).await
+}
+
+#[delete("/players/<list>/<target>")]
+async fn remove_player(list: &str, target: &str, audit: AuditParams<'_>, lock: &State<Arc<OperationLock>>) -> Result<Json<serde_json::Value>, (Status, String)> {
+    audit.set("list", list);
+    audit.set("target", target);
+    let target = target.to_string();
+    let change = match list {
+        "ops" => PlayerChange::Deop { name: target },
+        "whitelist" => PlayerChange::WhitelistRemove { name: target },
+        "bans" => PlayerChange::Pardon { name: target },
+        "ip_bans" => PlayerChange::PardonIp { ip: target },
+        _ => return Err((Status::NotFound, format!("Unknown player list '{}'", list))),
+    };
+    run_player_change(change, lock).await
+}
+
+/// With `dry_run=true`, returns what would be removed and copied instead of
+/// starting a job.
+#[post("/update_extras?<dry_run>")]
+#[tracing::instrument(name = "update_extras", skip_all, fields(request_id = %request_id))]
+async fn update_extras(
+    jobs: &State<Arc<JobManager>>,
+    lock: &State<Arc<OperationLock>>,
+    request_id: RequestId,
+    dry_run: Option<bool>,
+) -> Result<Custom<Json<serde_json::Value>>, Custom<Json<serde_json::Value>>> {
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let extra_mods_dir = std::env::var("EXTRA_MODS_DIR").unwrap_or_else(|_| DEFAULT_EXTRA_MODS_DIR.to_string());
+    if dry_run.unwrap_or(false) {
+        return match plan_update_extras(&server_location, &extra_mods_dir).await {
+            Ok(plan) => Ok(Custom(Status::Ok, Json(json!(plan)))),
+            Err(e) => Err(Custom(Status::InternalServerError, Json(json!({"error": e})))),
+        };
+    }
+    let guard = lock.try_acquire("update_extras").map_err(|op| Custom(Status::Conflict, busy(op).0))?;
+    let id = jobs.spawn_exclusive(guard, "update_extras", |job| run_update_extras(job, server_location, extra_mods_dir));
+    Ok(Custom(Status::Accepted, Json(json!({"job_id": id}))))
+}
+
+/// Renders the systemd user unit and, unless `dry_run` is set, installs,
+/// reloads and enables it. A different existing unit is only replaced with
+/// `overwrite=true`; otherwise the response is a 409 with the diff.
+#[post("/systemd_unit?<dry_run>&<overwrite>", data = "<options>")]
+async fn systemd_unit(
+    options: Json<UnitOptions>,
+    lock: &State<Arc<OperationLock>>,
+    dry_run: Option<bool>,
+    overwrite: Option<bool>,
+) -> Result<Json<UnitPlan>, Custom<Json<serde_json::Value>>> {
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let unit = std::env::var("SERVER_UNIT").unwrap_or_else(|_| SYSTEMD_SERVICE.to_string());
+    let fail = |status, e: String| Custom(status, Json(json!({"error": e})));
+    render_unit(&server_location, &options).map_err(|e| fail(Status::BadRequest, e))?;
+    let plan = plan_unit(&unit, &server_location, &options).await.map_err(|e| fail(Status::InternalServerError, e))?;
+    if dry_run.unwrap_or(false) {
+        return Ok(Json(plan));
+    }
+    if plan.overwrites() && !overwrite.unwrap_or(false) {
+        return Err(Custom(Status::Conflict, Json(json!({
+            "error": format!("{} already exists and differs; confirm with overwrite=true to replace it.\n{}", plan.path, plan.diff.clone().unwrap_or_default()),
+            "plan": plan,
+        }))));
+    }
+    let _guard = lock.try_acquire("install_unit").map_err(|op| Custom(Status::Conflict, busy(op).0))?;
+    install_unit(&plan).await.map_err(|e| fail(Status::InternalServerError, e))?;
+    info!("Installed {}", plan.path);
+    Ok(Json(plan))
+}
+
+/// Sets up a new server directory from a CurseForge server pack as a job.
+/// The request is checked first, so a bad project, an unaccepted EULA or a
+/// non-empty directory is a 400 rather than a failed job.
+#[post("/provision", data = "<request>")]
+#[tracing::instrument(name = "provision", skip_all, fields(request_id = %request_id))]
+async fn provision(
+    request: Json<ProvisionRequest>,
+    jobs: &State<Arc<JobManager>>,
+    lock: &State<Arc<OperationLock>>,
+    audit: AuditParams<'_>,
+    request_id: RequestId,
+) -> Result<Accepted<Json<serde_json::Value>>, Custom<Json<serde_json::Value>>> {
+    audit.set("request", &*request);
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let target = check_request(&request, &server_location).await
+        .map_err(|errors| Custom(Status::BadRequest, Json(json!({"errors": errors}))))?;
+    let guard = lock.try_acquire("provision").map_err(|op| Custom(Status::Conflict, busy(op).0))?;
+    let request = request.into_inner();
+    let id = jobs.spawn_exclusive(guard, "provision", |job| run_provision(job, request, target, ProvisionContext::from_env()));
+    Ok(Accepted(Json(json!({"job_id": id}))))
+}
+
+#[get("/servers")]
+async fn registered_servers() -> Result<Json<Vec<RegisteredServer>>, (Status, String)> {
+    read_registered_servers(&servers_file_path()).await.map(Json).map_err(|e| (Status::InternalServerError, e))
+}
+
+#[get("/worlds")]
+async fn worlds() -> Result<Json<Vec<WorldInfo>>, (Status, String)> {
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    list_worlds(&server_location).await.map(Json).map_err(|e| (Status::InternalServerError, e))
+}
+
+/// Streams the world as a zip while it is being built.
+#[get("/worlds/<name>/download")]
+async fn download_world(name: &str) -> Result<(rocket::http::ContentType, ReaderStream<One<File>>), (Status, String)> {
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    check_world_name(name).map_err(|e| (Status::BadRequest, e))?;
+    let stream = world_zip_stream(&server_location, name).await.map_err(|e| (Status::NotFound, e))?;
+    Ok((rocket::http::ContentType::new("application", "zip"), ReaderStream::one(stream)))
+}
+
+/// Installs a world zip sent as the request body under a new name.
+#[put("/worlds/<name>", data = "<upload>")]
+async fn upload_world(name: &str, mut upload: TempFile<'_>, audit: AuditParams<'_>, lock: &State<Arc<OperationLock>>) -> Result<Json<serde_json::Value>, (Status, String)> {
+    let _guard = lock.try_acquire("upload_world").map_err(|op| (Status::Conflict, busy_message(&op)))?;
+    audit.set("size", upload.len());
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    check_world_name(name).map_err(|e| (Status::BadRequest, e))?;
+    if fs::metadata(std::path::Path::new(&server_location).join(name)).await.is_ok() {
+        return Err((Status::Conflict, format!("'{}' already exists.", name)));
+    }
+    let archive = std::path::Path::new(&server_location).join(format!(".{}.upload.zip", name));
+    upload.copy_to(&archive).await.map_err(|e| (Status::InternalServerError, format!("Failed to save upload: {}", e)))?;
+    let installed = install_world(&server_location, name, &archive).await;
+    let _ = fs::remove_file(&archive).await;
+    let files = installed.map_err(|e| (Status::BadRequest, e))?;
+    info!("Installed world {} ({} files)", name, files);
+    Ok(Json(json!({"world": name, "files": files})))
+}
+
+/// Makes `name` the world the server loads. Refused while the server runs.
+#[post("/worlds/<name>/activate")]
+async fn activate_world(name: &str, lock: &State<Arc<OperationLock>>) -> Result<Json<serde_json::Value>, (Status, String)> {
+    let _guard = lock.try_acquire("switch_world").map_err(|op| (Status::Conflict, busy_message(&op)))?;
+    if server_backend().is_active().await {
+        return Err((Status::Conflict, "Stop the server before switching worlds.".to_string()));
+    }
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    switch_world(&server_location, name).await.map_err(|e| (Status::BadRequest, e))?;
+    Ok(Json(json!({"active": name})))
+}
+
+/// Seed, spawn, game rules, time and data packs from a world's `level.dat`;
+/// the active world unless `name` is given.
+#[get("/world/info?<name>")]
+async fn world_info(name: Option<&str>) -> Result<Json<LevelInfo>, (Status, String)> {
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let world = match name {
+        Some(name) => name.to_string(),
+        None => active_world(&server_location).await,
+    };
+    read_level_info(&server_location, &world).await.map(Json).map_err(|e| (Status::NotFound, e))
+}
+
+/// Edits game rules in `level.dat`. The server rewrites the file while it
+/// runs, so the active world can only be edited with the server stopped.
+#[patch("/world/gamerules?<name>", data = "<changes>")]
+async fn patch_game_rules(
+    name: Option<&str>,
+    changes: Json<HashMap<String, String>>,
+    audit: AuditParams<'_>,
+    lock: &State<Arc<OperationLock>>,
+) -> Result<Json<serde_json::Value>, (Status, Json<serde_json::Value>)> {
+    audit.set("changes", &*changes);
+    let _guard = lock.try_acquire("edit_game_rules").map_err(|op| (Status::Conflict, Json(json!({"errors": [busy_message(&op)]}))))?;
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let active = active_world(&server_location).await;
+    let world = name.map(|n| n.to_string()).unwrap_or_else(|| active.clone());
+    if world == active && server_backend().is_active().await {
+        return Err((Status::Conflict, Json(json!({"errors": ["Stop the server before editing game rules."]}))));
+    }
+    let game_rules = set_game_rules(&server_location, &world, &changes).await
+        .map_err(|errors| (Status::UnprocessableEntity, Json(json!({"errors": errors}))))?;
+    Ok(Json(json!({"world": world, "game_rules": game_rules})))
+}
 
-    if !systemctl_server(ServerAction::Start) {
-        return Err((Status::InternalServerError, "Failed to start server.".to_string()));
+/// Archives the active world and sets the seed for a fresh one, as a job.
+#[post("/worlds/reset", data = "<reset>")]
+#[tracing::instrument(name = "reset_world", skip_all, fields(request_id = %request_id))]
+async fn reset_world(
+    reset: Json<WorldReset>,
+    jobs: &State<Arc<JobManager>>,
+    lock: &State<Arc<OperationLock>>,
+    audit: AuditParams<'_>,
+    request_id: RequestId,
+) -> Result<Accepted<Json<serde_json::Value>>, Conflict<Json<serde_json::Value>>> {
+    audit.set("reset", &*reset);
+    let guard = lock.try_acquire("reset_world").map_err(busy)?;
+    if server_backend().is_active().await {
+        return Err(Conflict(Json(json!({"error": "Stop the server before resetting the world.", "server_running": true}))));
     }
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let seed = reset.into_inner().seed;
+    let id = jobs.spawn_exclusive(guard, "reset_world", |job| run_reset_world(job, server_location, seed));
+    Ok(Accepted(Json(json!({"job_id": id}))))
+}
+
+#[get("/jvm")]
+async fn get_jvm() -> Result<Json<serde_json::Value>, (Status, String)> {
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let settings = read_jvm_settings(&server_location).await.map_err(|e| (Status::InternalServerError, e))?;
+    let memory = system_memory();
+    let warnings = match validate_jvm(&settings, memory) {
+        Ok(warnings) => warnings,
+        Err(errors) => errors,
+    };
+    Ok(Json(json!({"settings": settings, "memory": memory, "warnings": warnings})))
+}
+
+/// Edits `user_jvm_args.txt` and the Java path in `startserver.sh`. Changes
+/// apply on the next start.
+#[patch("/jvm", data = "<update>")]
+async fn patch_jvm(update: Json<JvmUpdate>, audit: AuditParams<'_>, lock: &State<Arc<OperationLock>>) -> Result<Json<serde_json::Value>, (Status, Json<serde_json::Value>)> {
+    audit.set("update", &*update);
+    let _guard = lock.try_acquire("edit_jvm").map_err(|op| (Status::Conflict, Json(json!({"errors": [busy_message(&op)]}))))?;
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let change = update_jvm_settings(&server_location, &update).await
+        .map_err(|errors| (Status::UnprocessableEntity, Json(json!({"errors": errors}))))?;
+    let restart_required = server_backend().is_active().await;
+    Ok(Json(json!({"settings": change.settings, "warnings": change.warnings, "restart_required": restart_required})))
+}
+
+#[get("/jvm/javas")]
+async fn jvm_javas() -> Json<JavaReport> {
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    Json(detect_javas(&server_location).await)
+}
+
+#[get("/jobs")]
+fn list_jobs(jobs: &State<Arc<JobManager>>) -> Json<Vec<JobSnapshot>> {
+    Json(jobs.list())
+}
+
+#[get("/jobs/<id>")]
+fn job_status(jobs: &State<Arc<JobManager>>, id: u64) -> Option<Json<JobSnapshot>> {
+    jobs.get(id).map(|job| Json(job.snapshot()))
+}
+
+/// Streams the job's snapshot every `JOB_EVENT_INTERVAL_MS` until it finishes.
+#[get("/jobs/<id>/events")]
+fn job_events(jobs: &State<Arc<JobManager>>, id: u64) -> Option<EventStream![]> {
+    let job = jobs.get(id)?;
+    Some(EventStream! {
+        let mut interval = rocket::tokio::time::interval(std::time::Duration::from_millis(JOB_EVENT_INTERVAL_MS));
+        loop {
+            interval.tick().await;
+            let snapshot = job.snapshot();
+            let done = snapshot.finished.is_some();
+            yield SseEvent::json(&snapshot);
+            if done {
+                break;
+            }
+        }
+    })
+}
 
-    Ok(Status::Ok)
+#[get("/unauthorized")]
+fn unauthorized() -> (Status, &'static str) {
+    (Status::Unauthorized, "401 Unauthorized: a valid API token is required.")
 }
 
 #[catch(400)]
@@ -474,12 +939,66 @@ fn bad_request(_req: &Request) -> &'static str {
 
 #[launch]
 fn rocket() -> rocket::Rocket<rocket::Build> {
+    init_logging();
     let mut config = Config::release_default();
     config.address = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
     config.limits = rocket::data::Limits::new()
         .limit("file", ByteUnit::Gibibyte(1)) // Increased file limit
-        .limit("form", ByteUnit::Gibibyte(1)); // Increased form limit
+        .limit("form", ByteUnit::Gibibyte(1)) // Increased form limit
+        .limit("file/zip", ByteUnit::Gibibyte(16)); // World uploads
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let metrics = Arc::new(metrics_history_from_env());
+    let sampler_metrics = metrics.clone();
+    let tps = Arc::new(tps_history_from_env());
+    let sampler_tps = tps.clone();
+    let thresholds = LagThresholds::from_env();
+    let requests = RequestCounter::default();
+    let crash_log = Arc::new(CrashLog::default());
+    let watcher_crash_log = crash_log.clone();
+    let guard_crash_log = crash_log.clone();
+    let crash_loop = CrashLoopConfig::from_env();
+    let extra_mods_dir = std::env::var("EXTRA_MODS_DIR").unwrap_or_else(|_| DEFAULT_EXTRA_MODS_DIR.to_string());
+    let bus = EventBus::from_env();
+    let op_lock = Arc::new(OperationLock::default());
+    let guard_op_lock = op_lock.clone();
+    let audit_log = Arc::new(AuditLog::new(audit_log_path(), trusted_proxies_from_env()));
+    let watcher_bus = bus.clone();
+    let sessions = Arc::new(SessionTracker::from_env());
+    let tracker_sessions = sessions.clone();
     rocket::custom(config) 
+        .attach(RequestLogger)
+        .attach(TokenAuth::from_env())
+        .attach(requests.clone())
+        .manage(requests)
+        .attach(audit_log.clone())
+        .manage(audit_log)
+        .manage(UpdateCheckCache::default())
+        .manage(metrics)
+        .manage(tps)
+        .manage(thresholds)
+        .manage(crash_log)
+        .manage(crash_loop)
+        .manage(Arc::new(JobManager::default()))
+        .manage(op_lock)
+        .manage(bus.clone())
+        .manage(sessions)
+        .attach(AdHoc::on_liftoff("Background tasks", move |_| Box::pin(async move {
+            spawn_sampler(sampler_metrics, server_location.clone());
+            spawn_tps_sampler(sampler_tps, thresholds);
+            spawn_crash_watcher(watcher_crash_log, bus, server_location.clone());
+            spawn_event_watcher(watcher_bus, server_location.clone());
+            spawn_session_tracker(tracker_sessions, server_location.clone());
+            spawn_crash_guard(guard_crash_log, guard_op_lock, crash_loop, server_location, extra_mods_dir);
+        })))
+        // A child-process server would otherwise be orphaned with its stdin closed.
+        .attach(AdHoc::on_shutdown("Stop child server", |_| Box::pin(async {
+            let backend = server_backend();
+            if backend.manages_process()
+                && let Err(e) = backend.stop().await
+            {
+                error!("Failed to stop server on shutdown: {}", e);
+            }
+        })))
         .mount("/", routes![
             index_html, 
             style_css, 
@@ -493,6 +1012,44 @@ fn rocket() -> rocket::Rocket<rocket::Build> {
             update_extras, 
             log_tail, 
             check_server_update, 
+            server_status, 
+            metrics_history, 
+            tps_history, 
+            prometheus_metrics, 
+            list_crashes, 
+            download_crash_report, 
+            crash_guard, 
+            audit_entries, 
+            list_jobs, 
+            job_status, 
+            job_events, 
+            logs, 
+            console, 
+            systemd_unit, 
+            worlds, 
+            download_world, 
+            upload_world, 
+            activate_world, 
+            reset_world, 
+            world_info, 
+            patch_game_rules, 
+            provision, 
+            registered_servers, 
+            get_jvm, 
+            patch_jvm, 
+            jvm_javas, 
+            unauthorized, 
+            pack_info, 
+            get_server_properties, 
+            patch_server_properties, 
+            motd_preview, 
+            put_motd_template, 
+            motd_apply, 
+            player_lists, 
+            players_online, 
+            players_history, 
+            add_player, 
+            remove_player, 
             backup_server, 
             restore_server
         ])
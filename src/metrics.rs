@@ -0,0 +1,171 @@
+use std::process::Command;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use rocket::tokio;
+use serde::Serialize;
+use crate::constants::{DEFAULT_METRICS_HISTORY, DEFAULT_METRICS_INTERVAL_SECS, DISK_SAMPLE_EVERY};
+use crate::history::{History, Sample};
+use crate::serverctl::server_backend;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsSample {
+    pub time: DateTime<Utc>,
+    pub pid: Option<u32>,
+    pub cpu_percent: Option<f64>,
+    pub rss_bytes: Option<u64>,
+    pub threads: Option<u64>,
+    pub open_files: Option<u64>,
+    pub disk_bytes: Option<u64>,
+    pub backup_bytes: Option<u64>,
+}
+
+impl Sample for MetricsSample {
+    fn time(&self) -> DateTime<Utc> {
+        self.time
+    }
+}
+
+pub type MetricsHistory = History<MetricsSample>;
+
+pub fn metrics_history_from_env() -> MetricsHistory {
+    History::from_env("METRICS_INTERVAL_SECS", DEFAULT_METRICS_INTERVAL_SECS, "METRICS_HISTORY", DEFAULT_METRICS_HISTORY)
+}
+
+struct ProcStat {
+    cpu_ticks: u64,
+    threads: u64,
+}
+
+impl std::iter::Sum for ProcStat {
+    fn sum<I: Iterator<Item = ProcStat>>(iter: I) -> Self {
+        iter.fold(ProcStat { cpu_ticks: 0, threads: 0 }, |acc, s| ProcStat {
+            cpu_ticks: acc.cpu_ticks + s.cpu_ticks,
+            threads: acc.threads + s.threads,
+        })
+    }
+}
+
+fn read_proc_stat(pid: u32) -> Option<ProcStat> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name is wrapped in parentheses and may contain spaces, so
+    // split after the last ')'. Fields from there start at `state` (field 3).
+    let rest = &stat[stat.rfind(')')? + 2..];
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    let threads: u64 = fields.get(17)?.parse().ok()?;
+    Some(ProcStat { cpu_ticks: utime + stime, threads })
+}
+
+fn read_rss_bytes(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+fn count_open_files(pid: u32) -> Option<u64> {
+    std::fs::read_dir(format!("/proc/{}/fd", pid)).ok().map(|entries| entries.count() as u64)
+}
+
+fn disk_usage(path: &str) -> Option<u64> {
+    let output = Command::new("du").args(["-sb", path]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout).split_whitespace().next()?.parse().ok()
+}
+
+/// `pid` and all of its descendants. `startserver.sh` usually runs Java as a
+/// child rather than exec'ing it, so the unit's main PID is just the shell.
+fn process_tree(pid: u32) -> Vec<u32> {
+    let mut pids = vec![pid];
+    let mut i = 0;
+    while i < pids.len() {
+        let tasks = format!("/proc/{}/task", pids[i]);
+        if let Ok(entries) = std::fs::read_dir(&tasks) {
+            for entry in entries.flatten() {
+                let children = std::fs::read_to_string(entry.path().join("children")).unwrap_or_default();
+                pids.extend(children.split_whitespace().filter_map(|c| c.parse::<u32>().ok()));
+            }
+        }
+        i += 1;
+    }
+    pids
+}
+
+/// Sums `read` over the whole process tree, or `None` if no process in it
+/// could be read.
+fn sum_tree<T: std::iter::Sum<T>>(pids: &[u32], read: impl Fn(u32) -> Option<T>) -> Option<T> {
+    let values: Vec<T> = pids.iter().filter_map(|pid| read(*pid)).collect();
+    if values.is_empty() { None } else { Some(values.into_iter().sum()) }
+}
+
+fn clock_ticks_per_sec() -> u64 {
+    Command::new("getconf").arg("CLK_TCK").output().ok()
+        .and_then(|o| String::from_utf8_lossy(&o.stdout).trim().parse().ok())
+        .unwrap_or(100)
+}
+
+/// Samples the server process every `interval_secs` until the manager exits.
+/// Disk usage is expensive on large worlds, so it is only refreshed every
+/// few samples and carried forward in between.
+pub fn spawn_sampler(history: Arc<MetricsHistory>, server_location: String) {
+    tokio::spawn(async move {
+        let ticks_per_sec = clock_ticks_per_sec() as f64;
+        let mut interval = tokio::time::interval(Duration::from_secs(history.interval_secs));
+        let mut previous: Option<(u32, u64, Instant)> = None;
+        let mut disk_bytes = None;
+        let mut backup_bytes = None;
+        let mut tick: u64 = 0;
+        loop {
+            interval.tick().await;
+            let refresh_disk = tick.is_multiple_of(DISK_SAMPLE_EVERY);
+            tick += 1;
+            let location = server_location.clone();
+            let pid = server_backend().status().await.and_then(|s| s.main_pid);
+            let (pid, stat, rss_bytes, open_files, disk) = tokio::task::spawn_blocking(move || {
+                let tree = pid.map(process_tree).unwrap_or_default();
+                let stat = sum_tree(&tree, read_proc_stat);
+                let rss = sum_tree(&tree, read_rss_bytes);
+                let files = sum_tree(&tree, count_open_files);
+                let disk = if refresh_disk {
+                    Some((disk_usage(&location), disk_usage(&format!("{}_backup", location))))
+                } else {
+                    None
+                };
+                (pid, stat, rss, files, disk)
+            }).await.unwrap_or((None, None, None, None, None));
+            if let Some((server, backup)) = disk {
+                disk_bytes = server;
+                backup_bytes = backup;
+            }
+
+            let now = Instant::now();
+            let cpu_percent = match (pid, &stat, previous) {
+                (Some(pid), Some(stat), Some((prev_pid, prev_ticks, prev_time))) if pid == prev_pid => {
+                    let elapsed = now.duration_since(prev_time).as_secs_f64();
+                    let used = stat.cpu_ticks.saturating_sub(prev_ticks) as f64 / ticks_per_sec;
+                    (elapsed > 0.0).then(|| used / elapsed * 100.0)
+                }
+                _ => None,
+            };
+            previous = match (pid, &stat) {
+                (Some(pid), Some(stat)) => Some((pid, stat.cpu_ticks, now)),
+                _ => None,
+            };
+
+            history.push(MetricsSample {
+                time: Utc::now(),
+                pid,
+                cpu_percent,
+                rss_bytes,
+                threads: stat.map(|s| s.threads),
+                open_files,
+                disk_bytes,
+                backup_bytes,
+            });
+        }
+    });
+}
@@ -1,17 +1,18 @@
 use serde::Deserialize;
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct ModEntry {
     pub file: String,
     // Add other fields if needed
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct Modlist {
     pub mods: Vec<ModEntry>,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Clone, Deserialize)]
 pub struct BccCommonToml {
     pub general: BccCommonGeneral,
@@ -0,0 +1,64 @@
+use std::path::Path;
+use rocket::tokio::fs;
+
+/// Number of `.jar` files directly inside `dir` (0 if it can't be read).
+pub async fn count_jars(dir: &str) -> usize {
+    let Ok(mut entries) = fs::read_dir(dir).await else { return 0 };
+    let mut count = 0;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if entry.path().is_file() && entry.file_name().to_string_lossy().ends_with(".jar") {
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Names of the files in the extra mods directory.
+pub async fn list_extra_mods(dir: &str) -> Result<Vec<String>, String> {
+    let mut entries = fs::read_dir(dir).await
+        .map_err(|e| format!("Failed to read mods directory '{}': {}", dir, e))?;
+    let mut mods = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if path.is_file() && let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+            mods.push(name.to_string());
+        }
+    }
+    Ok(mods)
+}
+
+/// Reduces an uploaded file name to its last component, so it can't escape
+/// the mods directory, and checks that it is a `.jar`.
+pub fn sanitize_mod_filename(filename: &str) -> Result<String, String> {
+    let sanitized = Path::new(filename)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_string())
+        .unwrap_or_default();
+    if sanitized.is_empty() {
+        return Err("Filename is empty or invalid.".to_string());
+    }
+    if !sanitized.ends_with(".jar") {
+        return Err("Invalid file type. Only .jar files are allowed.".to_string());
+    }
+    Ok(sanitized)
+}
+
+/// Copies a local jar into the extra mods directory, creating it if needed.
+/// Returns the name it was saved under.
+pub async fn install_extra_mod(dir: &str, src: &Path) -> Result<String, String> {
+    let name = sanitize_mod_filename(&src.to_string_lossy())?;
+    fs::create_dir_all(dir).await
+        .map_err(|e| format!("Failed to create mods directory '{}': {}", dir, e))?;
+    fs::copy(src, Path::new(dir).join(&name)).await
+        .map_err(|e| format!("Failed to copy {}: {}", src.display(), e))?;
+    Ok(name)
+}
+
+pub async fn remove_extra_mod(dir: &str, name: &str) -> Result<(), String> {
+    if Path::new(name).file_name().and_then(|n| n.to_str()) != Some(name) {
+        return Err(format!("Invalid mod name: {}", name));
+    }
+    fs::remove_file(Path::new(dir).join(name)).await
+        .map_err(|e| format!("Failed to delete mod: {}", e))
+}
@@ -0,0 +1,118 @@
+use std::path::Path;
+use std::sync::LazyLock;
+use regex::Regex;
+use rocket::tokio::fs;
+use crate::config::read_bcc_common;
+use crate::constants::{DEFAULT_MOTD_TEMPLATE, DEFAULT_MOTD_TEMPLATE_FILE};
+use crate::mods::count_jars;
+use crate::properties::{read_server_properties, write_server_properties};
+use crate::serverctl::server_backend;
+
+static AMPERSAND_CODES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"&(&|[0-9a-fk-orA-FK-OR])").unwrap());
+static SECTION_CODES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"§[0-9a-fk-orA-FK-OR]").unwrap());
+
+/// Values available to a MOTD template as `{name}` placeholders.
+#[derive(Debug, Clone, Default)]
+pub struct MotdContext {
+    pub pack_name: String,
+    pub pack_version: String,
+    pub extra_mods: usize,
+    pub last_restart: String,
+}
+
+impl MotdContext {
+    pub async fn gather(server_location: &str, extra_mods_dir: &str) -> Self {
+        let general = read_bcc_common(server_location).await.ok().map(|cfg| cfg.general);
+        let extra_mods = count_jars(extra_mods_dir).await;
+        MotdContext {
+            pack_name: general.as_ref().and_then(|g| g.modpack_name.clone()).unwrap_or_default(),
+            pack_version: general.and_then(|g| g.modpack_version).unwrap_or_default(),
+            extra_mods,
+            last_restart: server_backend().status().await.and_then(|s| s.active_since).unwrap_or_default(),
+        }
+    }
+}
+
+/// Turns the template's `&`-prefixed color/format codes into `§` codes, then
+/// substitutes `{pack_name}`, `{pack_version}`, `{extra_mods}` and
+/// `{last_restart}`. `&&` is a literal `&`; substituted values are never
+/// read as codes. Unknown placeholders are left as-is.
+pub fn render(template: &str, ctx: &MotdContext) -> String {
+    let coded = AMPERSAND_CODES.replace_all(template, |caps: &regex::Captures| match &caps[1] {
+        "&" => "&".to_string(),
+        code => format!("§{}", code),
+    });
+    coded
+        .replace("{pack_name}", &ctx.pack_name)
+        .replace("{pack_version}", &ctx.pack_version)
+        .replace("{extra_mods}", &ctx.extra_mods.to_string())
+        .replace("{last_restart}", &ctx.last_restart)
+}
+
+/// The rendered MOTD with all `§` codes removed, for previews.
+pub fn strip_codes(motd: &str) -> String {
+    SECTION_CODES.replace_all(motd, "").to_string()
+}
+
+pub fn template_path() -> String {
+    std::env::var("MOTD_TEMPLATE_FILE").unwrap_or_else(|_| DEFAULT_MOTD_TEMPLATE_FILE.to_string())
+}
+
+/// The saved template, or the built-in default if none has been saved.
+pub async fn load_template() -> String {
+    match fs::read_to_string(template_path()).await {
+        Ok(contents) if !contents.trim().is_empty() => contents.trim_end_matches(['\r', '\n']).to_string(),
+        _ => DEFAULT_MOTD_TEMPLATE.to_string(),
+    }
+}
+
+pub async fn save_template(template: &str) -> Result<(), String> {
+    if template.contains(['\r', '\n']) {
+        return Err("MOTD template must be a single line.".to_string());
+    }
+    let path = template_path();
+    if let Some(parent) = Path::new(&path).parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).await
+            .map_err(|e| format!("Could not create template directory: {}", e))?;
+    }
+    fs::write(&path, template).await
+        .map_err(|e| format!("Could not write MOTD template: {}", e))
+}
+
+/// Renders the saved template and writes it to `server.properties`.
+/// Returns the rendered MOTD.
+pub async fn apply_motd(server_location: &str, extra_mods_dir: &str) -> Result<String, String> {
+    let ctx = MotdContext::gather(server_location, extra_mods_dir).await;
+    let motd = render(&load_template().await, &ctx);
+    let mut props = read_server_properties(server_location).await?;
+    props.set("motd", &motd);
+    write_server_properties(server_location, &props).await?;
+    Ok(motd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx() -> MotdContext {
+        MotdContext {
+            pack_name: "Tom&Jerry &aPack".to_string(),
+            pack_version: "2.41".to_string(),
+            extra_mods: 3,
+            last_restart: "Mon 2026-10-19 04:00:00 UTC".to_string(),
+        }
+    }
+
+    #[test]
+    fn renders_placeholders_and_codes() {
+        let motd = render("&a&l{pack_name}&r v{pack_version} +{extra_mods} mods, up since {last_restart} {unknown}", &ctx());
+        assert_eq!(motd, "§a§lTom&Jerry &aPack§r v2.41 +3 mods, up since Mon 2026-10-19 04:00:00 UTC {unknown}");
+        assert_eq!(strip_codes(&motd), "Tom&Jerry &aPack v2.41 +3 mods, up since Mon 2026-10-19 04:00:00 UTC {unknown}");
+    }
+
+    #[test]
+    fn double_ampersand_is_literal() {
+        assert_eq!(render("R&&D && &&a&b", &MotdContext::default()), "R&D & &a§b");
+        assert_eq!(render("Fish & Chips", &MotdContext::default()), "Fish & Chips");
+    }
+}
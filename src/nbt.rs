@@ -0,0 +1,410 @@
+//! Minecraft's Named Binary Tag format, as used by `level.dat`. Values are
+//! kept in file order so a read-modify-write round trip only changes what
+//! was edited.
+
+use std::io::{Read, Write};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+/// Compounds and lists nested deeper than this are rejected rather than
+/// recursed into. Parsing recurses once per level, and 512 levels overflow a
+/// 2 MiB thread stack in debug builds; real files nest a dozen at most.
+const MAX_DEPTH: usize = 128;
+
+/// Gzipped NBT that inflates past this is rejected, so a small crafted file
+/// can't exhaust memory.
+const MAX_NBT_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    /// The element type is kept so empty lists are written back unchanged.
+    List(u8, Vec<Tag>),
+    Compound(Vec<(String, Tag)>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    fn id(&self) -> u8 {
+        match self {
+            Tag::Byte(_) => 1,
+            Tag::Short(_) => 2,
+            Tag::Int(_) => 3,
+            Tag::Long(_) => 4,
+            Tag::Float(_) => 5,
+            Tag::Double(_) => 6,
+            Tag::ByteArray(_) => 7,
+            Tag::String(_) => 8,
+            Tag::List(..) => 9,
+            Tag::Compound(_) => 10,
+            Tag::IntArray(_) => 11,
+            Tag::LongArray(_) => 12,
+        }
+    }
+
+    /// A child of a compound.
+    pub fn get(&self, name: &str) -> Option<&Tag> {
+        match self {
+            Tag::Compound(entries) => entries.iter().find(|(n, _)| n == name).map(|(_, tag)| tag),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Tag> {
+        match self {
+            Tag::Compound(entries) => entries.iter_mut().find(|(n, _)| n == name).map(|(_, tag)| tag),
+            _ => None,
+        }
+    }
+
+    /// Follows a `/`-separated path of compound names.
+    pub fn path(&self, path: &str) -> Option<&Tag> {
+        path.split('/').try_fold(self, |tag, name| tag.get(name))
+    }
+
+    /// Any integer tag, widened.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Tag::Byte(v) => Some(v.into()),
+            Tag::Short(v) => Some(v.into()),
+            Tag::Int(v) => Some(v.into()),
+            Tag::Long(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Tag::Float(v) => Some(v.into()),
+            Tag::Double(v) => Some(v),
+            _ => self.as_i64().map(|v| v as f64),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Tag::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Tag]> {
+        match self {
+            Tag::List(_, items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_compound(&self) -> Option<&[(String, Tag)]> {
+        match self {
+            Tag::Compound(entries) => Some(entries),
+            _ => None,
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(n).filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| format!("Unexpected end of NBT data at byte {}", self.pos))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    /// A length prefix, checked against the bytes left so a corrupt file
+    /// can't make us allocate gigabytes.
+    fn len(&mut self, element_size: usize) -> Result<usize, String> {
+        let len = i32::from_be_bytes(self.array()?);
+        let len = usize::try_from(len).map_err(|_| format!("Negative length {} at byte {}", len, self.pos))?;
+        if len.saturating_mul(element_size) > self.bytes.len() - self.pos {
+            return Err(format!("Length {} at byte {} runs past the end of the data", len, self.pos));
+        }
+        Ok(len)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = u16::from_be_bytes(self.array()?) as usize;
+        Ok(decode_mutf8(self.take(len)?))
+    }
+
+    fn payload(&mut self, id: u8, depth: usize) -> Result<Tag, String> {
+        if depth > MAX_DEPTH {
+            return Err("NBT data is nested too deeply".to_string());
+        }
+        Ok(match id {
+            1 => Tag::Byte(i8::from_be_bytes(self.array()?)),
+            2 => Tag::Short(i16::from_be_bytes(self.array()?)),
+            3 => Tag::Int(i32::from_be_bytes(self.array()?)),
+            4 => Tag::Long(i64::from_be_bytes(self.array()?)),
+            5 => Tag::Float(f32::from_be_bytes(self.array()?)),
+            6 => Tag::Double(f64::from_be_bytes(self.array()?)),
+            7 => {
+                let len = self.len(1)?;
+                Tag::ByteArray(self.take(len)?.iter().map(|b| *b as i8).collect())
+            }
+            8 => Tag::String(self.string()?),
+            9 => {
+                let element = self.array::<1>()?[0];
+                let len = self.len(if element == 0 { 0 } else { 1 })?;
+                let items = (0..len).map(|_| self.payload(element, depth + 1)).collect::<Result<_, _>>()?;
+                Tag::List(element, items)
+            }
+            10 => {
+                let mut entries = Vec::new();
+                loop {
+                    let id = self.array::<1>()?[0];
+                    if id == 0 {
+                        break;
+                    }
+                    let name = self.string()?;
+                    entries.push((name, self.payload(id, depth + 1)?));
+                }
+                Tag::Compound(entries)
+            }
+            11 => {
+                let len = self.len(4)?;
+                Tag::IntArray((0..len).map(|_| self.array().map(i32::from_be_bytes)).collect::<Result<_, _>>()?)
+            }
+            12 => {
+                let len = self.len(8)?;
+                Tag::LongArray((0..len).map(|_| self.array().map(i64::from_be_bytes)).collect::<Result<_, _>>()?)
+            }
+            other => return Err(format!("Unknown NBT tag type {} at byte {}", other, self.pos)),
+        })
+    }
+}
+
+/// Parses uncompressed NBT: one named root tag, usually a compound.
+pub fn from_bytes(bytes: &[u8]) -> Result<(String, Tag), String> {
+    let mut reader = Reader { bytes, pos: 0 };
+    let id = reader.array::<1>()?[0];
+    if id == 0 {
+        return Err("NBT data has no root tag".to_string());
+    }
+    let name = reader.string()?;
+    let tag = reader.payload(id, 0)?;
+    Ok((name, tag))
+}
+
+/// Parses gzipped NBT, as in `level.dat`.
+pub fn from_gzip(bytes: &[u8]) -> Result<(String, Tag), String> {
+    from_gzip_limited(bytes, MAX_NBT_BYTES)
+}
+
+fn from_gzip_limited(bytes: &[u8], limit: u64) -> Result<(String, Tag), String> {
+    let mut raw = Vec::new();
+    GzDecoder::new(bytes).take(limit + 1).read_to_end(&mut raw).map_err(|e| format!("Not gzipped NBT: {}", e))?;
+    if raw.len() as u64 > limit {
+        return Err(format!("NBT data is larger than {} bytes uncompressed", limit));
+    }
+    from_bytes(&raw)
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) -> Result<(), String> {
+    let bytes = encode_mutf8(s);
+    let len = u16::try_from(bytes.len()).map_err(|_| "NBT string is longer than 65535 bytes".to_string())?;
+    out.extend(len.to_be_bytes());
+    out.extend(bytes);
+    Ok(())
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) -> Result<(), String> {
+    let len = i32::try_from(len).map_err(|_| "NBT array is too long".to_string())?;
+    out.extend(len.to_be_bytes());
+    Ok(())
+}
+
+fn write_payload(out: &mut Vec<u8>, tag: &Tag) -> Result<(), String> {
+    match tag {
+        Tag::Byte(v) => out.extend(v.to_be_bytes()),
+        Tag::Short(v) => out.extend(v.to_be_bytes()),
+        Tag::Int(v) => out.extend(v.to_be_bytes()),
+        Tag::Long(v) => out.extend(v.to_be_bytes()),
+        Tag::Float(v) => out.extend(v.to_be_bytes()),
+        Tag::Double(v) => out.extend(v.to_be_bytes()),
+        Tag::ByteArray(values) => {
+            write_len(out, values.len())?;
+            out.extend(values.iter().map(|b| *b as u8));
+        }
+        Tag::String(s) => write_string(out, s)?,
+        Tag::List(element, items) => {
+            if let Some(item) = items.iter().find(|item| item.id() != *element) {
+                return Err(format!("NBT list of type {} contains a tag of type {}", element, item.id()));
+            }
+            out.push(*element);
+            write_len(out, items.len())?;
+            for item in items {
+                write_payload(out, item)?;
+            }
+        }
+        Tag::Compound(entries) => {
+            for (name, tag) in entries {
+                out.push(tag.id());
+                write_string(out, name)?;
+                write_payload(out, tag)?;
+            }
+            out.push(0);
+        }
+        Tag::IntArray(values) => {
+            write_len(out, values.len())?;
+            values.iter().for_each(|v| out.extend(v.to_be_bytes()));
+        }
+        Tag::LongArray(values) => {
+            write_len(out, values.len())?;
+            values.iter().for_each(|v| out.extend(v.to_be_bytes()));
+        }
+    }
+    Ok(())
+}
+
+pub fn to_bytes(name: &str, tag: &Tag) -> Result<Vec<u8>, String> {
+    let mut out = vec![tag.id()];
+    write_string(&mut out, name)?;
+    write_payload(&mut out, tag)?;
+    Ok(out)
+}
+
+pub fn to_gzip(name: &str, tag: &Tag) -> Result<Vec<u8>, String> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&to_bytes(name, tag)?).map_err(|e| e.to_string())?;
+    encoder.finish().map_err(|e| e.to_string())
+}
+
+/// Java's "modified UTF-8": NUL is two bytes and characters outside the BMP
+/// are encoded as surrogate pairs of three bytes each.
+fn decode_mutf8(bytes: &[u8]) -> String {
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i] as u16;
+        let (unit, width) = if b < 0x80 {
+            (b, 1)
+        } else if b & 0xE0 == 0xC0 && i + 1 < bytes.len() {
+            (((b & 0x1F) << 6) | (bytes[i + 1] as u16 & 0x3F), 2)
+        } else if b & 0xF0 == 0xE0 && i + 2 < bytes.len() {
+            (((b & 0x0F) << 12) | ((bytes[i + 1] as u16 & 0x3F) << 6) | (bytes[i + 2] as u16 & 0x3F), 3)
+        } else {
+            (0xFFFD, 1)
+        };
+        units.push(unit);
+        i += width;
+    }
+    String::from_utf16_lossy(&units)
+}
+
+fn encode_mutf8(s: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(s.len());
+    for unit in s.encode_utf16() {
+        match unit {
+            0x01..=0x7F => out.push(unit as u8),
+            0x00 | 0x80..=0x7FF => out.extend([0xC0 | (unit >> 6) as u8, 0x80 | (unit & 0x3F) as u8]),
+            _ => out.extend([0xE0 | (unit >> 12) as u8, 0x80 | ((unit >> 6) & 0x3F) as u8, 0x80 | (unit & 0x3F) as u8]),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A named root compound holding one of each tag type.
+    fn sample() -> Vec<u8> {
+        let mut b = vec![10, 0, 4];
+        b.extend(b"Data");
+        b.extend([1, 0, 1, b'b', 0xFF]);
+        b.extend([2, 0, 1, b's', 0x01, 0x02]);
+        b.extend([3, 0, 1, b'i', 0x80, 0, 0, 0]);
+        b.extend([4, 0, 1, b'l', 0, 0, 0, 0, 0, 0, 0x12, 0x34]);
+        b.extend([5, 0, 1, b'f', 0x3F, 0xC0, 0, 0]);
+        b.extend([6, 0, 1, b'd', 0x40, 0x09, 0x21, 0xFB, 0x54, 0x44, 0x2D, 0x18]);
+        b.extend([7, 0, 2, b'b', b'a', 0, 0, 0, 2, 0x01, 0xFE]);
+        b.extend([8, 0, 4, b'n', b'a', b'm', b'e', 0, 5, b'W', b'o', b'r', b'l', b'd']);
+        b.extend([9, 0, 4, b'l', b'i', b's', b't', 3, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2]);
+        b.extend([9, 0, 5, b'e', b'm', b'p', b't', b'y', 10, 0, 0, 0, 0]);
+        b.extend([10, 0, 3, b's', b'u', b'b', 1, 0, 1, b'z', 7, 0]);
+        b.extend([11, 0, 2, b'i', b'a', 0, 0, 0, 1, 0, 0, 0, 9]);
+        b.extend([12, 0, 2, b'l', b'a', 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 9]);
+        b.push(0);
+        b
+    }
+
+    #[test]
+    fn round_trips_byte_for_byte() {
+        let bytes = sample();
+        let (name, tag) = from_bytes(&bytes).unwrap();
+        assert_eq!(name, "Data");
+        assert_eq!(tag.get("b"), Some(&Tag::Byte(-1)));
+        assert_eq!(tag.get("i"), Some(&Tag::Int(i32::MIN)));
+        assert_eq!(tag.get("d"), Some(&Tag::Double(std::f64::consts::PI)));
+        assert_eq!(tag.path("sub/z").and_then(Tag::as_i64), Some(7));
+        assert_eq!(to_bytes(&name, &tag).unwrap(), bytes);
+        let gzipped = to_gzip(&name, &tag).unwrap();
+        assert_eq!(from_gzip(&gzipped).unwrap(), (name, tag));
+    }
+
+    #[test]
+    fn empty_lists_keep_their_element_type() {
+        let (_, tag) = from_bytes(&sample()).unwrap();
+        assert_eq!(tag.get("empty"), Some(&Tag::List(10, Vec::new())));
+        let bytes = to_bytes("", &Tag::Compound(vec![("e".to_string(), Tag::List(8, Vec::new()))])).unwrap();
+        assert_eq!(bytes, [10, 0, 0, 9, 0, 1, b'e', 8, 0, 0, 0, 0, 0]);
+        assert!(to_bytes("", &Tag::List(3, vec![Tag::Byte(1)])).is_err());
+    }
+
+    #[test]
+    fn strings_use_modified_utf8() {
+        let text = "a\0b\u{1F600}";
+        let encoded = [0x61, 0xC0, 0x80, 0x62, 0xED, 0xA0, 0xBD, 0xED, 0xB8, 0x80];
+        assert_eq!(encode_mutf8(text), encoded);
+        assert_eq!(decode_mutf8(&encoded), text);
+        let bytes = to_bytes(text, &Tag::String(text.to_string())).unwrap();
+        assert_eq!(from_bytes(&bytes).unwrap(), (text.to_string(), Tag::String(text.to_string())));
+    }
+
+    #[test]
+    fn rejects_malformed_data() {
+        let bytes = sample();
+        for len in 0..bytes.len() {
+            assert!(from_bytes(&bytes[..len]).is_err(), "accepted {} of {} bytes", len, bytes.len());
+        }
+        let negative = [7, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF];
+        assert!(from_bytes(&negative).unwrap_err().starts_with("Negative length -1"));
+        let oversized = [11, 0, 0, 0, 0, 0, 2, 0, 0, 0, 1];
+        assert!(from_bytes(&oversized).unwrap_err().contains("runs past the end"));
+
+        // Lists of lists, each holding one more, then an empty one.
+        let mut nested = vec![9, 0, 0];
+        for _ in 0..=MAX_DEPTH {
+            nested.extend([9, 0, 0, 0, 1]);
+        }
+        nested.extend([0, 0, 0, 0, 0]);
+        assert_eq!(from_bytes(&nested).unwrap_err(), "NBT data is nested too deeply");
+    }
+
+    #[test]
+    fn caps_decompressed_size() {
+        let gzipped = to_gzip("", &Tag::ByteArray(vec![0; 4096])).unwrap();
+        assert!(from_gzip_limited(&gzipped, 5000).is_ok());
+        assert_eq!(from_gzip_limited(&gzipped, 4096).unwrap_err(), "NBT data is larger than 4096 bytes uncompressed");
+    }
+}
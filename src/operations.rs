@@ -0,0 +1,344 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use rocket::tokio::fs;
+use serde::Serialize;
+use serde_json::{Value, json};
+use crate::constants::{FILES_TO_BACKUP, SERVER_PROPERTIES};
+use crate::crashguard::write_recent_extras;
+use crate::events::{Event, EventBus};
+use crate::jobs::JobHandle;
+use crate::motd::apply_motd;
+use crate::serverctl::server_backend;
+use crate::worlds::active_world;
+
+/// Number of files and total bytes under `path` (or of `path` itself).
+pub async fn tree_totals(path: &Path) -> (u64, u64) {
+    let (mut files, mut bytes) = (0, 0);
+    let mut stack = vec![path.to_path_buf()];
+    while let Some(path) = stack.pop() {
+        let Ok(meta) = fs::symlink_metadata(&path).await else { continue };
+        if meta.is_dir() {
+            if let Ok(mut entries) = fs::read_dir(&path).await {
+                while let Ok(Some(entry)) = entries.next_entry().await {
+                    stack.push(entry.path());
+                }
+            }
+        } else {
+            files += 1;
+            bytes += meta.len();
+        }
+    }
+    (files, bytes)
+}
+
+/// Copies a file or directory tree into `dst`, merging into existing
+/// directories and overwriting files, and reports each file to `job`.
+pub async fn copy_tree(src: &Path, dst: &Path, job: &JobHandle) -> Result<(), String> {
+    let mut stack: Vec<(PathBuf, PathBuf)> = vec![(src.to_path_buf(), dst.to_path_buf())];
+    while let Some((src, dst)) = stack.pop() {
+        let meta = fs::symlink_metadata(&src).await
+            .map_err(|e| format!("Failed to read {}: {}", src.display(), e))?;
+        if meta.is_dir() {
+            fs::create_dir_all(&dst).await
+                .map_err(|e| format!("Failed to create {}: {}", dst.display(), e))?;
+            let mut entries = fs::read_dir(&src).await
+                .map_err(|e| format!("Failed to read {}: {}", src.display(), e))?;
+            while let Some(entry) = entries.next_entry().await
+                .map_err(|e| format!("Failed to read {}: {}", src.display(), e))?
+            {
+                stack.push((entry.path(), dst.join(entry.file_name())));
+            }
+        } else {
+            let bytes = fs::copy(&src, &dst).await
+                .map_err(|e| format!("Failed to copy file from {} to {}: {}", src.display(), dst.display(), e))?;
+            job.advance(1, bytes);
+        }
+    }
+    Ok(())
+}
+
+/// Replaces `dst` with a copy of `src`, so nothing that was only in `dst`
+/// survives. The copy is made next to `dst` first and swapped in at the end;
+/// if it fails, `dst` is left as it was.
+pub async fn replace_tree(src: &Path, dst: &Path, job: &JobHandle) -> Result<(), String> {
+    let name = dst.file_name().and_then(|n| n.to_str()).ok_or_else(|| format!("Invalid path {}", dst.display()))?;
+    let staging = dst.with_file_name(format!(".{}.restoring", name));
+    remove_path(&staging).await?;
+    if let Err(e) = copy_tree(src, &staging, job).await {
+        let _ = remove_path(&staging).await;
+        return Err(e);
+    }
+    remove_path(dst).await?;
+    fs::rename(&staging, dst).await
+        .map_err(|e| format!("Failed to move {} into place: {}", dst.display(), e))
+}
+
+/// Removes a file or directory tree; a missing path is fine.
+pub async fn remove_path(path: &Path) -> Result<(), String> {
+    let result = match fs::symlink_metadata(path).await {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path).await,
+        Ok(_) => fs::remove_file(path).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(format!("Failed to remove {}: {}", path.display(), e)),
+        _ => Ok(()),
+    }
+}
+
+async fn list_jars(dir: &Path) -> Option<Vec<String>> {
+    let mut entries = fs::read_dir(dir).await.ok()?;
+    let mut names = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if path.is_file() && let Some(name) = path.file_name().and_then(|n| n.to_str()) && name.ends_with(".jar") {
+            names.push(name.to_string());
+        }
+    }
+    Some(names)
+}
+
+/// Writes the jars currently in `mods/` to `mods.list`, which Update Extras
+/// treats as the pack's own mods. Returns `None` if `mods/` can't be read.
+pub async fn write_mods_list(server_location: &str) -> Result<Option<usize>, String> {
+    let Some(mod_names) = list_jars(&Path::new(server_location).join("mods")).await else { return Ok(None) };
+    fs::write(Path::new(server_location).join("mods.list"), mod_names.join("\n")).await
+        .map_err(|e| format!("Failed to write mods.list: {}", e))?;
+    Ok(Some(mod_names.len()))
+}
+
+/// `FILES_TO_BACKUP` plus the world named by `level-name` in `dir`.
+async fn backup_items(dir: &str) -> Vec<String> {
+    let mut items: Vec<String> = FILES_TO_BACKUP.iter().map(|item| item.to_string()).collect();
+    items.push(active_world(dir).await);
+    items
+}
+
+fn finish(job: &JobHandle, status: &str, mut result: Value, warnings: Vec<String>) -> Value {
+    for warning in &warnings {
+        job.log(format!("Warning: {}", warning));
+    }
+    job.log(status);
+    result["status"] = json!(status);
+    result["warnings"] = json!(warnings);
+    result
+}
+
+/// Snapshots `FILES_TO_BACKUP` and the active world into `<server>_backup`, writing `mods.list`
+/// first so a restore knows which jars belong to the pack.
+pub async fn run_backup(job: JobHandle, bus: EventBus, server_location: String) -> Result<Value, String> {
+    let backup_dir = format!("{}_backup", server_location);
+    let mut warnings = Vec::new();
+
+    if let Err(e) = fs::remove_dir_all(&backup_dir).await && e.kind() != std::io::ErrorKind::NotFound {
+        return Err(format!("Failed to remove existing backup directory: {}", e));
+    }
+    fs::create_dir_all(&backup_dir).await
+        .map_err(|e| format!("Failed to create backup directory: {}", e))?;
+
+    match write_mods_list(&server_location).await? {
+        Some(count) => job.log(format!("Wrote mods.list with {} mods", count)),
+        None => warnings.push(format!("Could not read {}/mods; mods.list was not updated.", server_location)),
+    }
+
+    let mut items = Vec::new();
+    let (mut files, mut bytes) = (0, 0);
+    for item in backup_items(&server_location).await {
+        let src = Path::new(&server_location).join(&item);
+        if src.exists() {
+            let (f, b) = tree_totals(&src).await;
+            files += f;
+            bytes += b;
+            items.push(item);
+        } else {
+            warnings.push(format!("Skipped '{}': not present in the server directory.", item));
+        }
+    }
+    job.set_totals(files, bytes);
+
+    for item in items {
+        job.log(format!("Copying {}", item));
+        copy_tree(&Path::new(&server_location).join(&item), &Path::new(&backup_dir).join(&item), &job).await?;
+    }
+
+    bus.emit(Event::BackupComplete { backup_dir: backup_dir.clone() });
+    Ok(finish(&job, "Backup complete", json!({"backup_dir": backup_dir}), warnings))
+}
+
+/// Replaces each backed-up item in the server directory with its backup
+/// (files added since the backup are removed) and re-applies the MOTD,
+/// stopping the server first if `stop_first` is set.
+pub async fn run_restore(job: JobHandle, server_location: String, extra_mods_dir: String, stop_first: bool) -> Result<Value, String> {
+    let backup_dir = format!("{}_backup", server_location);
+    let mut warnings = Vec::new();
+
+    if stop_first {
+        job.log("Stopping server");
+        server_backend().stop().await.map_err(|e| format!("Failed to stop server: {}", e))?;
+    }
+
+    let mut items = Vec::new();
+    let (mut files, mut bytes) = (0, 0);
+    for item in backup_items(&backup_dir).await {
+        let src = Path::new(&backup_dir).join(&item);
+        if src.exists() {
+            let (f, b) = tree_totals(&src).await;
+            files += f;
+            bytes += b;
+            items.push(item);
+        } else {
+            warnings.push(format!("Skipped '{}': not present in the backup.", item));
+        }
+    }
+    job.set_totals(files, bytes);
+
+    for item in items {
+        job.log(format!("Restoring {}", item));
+        replace_tree(&Path::new(&backup_dir).join(&item), &Path::new(&server_location).join(&item), &job).await?;
+    }
+
+    // A fresh directory has no server.properties until the server first runs.
+    if Path::new(&server_location).join(SERVER_PROPERTIES).exists() {
+        apply_motd(&server_location, &extra_mods_dir).await
+            .map_err(|e| format!("Failed to apply MOTD: {}", e))?;
+    }
+
+    let start_script = Path::new(&server_location).join("startserver.sh");
+    let status = Command::new("chmod").arg("+x").arg(start_script).status();
+    if !status.is_ok_and(|s| s.success()) {
+        return Err("Failed to chmod startserver.sh.".to_string());
+    }
+
+    if !Path::new(&server_location).join("mods.list").exists()
+        && write_mods_list(&server_location).await?.is_none()
+    {
+        warnings.push(format!("Could not read {}/mods; mods.list was not written.", server_location));
+    }
+
+    Ok(finish(&job, "Restore complete", json!({}), warnings))
+}
+
+/// What Update Extras would change, worked out without touching anything.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtrasPlan {
+    /// Jars in `mods/` that aren't listed in `mods.list`.
+    pub remove: Vec<String>,
+    /// Every extra mod; these overwrite any copy already in `mods/`.
+    pub copy: Vec<String>,
+    /// Extra mods that aren't in `mods/` yet.
+    pub add: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+pub async fn plan_update_extras(server_location: &str, extra_mods_dir: &str) -> Result<ExtrasPlan, String> {
+    let mods_dir = Path::new(server_location).join("mods");
+    let mods_list_path = Path::new(server_location).join("mods.list");
+    let allowed_mods: Vec<String> = match fs::read_to_string(&mods_list_path).await {
+        Ok(contents) => contents.lines().map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+        Err(e) => return Err(format!("Failed to read mods.list: {}", e)),
+    };
+    let installed = list_jars(&mods_dir).await
+        .ok_or_else(|| format!("Failed to read mods directory: {}", mods_dir.display()))?;
+
+    let mut warnings = Vec::new();
+    let copy = match list_jars(Path::new(extra_mods_dir)).await {
+        Some(extras) => extras,
+        None => {
+            warnings.push(format!("Could not read extra mods directory '{}'; no extras will be copied.", extra_mods_dir));
+            Vec::new()
+        }
+    };
+    Ok(ExtrasPlan {
+        remove: installed.iter().filter(|name| !allowed_mods.contains(name)).cloned().collect(),
+        add: copy.iter().filter(|name| !installed.contains(name)).cloned().collect(),
+        copy,
+        warnings,
+    })
+}
+
+/// Stops the server, resets `mods/` to the jars in `mods.list`, copies in
+/// every extra mod and starts the server again.
+pub async fn run_update_extras(job: JobHandle, server_location: String, extra_mods_dir: String) -> Result<Value, String> {
+    let plan = plan_update_extras(&server_location, &extra_mods_dir).await?;
+    let mut warnings = plan.warnings;
+    job.log("Stopping server");
+    server_backend().stop().await.map_err(|e| format!("Failed to stop server: {}", e))?;
+
+    let mods_dir = Path::new(&server_location).join("mods");
+    for name in &plan.remove {
+        match fs::remove_file(mods_dir.join(name)).await {
+            Ok(()) => job.log(format!("Removed {}", name)),
+            Err(e) => warnings.push(format!("Failed to remove disallowed mod '{}': {}", name, e)),
+        }
+    }
+
+    let mut bytes = 0;
+    for name in &plan.copy {
+        bytes += tree_totals(&Path::new(&extra_mods_dir).join(name)).await.1;
+    }
+    job.set_totals(plan.copy.len() as u64, bytes);
+    let mut added_jars = Vec::new();
+    for name in plan.copy {
+        match fs::copy(Path::new(&extra_mods_dir).join(&name), mods_dir.join(&name)).await {
+            Ok(bytes) => {
+                job.advance(1, bytes);
+                if plan.add.contains(&name) {
+                    added_jars.push(name);
+                }
+            }
+            Err(e) => warnings.push(format!("Failed to copy extra mod '{}': {}", name, e)),
+        }
+    }
+    // The crash-loop guard blames these if the server stops booting.
+    if let Err(e) = write_recent_extras(&server_location, &added_jars).await {
+        warnings.push(e);
+    }
+
+    if let Err(e) = apply_motd(&server_location, &extra_mods_dir).await {
+        warnings.push(format!("Failed to apply MOTD: {}", e));
+    }
+
+    job.log("Starting server");
+    server_backend().start().await.map_err(|e| format!("Failed to start server: {}", e))?;
+
+    Ok(finish(&job, "Extras updated", json!({"added": added_jars}), warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::temp_dir;
+    use super::*;
+
+    #[tokio::test]
+    async fn replace_tree_drops_files_not_in_the_source() {
+        let root = temp_dir("restore");
+        let (backup, world) = (root.join("backup/world"), root.join("server/world"));
+        std::fs::create_dir_all(backup.join("region")).unwrap();
+        std::fs::write(backup.join("level.dat"), "old level").unwrap();
+        std::fs::write(backup.join("region/r.0.0.mca"), "old region").unwrap();
+        std::fs::create_dir_all(world.join("region")).unwrap();
+        std::fs::create_dir_all(world.join("playerdata")).unwrap();
+        std::fs::write(world.join("level.dat"), "new level").unwrap();
+        std::fs::write(world.join("region/r.0.0.mca"), "new region").unwrap();
+        std::fs::write(world.join("region/r.5.5.mca"), "explored later").unwrap();
+        std::fs::write(world.join("playerdata/steve.dat"), "joined later").unwrap();
+
+        let job = JobHandle::detached("restore");
+        replace_tree(&backup, &world, &job).await.unwrap();
+        assert_eq!(std::fs::read_to_string(world.join("level.dat")).unwrap(), "old level");
+        assert_eq!(std::fs::read_to_string(world.join("region/r.0.0.mca")).unwrap(), "old region");
+        assert!(!world.join("region/r.5.5.mca").exists());
+        assert!(!world.join("playerdata").exists());
+        assert!(!root.join("server/.world.restoring").exists());
+        assert_eq!(job.snapshot().progress.files_done, 2);
+
+        // A single file, and a missing source, which leaves the target alone.
+        std::fs::write(root.join("backup/ops.json"), "[]").unwrap();
+        std::fs::write(root.join("server/ops.json"), "[{}]").unwrap();
+        replace_tree(&root.join("backup/ops.json"), &root.join("server/ops.json"), &job).await.unwrap();
+        assert_eq!(std::fs::read_to_string(root.join("server/ops.json")).unwrap(), "[]");
+        assert!(replace_tree(&root.join("backup/missing"), &world, &job).await.is_err());
+        assert!(world.join("level.dat").exists());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
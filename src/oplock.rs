@@ -0,0 +1,55 @@
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// The operation currently holding the lock.
+#[derive(Debug, Clone, Serialize)]
+pub struct Operation {
+    pub kind: String,
+    pub since: DateTime<Utc>,
+    pub job_id: Option<u64>,
+}
+
+/// Lets only one operation that touches the server's files or unit state
+/// (start/stop, backup, restore, Update Extras, extra mod changes, the
+/// crash-loop guard) run at a time. Conflicting requests are rejected, not
+/// queued.
+#[derive(Default)]
+pub struct OperationLock {
+    current: Mutex<Option<Operation>>,
+}
+
+impl OperationLock {
+    /// Takes the lock for `kind`, or returns the operation holding it.
+    pub fn try_acquire(self: &Arc<Self>, kind: &str) -> Result<OperationGuard, Operation> {
+        let mut current = self.current.lock().unwrap();
+        if let Some(op) = current.as_ref() {
+            return Err(op.clone());
+        }
+        *current = Some(Operation { kind: kind.to_string(), since: Utc::now(), job_id: None });
+        Ok(OperationGuard { lock: self.clone() })
+    }
+
+    pub fn current(&self) -> Option<Operation> {
+        self.current.lock().unwrap().clone()
+    }
+}
+
+/// Releases the lock when dropped.
+pub struct OperationGuard {
+    lock: Arc<OperationLock>,
+}
+
+impl OperationGuard {
+    pub fn set_job(&self, id: u64) {
+        if let Some(op) = self.lock.current.lock().unwrap().as_mut() {
+            op.job_id = Some(id);
+        }
+    }
+}
+
+impl Drop for OperationGuard {
+    fn drop(&mut self) {
+        *self.lock.current.lock().unwrap() = None;
+    }
+}
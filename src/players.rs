@@ -0,0 +1,403 @@
+use std::path::Path;
+use chrono::Utc;
+use reqwest::Client;
+use rocket::tokio::fs;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use crate::constants::{BANNED_IPS_FILE, BANNED_PLAYERS_FILE, DEFAULT_MOJANG_API_URL, OPS_FILE, WHITELIST_FILE};
+use crate::properties::read_server_properties;
+use crate::serverctl::server_backend;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpEntry {
+    pub uuid: String,
+    pub name: String,
+    pub level: u8,
+    #[serde(rename = "bypassesPlayerLimit")]
+    pub bypasses_player_limit: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WhitelistEntry {
+    pub uuid: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BannedPlayer {
+    pub uuid: String,
+    pub name: String,
+    pub created: String,
+    pub source: String,
+    pub expires: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BannedIp {
+    pub ip: String,
+    pub created: String,
+    pub source: String,
+    pub expires: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerProfile {
+    pub uuid: String,
+    pub name: String,
+}
+
+/// Resolves usernames to UUIDs. Implementations other than Mojang's API can
+/// be passed to [`apply_player_change_with`], e.g. to stub lookups in tests.
+#[rocket::async_trait]
+pub trait UuidLookup: Send + Sync {
+    async fn lookup(&self, name: &str) -> Result<PlayerProfile, String>;
+}
+
+/// Looks profiles up with Mojang's API, or `MOJANG_API_URL` when set.
+pub struct MojangLookup {
+    client: Client,
+    api_url: String,
+}
+
+impl MojangLookup {
+    pub fn new(client: Client) -> Self {
+        let api_url = std::env::var("MOJANG_API_URL").unwrap_or_else(|_| DEFAULT_MOJANG_API_URL.to_string());
+        MojangLookup { client, api_url }
+    }
+}
+
+#[derive(Deserialize)]
+struct MojangProfile {
+    id: String,
+    name: String,
+}
+
+#[rocket::async_trait]
+impl UuidLookup for MojangLookup {
+    async fn lookup(&self, name: &str) -> Result<PlayerProfile, String> {
+        let url = format!("{}/users/profiles/minecraft/{}", self.api_url.trim_end_matches('/'), name);
+        let resp = self.client.get(&url)
+            .header("User-Agent", "mc-manager/1.0 (https://github.com/xela/mc-manager)")
+            .send().await.map_err(|_| "Failed to reach the Mojang API".to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("Unknown player '{}'", name));
+        }
+        let profile: MojangProfile = resp.json().await
+            .map_err(|_| "Failed to parse Mojang API response".to_string())?;
+        Ok(PlayerProfile { uuid: hyphenate(&profile.id), name: profile.name })
+    }
+}
+
+/// Derives the same UUIDs an offline-mode server would and never touches the
+/// network.
+pub struct OfflineLookup;
+
+#[rocket::async_trait]
+impl UuidLookup for OfflineLookup {
+    async fn lookup(&self, name: &str) -> Result<PlayerProfile, String> {
+        Ok(PlayerProfile { uuid: offline_uuid(name), name: name.to_string() })
+    }
+}
+
+/// Offline lookups when `UUID_LOOKUP=offline` or the server runs with
+/// `online-mode=false`, Mojang's API otherwise.
+pub async fn lookup_for_server(server_location: &str) -> Box<dyn UuidLookup> {
+    if std::env::var("UUID_LOOKUP").is_ok_and(|v| v == "offline") {
+        return Box::new(OfflineLookup);
+    }
+    match read_server_properties(server_location).await {
+        Ok(props) if props.get("online-mode").map(str::trim) == Some("false") => Box::new(OfflineLookup),
+        _ => Box::new(MojangLookup::new(Client::new())),
+    }
+}
+
+fn hyphenate(id: &str) -> String {
+    if id.len() != 32 {
+        return id.to_string();
+    }
+    format!("{}-{}-{}-{}-{}", &id[0..8], &id[8..12], &id[12..16], &id[16..20], &id[20..32])
+}
+
+/// Java's `UUID.nameUUIDFromBytes("OfflinePlayer:" + name)`.
+pub fn offline_uuid(name: &str) -> String {
+    let mut bytes = md5::compute(format!("OfflinePlayer:{}", name)).0;
+    bytes[6] = (bytes[6] & 0x0f) | 0x30;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    hyphenate(&hex)
+}
+
+async fn read_list<T: DeserializeOwned>(server_location: &str, file: &str) -> Result<Vec<T>, String> {
+    let path = Path::new(server_location).join(file);
+    match fs::read_to_string(&path).await {
+        Ok(contents) if contents.trim().is_empty() => Ok(Vec::new()),
+        Ok(contents) => serde_json::from_str(&contents).map_err(|e| format!("Could not parse {}: {}", file, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("Could not read {}: {}", file, e)),
+    }
+}
+
+async fn write_list<T: Serialize>(server_location: &str, file: &str, entries: &[T]) -> Result<(), String> {
+    let path = Path::new(server_location).join(file);
+    let contents = serde_json::to_string_pretty(entries)
+        .map_err(|e| format!("Could not serialize {}: {}", file, e))?;
+    fs::write(&path, contents).await
+        .map_err(|e| format!("Could not write {}: {}", file, e))
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlayerLists {
+    pub ops: Vec<OpEntry>,
+    pub whitelist: Vec<WhitelistEntry>,
+    pub banned_players: Vec<BannedPlayer>,
+    pub banned_ips: Vec<BannedIp>,
+}
+
+pub async fn read_player_lists(server_location: &str) -> Result<PlayerLists, String> {
+    Ok(PlayerLists {
+        ops: read_list(server_location, OPS_FILE).await?,
+        whitelist: read_list(server_location, WHITELIST_FILE).await?,
+        banned_players: read_list(server_location, BANNED_PLAYERS_FILE).await?,
+        banned_ips: read_list(server_location, BANNED_IPS_FILE).await?,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub enum PlayerChange {
+    Op { name: String, level: u8 },
+    Deop { name: String },
+    WhitelistAdd { name: String },
+    WhitelistRemove { name: String },
+    Ban { name: String, reason: Option<String> },
+    Pardon { name: String },
+    BanIp { ip: String, reason: Option<String> },
+    PardonIp { ip: String },
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 16 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl PlayerChange {
+    pub fn validate(&self) -> Result<(), String> {
+        if let PlayerChange::Ban { reason: Some(reason), .. } | PlayerChange::BanIp { reason: Some(reason), .. } = self
+            && reason.chars().any(|c| c.is_control())
+        {
+            return Err("Ban reason must be a single line".to_string());
+        }
+        match self {
+            PlayerChange::Op { name, level } => {
+                if !(1..=4).contains(level) {
+                    return Err("Op level must be between 1 and 4".to_string());
+                }
+                if valid_name(name) { Ok(()) } else { Err(format!("Invalid player name '{}'", name)) }
+            }
+            PlayerChange::Deop { name }
+            | PlayerChange::WhitelistAdd { name }
+            | PlayerChange::WhitelistRemove { name }
+            | PlayerChange::Ban { name, .. }
+            | PlayerChange::Pardon { name } => {
+                if valid_name(name) { Ok(()) } else { Err(format!("Invalid player name '{}'", name)) }
+            }
+            PlayerChange::BanIp { ip, .. } | PlayerChange::PardonIp { ip } => {
+                ip.parse::<std::net::IpAddr>().map(|_| ()).map_err(|_| format!("Invalid IP address '{}'", ip))
+            }
+        }
+    }
+
+    /// The console command that makes this change on a running server.
+    fn command(&self) -> String {
+        match self {
+            // `op` always grants the server's default op level; the level is
+            // only honoured when editing ops.json directly.
+            PlayerChange::Op { name, .. } => format!("op {}", name),
+            PlayerChange::Deop { name } => format!("deop {}", name),
+            PlayerChange::WhitelistAdd { name } => format!("whitelist add {}", name),
+            PlayerChange::WhitelistRemove { name } => format!("whitelist remove {}", name),
+            PlayerChange::Ban { name, reason } => format!("ban {} {}", name, reason.as_deref().unwrap_or("")).trim_end().to_string(),
+            PlayerChange::Pardon { name } => format!("pardon {}", name),
+            PlayerChange::BanIp { ip, reason } => format!("ban-ip {} {}", ip, reason.as_deref().unwrap_or("")).trim_end().to_string(),
+            PlayerChange::PardonIp { ip } => format!("pardon-ip {}", ip),
+        }
+    }
+
+    async fn apply_to_files(&self, server_location: &str, lookup: &dyn UuidLookup) -> Result<(), String> {
+        let created = Utc::now().format("%Y-%m-%d %H:%M:%S %z").to_string();
+        match self {
+            PlayerChange::Op { name, level } => {
+                let profile = lookup.lookup(name).await?;
+                let mut ops: Vec<OpEntry> = read_list(server_location, OPS_FILE).await?;
+                ops.retain(|op| op.uuid != profile.uuid);
+                ops.push(OpEntry { uuid: profile.uuid, name: profile.name, level: *level, bypasses_player_limit: false });
+                write_list(server_location, OPS_FILE, &ops).await
+            }
+            PlayerChange::Deop { name } => {
+                let mut ops: Vec<OpEntry> = read_list(server_location, OPS_FILE).await?;
+                ops.retain(|op| !op.name.eq_ignore_ascii_case(name));
+                write_list(server_location, OPS_FILE, &ops).await
+            }
+            PlayerChange::WhitelistAdd { name } => {
+                let profile = lookup.lookup(name).await?;
+                let mut list: Vec<WhitelistEntry> = read_list(server_location, WHITELIST_FILE).await?;
+                list.retain(|entry| entry.uuid != profile.uuid);
+                list.push(WhitelistEntry { uuid: profile.uuid, name: profile.name });
+                write_list(server_location, WHITELIST_FILE, &list).await
+            }
+            PlayerChange::WhitelistRemove { name } => {
+                let mut list: Vec<WhitelistEntry> = read_list(server_location, WHITELIST_FILE).await?;
+                list.retain(|entry| !entry.name.eq_ignore_ascii_case(name));
+                write_list(server_location, WHITELIST_FILE, &list).await
+            }
+            PlayerChange::Ban { name, reason } => {
+                let profile = lookup.lookup(name).await?;
+                let mut bans: Vec<BannedPlayer> = read_list(server_location, BANNED_PLAYERS_FILE).await?;
+                bans.retain(|ban| ban.uuid != profile.uuid);
+                bans.push(BannedPlayer {
+                    uuid: profile.uuid,
+                    name: profile.name,
+                    created,
+                    source: "mc-manager".to_string(),
+                    expires: "forever".to_string(),
+                    reason: reason.clone().unwrap_or_else(|| "Banned by an operator.".to_string()),
+                });
+                write_list(server_location, BANNED_PLAYERS_FILE, &bans).await
+            }
+            PlayerChange::Pardon { name } => {
+                let mut bans: Vec<BannedPlayer> = read_list(server_location, BANNED_PLAYERS_FILE).await?;
+                bans.retain(|ban| !ban.name.eq_ignore_ascii_case(name));
+                write_list(server_location, BANNED_PLAYERS_FILE, &bans).await
+            }
+            PlayerChange::BanIp { ip, reason } => {
+                let mut bans: Vec<BannedIp> = read_list(server_location, BANNED_IPS_FILE).await?;
+                bans.retain(|ban| &ban.ip != ip);
+                bans.push(BannedIp {
+                    ip: ip.clone(),
+                    created,
+                    source: "mc-manager".to_string(),
+                    expires: "forever".to_string(),
+                    reason: reason.clone().unwrap_or_else(|| "Banned by an operator.".to_string()),
+                });
+                write_list(server_location, BANNED_IPS_FILE, &bans).await
+            }
+            PlayerChange::PardonIp { ip } => {
+                let mut bans: Vec<BannedIp> = read_list(server_location, BANNED_IPS_FILE).await?;
+                bans.retain(|ban| &ban.ip != ip);
+                write_list(server_location, BANNED_IPS_FILE, &bans).await
+            }
+        }
+    }
+}
+
+/// Applies `change` through the server console when the server is running,
+/// or by editing the JSON files when it is stopped (a running server would
+/// overwrite them). Returns a short description of what was done.
+pub async fn apply_player_change(server_location: &str, change: PlayerChange) -> Result<String, String> {
+    let lookup = lookup_for_server(server_location).await;
+    apply_player_change_with(server_location, change, lookup.as_ref()).await
+}
+
+/// [`apply_player_change`] with a caller-supplied UUID lookup.
+pub async fn apply_player_change_with(server_location: &str, change: PlayerChange, lookup: &dyn UuidLookup) -> Result<String, String> {
+    change.validate()?;
+    if server_backend().is_active().await {
+        let reply = server_backend().send_command(&change.command()).await
+            .map_err(|e| format!("Server is running but the console is unavailable: {}", e))?;
+        Ok(if reply.is_empty() { "Command sent to the server console.".to_string() } else { reply })
+    } else {
+        change.apply_to_files(server_location, lookup).await?;
+        Ok("Player files updated.".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::{http_stand_in, temp_dir};
+    use super::*;
+
+    fn temp_server(name: &str) -> String {
+        temp_dir(&format!("players-{}", name)).to_string_lossy().to_string()
+    }
+
+    /// Always resolves to a fixed, recognisable UUID.
+    struct StubLookup;
+
+    #[rocket::async_trait]
+    impl UuidLookup for StubLookup {
+        async fn lookup(&self, name: &str) -> Result<PlayerProfile, String> {
+            Ok(PlayerProfile { uuid: format!("uuid-of-{}", name.to_lowercase()), name: name.to_string() })
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_lists_in_server_format() {
+        let dir = temp_server("read");
+        std::fs::write(Path::new(&dir).join(OPS_FILE), r#"[
+  {"uuid": "069a79f4-44e9-4726-a5be-fca90e38aaf5", "name": "Notch", "level": 4, "bypassesPlayerLimit": false}
+]"#).unwrap();
+        std::fs::write(Path::new(&dir).join(BANNED_IPS_FILE), r#"[
+  {"ip": "10.0.0.1", "created": "2024-01-01 00:00:00 +0000", "source": "Server", "expires": "forever", "reason": "Banned by an operator."}
+]"#).unwrap();
+        std::fs::write(Path::new(&dir).join(WHITELIST_FILE), "  \n").unwrap();
+
+        let lists = read_player_lists(&dir).await.unwrap();
+        assert_eq!(lists.ops.len(), 1);
+        assert_eq!(lists.ops[0].name, "Notch");
+        assert_eq!(lists.ops[0].level, 4);
+        assert!(lists.whitelist.is_empty());
+        assert!(lists.banned_players.is_empty());
+        assert_eq!(lists.banned_ips[0].ip, "10.0.0.1");
+
+        std::fs::write(Path::new(&dir).join(OPS_FILE), "{not json").unwrap();
+        assert!(read_player_lists(&dir).await.unwrap_err().contains("Could not parse ops.json"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_changes_use_the_lookup_and_replace_entries() {
+        let dir = temp_server("write");
+        let op = PlayerChange::Op { name: "Alex".to_string(), level: 2 };
+        op.apply_to_files(&dir, &StubLookup).await.unwrap();
+        op.apply_to_files(&dir, &StubLookup).await.unwrap();
+        PlayerChange::Ban { name: "Steve".to_string(), reason: None }.apply_to_files(&dir, &StubLookup).await.unwrap();
+
+        let lists = read_player_lists(&dir).await.unwrap();
+        assert_eq!(lists.ops.len(), 1);
+        assert_eq!(lists.ops[0].uuid, "uuid-of-alex");
+        assert_eq!(lists.ops[0].level, 2);
+        assert_eq!(lists.banned_players[0].uuid, "uuid-of-steve");
+
+        PlayerChange::Deop { name: "alex".to_string() }.apply_to_files(&dir, &StubLookup).await.unwrap();
+        PlayerChange::Pardon { name: "STEVE".to_string() }.apply_to_files(&dir, &StubLookup).await.unwrap();
+        let lists = read_player_lists(&dir).await.unwrap();
+        assert!(lists.ops.is_empty());
+        assert!(lists.banned_players.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn offline_lookup_matches_the_server() {
+        let profile = OfflineLookup.lookup("Notch").await.unwrap();
+        assert_eq!(profile.uuid, "b50ad385-829d-3141-a216-7e7d7539ba7f");
+        assert_eq!(profile.name, "Notch");
+    }
+
+    /// Answers Mojang's profile endpoint for "notch" and 404s everything else.
+    fn fake_mojang() -> String {
+        let (url, _) = http_stand_in(|req| match req.path.as_str() {
+            "/users/profiles/minecraft/notch" => (200, br#"{"id":"069a79f944e94726a5befca90e38aaf5","name":"Notch"}"#.to_vec()),
+            _ => (404, Vec::new()),
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn mojang_lookup_hyphenates_and_reports_unknown_players() {
+        let lookup = MojangLookup { client: Client::new(), api_url: fake_mojang() };
+        let profile = lookup.lookup("notch").await.unwrap();
+        assert_eq!(profile.uuid, "069a79f9-44e9-4726-a5be-fca90e38aaf5");
+        assert_eq!(profile.name, "Notch");
+        assert_eq!(lookup.lookup("nobody").await.unwrap_err(), "Unknown player 'nobody'");
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Request, Response};
+
+/// (method, route, status) -> responses served.
+pub type RequestCounts = BTreeMap<(String, String, u16), u64>;
+
+/// Counts responses per (method, route, status) for the `/metrics` endpoint.
+#[derive(Clone, Default)]
+pub struct RequestCounter {
+    counts: Arc<Mutex<RequestCounts>>,
+}
+
+impl RequestCounter {
+    pub fn snapshot(&self) -> RequestCounts {
+        self.counts.lock().unwrap().clone()
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RequestCounter {
+    fn info(&self) -> Info {
+        Info { name: "Request counter", kind: Kind::Response }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let route = req.route()
+            .map(|r| r.uri.to_string())
+            .unwrap_or_else(|| "unmatched".to_string());
+        let key = (req.method().to_string(), route, res.status().code);
+        *self.counts.lock().unwrap().entry(key).or_insert(0) += 1;
+    }
+}
+
+/// Builds a Prometheus text exposition document one metric family at a time.
+#[derive(Default)]
+pub struct Exposition {
+    out: String,
+}
+
+impl Exposition {
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) -> &mut Self {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+        self
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) -> &mut Self {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            let rendered: Vec<String> = labels.iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+                .collect();
+            let _ = write!(self.out, "{{{}}}", rendered.join(","));
+        }
+        let _ = writeln!(self.out, " {}", value);
+        self
+    }
+
+    /// A family with a single unlabelled sample, skipped when there is no value.
+    pub fn gauge(&mut self, name: &str, help: &str, value: Option<f64>) -> &mut Self {
+        if let Some(value) = value {
+            self.family(name, "gauge", help).sample(name, &[], value);
+        }
+        self
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
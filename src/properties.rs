@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::path::Path;
+use rocket::tokio::fs;
+use serde::Serialize;
+use crate::constants::SERVER_PROPERTIES;
+
+/// A logical line of `server.properties`. Entries keep the text they were
+/// read from so comments, ordering and formatting survive a round trip; only
+/// entries that were changed are re-escaped.
+#[derive(Debug, Clone)]
+enum Line {
+    Raw(String),
+    Entry { key: String, value: String, raw: Option<String> },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ServerProperties {
+    lines: Vec<Line>,
+}
+
+impl ServerProperties {
+    /// Parses the file the way `java.util.Properties.load` does: `=`, `:` or
+    /// whitespace separate key and value, a trailing backslash continues the
+    /// line, and `\uXXXX` and the other backslash escapes are decoded.
+    pub fn parse(contents: &str) -> Self {
+        let mut lines = Vec::new();
+        let mut physical = contents.lines();
+        while let Some(line) = physical.next() {
+            let trimmed = line.trim_start_matches(is_properties_whitespace);
+            if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('!') {
+                lines.push(Line::Raw(line.to_string()));
+                continue;
+            }
+            let mut raw = line.to_string();
+            let mut logical = trimmed.to_string();
+            while ends_with_continuation(&logical) {
+                logical.pop();
+                let Some(next) = physical.next() else { break };
+                raw.push('\n');
+                raw.push_str(next);
+                logical.push_str(next.trim_start_matches(is_properties_whitespace));
+            }
+            let (key, value) = split_entry(&logical);
+            lines.push(Line::Entry { key: unescape(key), value: unescape(value), raw: Some(raw) });
+        }
+        ServerProperties { lines }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines.iter().find_map(|line| match line {
+            Line::Entry { key: k, value, .. } if k == key => Some(value.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Updates `key` in place, or appends it if it is not present yet.
+    pub fn set(&mut self, key: &str, value: &str) {
+        for line in self.lines.iter_mut() {
+            if let Line::Entry { key: k, value: v, raw } = line && k == key {
+                if v != value {
+                    *v = value.to_string();
+                    *raw = None;
+                }
+                return;
+            }
+        }
+        self.lines.push(Line::Entry { key: key.to_string(), value: value.to_string(), raw: None });
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Entry { key, value, .. } => Some((key.as_str(), value.as_str())),
+            Line::Raw(_) => None,
+        })
+    }
+
+    /// Writes the file as Java's `Properties.store` would: pure ASCII, with
+    /// everything else as `\uXXXX`, since the server reads it as ISO-8859-1.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for line in &self.lines {
+            match line {
+                Line::Raw(raw) | Line::Entry { raw: Some(raw), .. } => out.push_str(raw),
+                Line::Entry { key, value, raw: None } => {
+                    out.push_str(&escape(key, true));
+                    out.push('=');
+                    out.push_str(&escape(value, false));
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+fn is_properties_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\x0c')
+}
+
+/// An odd number of trailing backslashes means the last one escapes the newline.
+fn ends_with_continuation(line: &str) -> bool {
+    line.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1
+}
+
+/// Splits at the first unescaped `=`, `:` or whitespace. Whitespace around the
+/// separator is dropped, as is one `=` or `:` after a whitespace separator.
+fn split_entry(line: &str) -> (&str, &str) {
+    let mut escaped = false;
+    let mut key_end = line.len();
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '=' || c == ':' || is_properties_whitespace(c) {
+            key_end = i;
+            break;
+        }
+    }
+    let key = &line[..key_end];
+    let mut rest = line[key_end..].trim_start_matches(is_properties_whitespace);
+    if let Some(stripped) = rest.strip_prefix(['=', ':']) {
+        rest = stripped.trim_start_matches(is_properties_whitespace);
+    }
+    (key, rest)
+}
+
+fn unescape(text: &str) -> String {
+    let mut units: Vec<u16> = Vec::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let c = if c != '\\' {
+            c
+        } else {
+            match chars.next() {
+                Some('t') => '\t',
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('f') => '\x0c',
+                Some('u') => {
+                    let hex: String = chars.clone().take(4).collect();
+                    match u16::from_str_radix(&hex, 16) {
+                        Ok(unit) if hex.len() == 4 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+                            chars.nth(3);
+                            units.push(unit);
+                            continue;
+                        }
+                        _ => 'u',
+                    }
+                }
+                Some(other) => other,
+                None => break,
+            }
+        };
+        let mut buf = [0u16; 2];
+        units.extend_from_slice(c.encode_utf16(&mut buf));
+    }
+    // Escaped surrogate pairs are joined back into one character here.
+    String::from_utf16_lossy(&units)
+}
+
+/// Escapes a key or value like `Properties.store`. Keys escape every space,
+/// values only a leading one.
+fn escape(text: &str, is_key: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    for (i, c) in text.chars().enumerate() {
+        match c {
+            ' ' if is_key || i == 0 => out.push_str("\\ "),
+            '\\' | '=' | ':' | '#' | '!' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\x0c' => out.push_str("\\f"),
+            ' '..='~' => out.push(c),
+            _ => {
+                let mut buf = [0u16; 2];
+                for unit in c.encode_utf16(&mut buf) {
+                    out.push_str(&format!("\\u{:04X}", unit));
+                }
+            }
+        }
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PropertyKind {
+    Bool,
+    Int { min: i64, max: i64 },
+    Enum { values: &'static [&'static str] },
+    String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct PropertySchema {
+    pub key: &'static str,
+    #[serde(flatten)]
+    pub kind: PropertyKind,
+    /// False for settings that can also be applied to a running server with a
+    /// console command (`/difficulty`, `/whitelist on`, ...).
+    pub restart_required: bool,
+}
+
+const fn prop(key: &'static str, kind: PropertyKind, restart_required: bool) -> PropertySchema {
+    PropertySchema { key, kind, restart_required }
+}
+
+const PORT: PropertyKind = PropertyKind::Int { min: 1, max: 65535 };
+const DISTANCE: PropertyKind = PropertyKind::Int { min: 3, max: 32 };
+
+pub const KNOWN_PROPERTIES: &[PropertySchema] = &[
+    prop("motd", PropertyKind::String, true),
+    prop("server-port", PORT, true),
+    prop("query.port", PORT, true),
+    prop("rcon.port", PORT, true),
+    prop("max-players", PropertyKind::Int { min: 1, max: 10000 }, true),
+    prop("view-distance", DISTANCE, true),
+    prop("simulation-distance", DISTANCE, true),
+    prop("spawn-protection", PropertyKind::Int { min: 0, max: 1000 }, true),
+    prop("difficulty", PropertyKind::Enum { values: &["peaceful", "easy", "normal", "hard"] }, false),
+    prop("gamemode", PropertyKind::Enum { values: &["survival", "creative", "adventure", "spectator"] }, true),
+    prop("level-name", PropertyKind::String, true),
+    prop("level-seed", PropertyKind::String, true),
+    prop("online-mode", PropertyKind::Bool, true),
+    prop("pvp", PropertyKind::Bool, true),
+    prop("hardcore", PropertyKind::Bool, true),
+    prop("allow-flight", PropertyKind::Bool, true),
+    prop("allow-nether", PropertyKind::Bool, true),
+    prop("spawn-monsters", PropertyKind::Bool, true),
+    prop("enable-command-block", PropertyKind::Bool, true),
+    prop("enable-query", PropertyKind::Bool, true),
+    prop("enable-rcon", PropertyKind::Bool, true),
+    prop("force-gamemode", PropertyKind::Bool, true),
+    prop("white-list", PropertyKind::Bool, false),
+    prop("enforce-whitelist", PropertyKind::Bool, false),
+];
+
+pub fn schema_for(key: &str) -> Option<&'static PropertySchema> {
+    KNOWN_PROPERTIES.iter().find(|p| p.key == key)
+}
+
+/// Checks `value` against the schema for `key`. Unknown keys are accepted as
+/// free-form strings.
+pub fn validate(key: &str, value: &str) -> Result<(), String> {
+    if key.is_empty() || key.contains(['=', '\n', '\r']) {
+        return Err(format!("Invalid property name '{}'", key));
+    }
+    if value.contains(['\n', '\r']) {
+        return Err(format!("Value for '{}' must be a single line", key));
+    }
+    let Some(schema) = schema_for(key) else { return Ok(()) };
+    match schema.kind {
+        PropertyKind::Bool => match value {
+            "true" | "false" => Ok(()),
+            _ => Err(format!("'{}' must be true or false", key)),
+        },
+        PropertyKind::Int { min, max } => match value.parse::<i64>() {
+            Ok(n) if (min..=max).contains(&n) => Ok(()),
+            _ => Err(format!("'{}' must be an integer between {} and {}", key, min, max)),
+        },
+        PropertyKind::Enum { values } => {
+            if values.contains(&value) {
+                Ok(())
+            } else {
+                Err(format!("'{}' must be one of: {}", key, values.join(", ")))
+            }
+        }
+        PropertyKind::String => Ok(()),
+    }
+}
+
+/// Validates every change before touching `props`, so a bad value leaves the
+/// file untouched. Returns the keys that need a server restart to take effect.
+pub fn apply_changes(props: &mut ServerProperties, changes: &HashMap<String, String>) -> Result<Vec<String>, Vec<String>> {
+    let errors: Vec<String> = changes.iter()
+        .filter_map(|(key, value)| validate(key, value).err())
+        .collect();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let mut restart_required = Vec::new();
+    for (key, value) in changes {
+        if props.get(key) == Some(value.as_str()) {
+            continue;
+        }
+        props.set(key, value);
+        if schema_for(key).is_none_or(|s| s.restart_required) {
+            restart_required.push(key.clone());
+        }
+    }
+    restart_required.sort();
+    Ok(restart_required)
+}
+
+/// Reads the file as UTF-8, falling back to ISO-8859-1 for files written by
+/// older tools.
+pub async fn read_server_properties(server_location: &str) -> Result<ServerProperties, String> {
+    let path = Path::new(server_location).join(SERVER_PROPERTIES);
+    let bytes = fs::read(&path).await
+        .map_err(|e| format!("Could not read server.properties: {}", e))?;
+    let contents = String::from_utf8(bytes)
+        .unwrap_or_else(|e| e.as_bytes().iter().map(|b| *b as char).collect());
+    Ok(ServerProperties::parse(&contents))
+}
+
+pub async fn write_server_properties(server_location: &str, props: &ServerProperties) -> Result<(), String> {
+    let path = Path::new(server_location).join(SERVER_PROPERTIES);
+    fs::write(&path, props.render()).await
+        .map_err(|e| format!("Could not write server.properties: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn untouched_file_round_trips() {
+        let contents = "#Minecraft server properties\n#Sat Jan 01 00:00:00 UTC 2022\n\
+            allow-flight=false\n\n  ! bang comment\nmotd=\\u00A7aHi\\: there\n\
+            key\\ with\\ spaces = v\nlevel-name=world\n";
+        assert_eq!(ServerProperties::parse(contents).render(), contents);
+    }
+
+    #[test]
+    fn set_keeps_order_and_comments() {
+        let mut props = ServerProperties::parse("# top\nb=1\n# middle\na=2\n");
+        props.set("a", "3");
+        props.set("b", "1");
+        props.set("c", "4");
+        assert_eq!(props.render(), "# top\nb=1\n# middle\na=3\nc=4\n");
+        let keys: Vec<_> = props.entries().map(|(k, _)| k).collect();
+        assert_eq!(keys, ["b", "a", "c"]);
+    }
+
+    #[test]
+    fn parses_java_separators_and_escapes() {
+        let contents = concat!(
+            "colon:value\n",
+            "spaced   value with spaces\n",
+            "both = = x\n",
+            "escaped\\=key=a\\=b\\:c\\\\d\n",
+            "unicode=\\u00A7cRed \\uD83D\\uDE00\n",
+            "tabs=a\\tb\n",
+            "bare\n",
+            "multi=one, \\\n    two, \\\n    three\n",
+            "after=ok\n",
+        );
+        let props = ServerProperties::parse(contents);
+        assert_eq!(props.render(), contents);
+        assert_eq!(props.get("colon"), Some("value"));
+        assert_eq!(props.get("spaced"), Some("value with spaces"));
+        assert_eq!(props.get("both"), Some("= x"));
+        assert_eq!(props.get("escaped=key"), Some("a=b:c\\d"));
+        assert_eq!(props.get("unicode"), Some("\u{a7}cRed \u{1F600}"));
+        assert_eq!(props.get("tabs"), Some("a\tb"));
+        assert_eq!(props.get("bare"), Some(""));
+        assert_eq!(props.get("multi"), Some("one, two, three"));
+        assert_eq!(props.get("after"), Some("ok"));
+    }
+
+    #[test]
+    fn changed_values_are_escaped_as_ascii() {
+        let mut props = ServerProperties::parse("motd=A Minecraft Server\n");
+        props.set("motd", " \u{a7}aWelcome: a=b #1 \\ \u{1F600}");
+        props.set("odd key", "x");
+        let rendered = props.render();
+        assert!(rendered.is_ascii());
+        assert_eq!(rendered, "motd=\\ \\u00A7aWelcome\\: a\\=b \\#1 \\\\ \\uD83D\\uDE00\nodd\\ key=x\n");
+        let reparsed = ServerProperties::parse(&rendered);
+        assert_eq!(reparsed.get("motd"), Some(" \u{a7}aWelcome: a=b #1 \\ \u{1F600}"));
+        assert_eq!(reparsed.get("odd key"), Some("x"));
+    }
+
+    #[test]
+    fn malformed_unicode_escape_is_kept_literally() {
+        let props = ServerProperties::parse("a=\\u+123\nb=\\u12\n");
+        assert_eq!(props.get("a"), Some("u+123"));
+        assert_eq!(props.get("b"), Some("u12"));
+    }
+}
@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use reqwest::Client;
+use rocket::tokio::fs::{self, File};
+use rocket::tokio::io::AsyncWriteExt;
+use rocket::tokio::process::Command;
+use rocket::tokio::task::spawn_blocking;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use crate::constants::{DEFAULT_SERVERS_FILE, EULA_FILE, SERVER_PROPERTIES, SYSTEMD_SERVICE};
+use crate::curseforge::{CurseForgeFile, api_base, download_file, fetch_file, fetch_latest_pack_file, fetch_server_pack_file, pack_version};
+use crate::jobs::JobHandle;
+use crate::operations::{remove_path, write_mods_list};
+use crate::properties::{ServerProperties, apply_changes};
+use crate::unitfile::{UnitOptions, install_unit, plan_unit};
+
+/// What to provision. `file_id` defaults to the newest file with a server
+/// pack; `server_location` to `SERVER_LOCATION`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProvisionRequest {
+    pub project_id: u64,
+    pub file_id: Option<u64>,
+    pub server_location: Option<String>,
+    /// Must be true: provisioning writes `eula=true` on the operator's behalf.
+    pub accept_eula: bool,
+    /// Initial `server.properties` values, validated like `PATCH /server_properties`.
+    pub properties: HashMap<String, String>,
+    /// Java used to run a loader installer; defaults to `java` on `PATH`.
+    pub java_path: Option<String>,
+    /// Also write and enable the systemd user unit for the new directory.
+    pub install_unit: bool,
+}
+
+/// A server directory set up by provisioning.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisteredServer {
+    pub server_location: String,
+    pub project_id: u64,
+    pub file_id: i64,
+    pub pack_name: String,
+    pub version: String,
+    pub provisioned: DateTime<Utc>,
+}
+
+pub fn servers_file_path() -> String {
+    std::env::var("SERVERS_FILE").unwrap_or_else(|_| DEFAULT_SERVERS_FILE.to_string())
+}
+
+/// Where provisioning fetches packs from and records what it set up.
+#[derive(Debug, Clone)]
+pub struct ProvisionContext {
+    pub api_url: String,
+    pub servers_file: String,
+}
+
+impl ProvisionContext {
+    /// `CURSEFORGE_API_URL` and `SERVERS_FILE`.
+    pub fn from_env() -> Self {
+        ProvisionContext { api_url: api_base(), servers_file: servers_file_path() }
+    }
+}
+
+pub async fn read_registered_servers(path: &str) -> Result<Vec<RegisteredServer>, String> {
+    match fs::read_to_string(path).await {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|e| format!("Could not parse {}: {}", path, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("Could not read {}: {}", path, e)),
+    }
+}
+
+/// Adds `server` to the registry, replacing any entry for the same directory.
+async fn register_server(path: &str, server: RegisteredServer) -> Result<(), String> {
+    let mut servers = read_registered_servers(path).await?;
+    servers.retain(|s| s.server_location != server.server_location);
+    servers.push(server);
+    let contents = serde_json::to_string_pretty(&servers).map_err(|e| e.to_string())?;
+    fs::write(path, contents).await.map_err(|e| format!("Could not write {}: {}", path, e))
+}
+
+/// Checks a request before any work is started, so mistakes come back as a
+/// 400 rather than a failed job. Returns the absolute target directory.
+pub async fn check_request(req: &ProvisionRequest, default_location: &str) -> Result<PathBuf, Vec<String>> {
+    let mut errors = Vec::new();
+    if req.project_id == 0 {
+        errors.push("project_id is required.".to_string());
+    }
+    if !req.accept_eula {
+        errors.push("The Minecraft EULA (https://aka.ms/MinecraftEULA) must be accepted with accept_eula=true.".to_string());
+    }
+    if let Err(mut e) = apply_changes(&mut ServerProperties::default(), &req.properties) {
+        errors.append(&mut e);
+    }
+    let location = req.server_location.as_deref().unwrap_or(default_location);
+    let target = match std::path::absolute(location) {
+        Ok(target) => target,
+        Err(e) => return Err(vec![format!("Failed to resolve {}: {}", location, e)]),
+    };
+    if let Ok(mut entries) = fs::read_dir(&target).await
+        && let Ok(Some(_)) = entries.next_entry().await
+    {
+        errors.push(format!("{} already exists and is not empty.", target.display()));
+    }
+    if errors.is_empty() { Ok(target) } else { Err(errors) }
+}
+
+/// The single top-level directory every entry is under, if there is one.
+/// Most server packs wrap their files in e.g. `ServerFiles-2.41/`.
+fn common_root(names: &[PathBuf]) -> Option<PathBuf> {
+    let first = names.first()?.components().next()?;
+    let shared = names.iter().all(|n| n.components().next() == Some(first));
+    let is_dir = names.iter().any(|n| n.components().count() > 1);
+    match first {
+        Component::Normal(root) if shared && is_dir => Some(PathBuf::from(root)),
+        _ => None,
+    }
+}
+
+/// Unpacks `archive` into `target`, dropping a shared top-level directory and
+/// keeping Unix permissions so the start scripts stay executable.
+pub fn unpack(archive: &Path, target: &Path) -> Result<u64, String> {
+    let file = std::fs::File::open(archive).map_err(|e| format!("Failed to open {}: {}", archive.display(), e))?;
+    let mut zip = zip::ZipArchive::new(file).map_err(|e| format!("Not a valid zip: {}", e))?;
+    let mut names = Vec::new();
+    for i in 0..zip.len() {
+        let entry = zip.by_index(i).map_err(|e| e.to_string())?;
+        names.push(entry.enclosed_name().ok_or_else(|| format!("Unsafe path in zip: {}", entry.name()))?);
+    }
+    let root = common_root(&names);
+    let mut files = 0;
+    for (i, name) in names.iter().enumerate() {
+        let relative = match &root {
+            Some(root) => name.strip_prefix(root).unwrap_or(name),
+            None => name,
+        };
+        if relative.as_os_str().is_empty() {
+            continue;
+        }
+        let dest = target.join(relative);
+        let mut entry = zip.by_index(i).map_err(|e| e.to_string())?;
+        if entry.is_dir() {
+            std::fs::create_dir_all(&dest).map_err(|e| format!("Failed to create {}: {}", dest.display(), e))?;
+            continue;
+        }
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        let mut out = std::fs::File::create(&dest).map_err(|e| format!("Failed to create {}: {}", dest.display(), e))?;
+        std::io::copy(&mut entry, &mut out).map_err(|e| format!("Failed to extract {}: {}", dest.display(), e))?;
+        #[cfg(unix)]
+        if let Some(mode) = entry.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = std::fs::set_permissions(&dest, std::fs::Permissions::from_mode(mode & 0o777));
+        }
+        files += 1;
+    }
+    Ok(files)
+}
+
+/// Streams the server pack to `dest`, reporting bytes to `job`.
+async fn download(client: &Client, api_url: &str, pack: &CurseForgeFile, dest: &Path, job: &JobHandle) -> Result<(), String> {
+    let mut resp = download_file(client, api_url, pack.project_id as u64, pack.id).await?;
+    let mut out = File::create(dest).await.map_err(|e| format!("Failed to create {}: {}", dest.display(), e))?;
+    while let Some(chunk) = resp.chunk().await.map_err(|e| format!("Download failed: {}", e))? {
+        out.write_all(&chunk).await.map_err(|e| format!("Failed to write {}: {}", dest.display(), e))?;
+        job.advance(0, chunk.len() as u64);
+    }
+    out.flush().await.map_err(|e| format!("Failed to write {}: {}", dest.display(), e))?;
+    job.advance(1, 0);
+    Ok(())
+}
+
+/// Runs a NeoForge, Forge or Fabric installer shipped in the pack's root.
+/// Returns the installer's file name, or `None` if the pack has none.
+async fn run_installer(target: &Path, java: &str, job: &JobHandle) -> Result<Option<String>, String> {
+    let forge = Regex::new(r"^(neo)?forge-.*installer\.jar$").unwrap();
+    let fabric = Regex::new(r"^fabric-installer.*\.jar$").unwrap();
+    let mut entries = fs::read_dir(target).await.map_err(|e| format!("Failed to read {}: {}", target.display(), e))?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let args: &[&str] = if forge.is_match(&name) {
+            &["--installServer"]
+        } else if fabric.is_match(&name) {
+            &["server", "-downloadMinecraft"]
+        } else {
+            continue;
+        };
+        job.log(format!("Running {}", name));
+        let output = Command::new(java).arg("-jar").arg(&name).args(args).current_dir(target)
+            .output().await.map_err(|e| format!("Failed to run {}: {}", java, e))?;
+        let text = String::from_utf8_lossy(&output.stdout).to_string() + &String::from_utf8_lossy(&output.stderr);
+        let lines: Vec<&str> = text.lines().collect();
+        for line in &lines[lines.len().saturating_sub(10)..] {
+            job.log(line.to_string());
+        }
+        if !output.status.success() {
+            return Err(format!("{} failed with {}", name, output.status));
+        }
+        return Ok(Some(name));
+    }
+    Ok(None)
+}
+
+/// What setting up the staging directory produced.
+struct Prepared {
+    files: u64,
+    installer: Option<String>,
+    mods: Option<usize>,
+}
+
+/// Downloads and unpacks the server pack into `dir`, accepts the EULA, runs
+/// the loader installer and writes `server.properties` and `mods.list`.
+async fn prepare(client: &Client, api_url: &str, req: &ProvisionRequest, pack: &CurseForgeFile, dir: &Path, job: &JobHandle) -> Result<Prepared, String> {
+    fs::create_dir_all(dir).await.map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let archive = dir.join(format!(".{}", pack.file_name));
+    download(client, api_url, pack, &archive, job).await?;
+    let (archive_path, dir_path) = (archive.clone(), dir.to_path_buf());
+    let files = spawn_blocking(move || unpack(&archive_path, &dir_path)).await.map_err(|e| e.to_string())??;
+    fs::remove_file(&archive).await.map_err(|e| format!("Failed to remove {}: {}", archive.display(), e))?;
+    job.log(format!("Unpacked {} files", files));
+
+    fs::write(dir.join(EULA_FILE), format!("# Accepted through mc-manager on {}\neula=true\n", Utc::now().to_rfc3339())).await
+        .map_err(|e| format!("Failed to write {}: {}", EULA_FILE, e))?;
+
+    let installer = run_installer(dir, req.java_path.as_deref().unwrap_or("java"), job).await?;
+
+    let props_path = dir.join(SERVER_PROPERTIES);
+    let mut props = ServerProperties::parse(&fs::read_to_string(&props_path).await.unwrap_or_default());
+    apply_changes(&mut props, &req.properties).map_err(|errors| errors.join(" "))?;
+    fs::write(&props_path, props.render()).await.map_err(|e| format!("Could not write server.properties: {}", e))?;
+
+    let mods = write_mods_list(&dir.to_string_lossy()).await?;
+    if let Some(count) = mods {
+        job.log(format!("Wrote mods.list with {} mods", count));
+    }
+    Ok(Prepared { files, installer, mods })
+}
+
+/// Sets up a CurseForge server pack in an empty directory and registers it
+/// in the servers file. The pack is prepared in a staging directory next to
+/// the target and moved into place once it's complete, so a failed run
+/// leaves nothing behind and can simply be retried.
+pub async fn run_provision(job: JobHandle, req: ProvisionRequest, target: PathBuf, ctx: ProvisionContext) -> Result<Value, String> {
+    let client = Client::new();
+    let api_url = ctx.api_url.trim_end_matches('/');
+    let file = match req.file_id {
+        Some(file_id) => fetch_file(&client, api_url, req.project_id, file_id).await?,
+        None => fetch_latest_pack_file(&client, api_url, req.project_id).await?,
+    };
+    let pack = fetch_server_pack_file(&client, api_url, &file).await?;
+    job.log(format!("Provisioning {} ({}) into {}", file.display_name, pack.file_name, target.display()));
+    job.set_totals(1, pack.file_length.max(0) as u64);
+
+    let name = target.file_name().and_then(|n| n.to_str()).ok_or_else(|| format!("Invalid path {}", target.display()))?;
+    let staging = target.with_file_name(format!(".{}.provisioning", name));
+    remove_path(&staging).await?;
+    let Prepared { files, installer, mods } = match prepare(&client, api_url, &req, &pack, &staging, &job).await {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            let _ = remove_path(&staging).await;
+            return Err(e);
+        }
+    };
+    // Renaming over the target works as long as it's missing or empty.
+    if let Err(e) = fs::rename(&staging, &target).await {
+        let _ = remove_path(&staging).await;
+        return Err(format!("Failed to move {} into place: {}", target.display(), e));
+    }
+
+    let location = target.to_string_lossy().to_string();
+    let version = pack_version(&file);
+    register_server(&ctx.servers_file, RegisteredServer {
+        server_location: location.clone(),
+        project_id: req.project_id,
+        file_id: file.id,
+        pack_name: file.display_name.clone(),
+        version: version.clone(),
+        provisioned: Utc::now(),
+    }).await?;
+    job.log(format!("Registered {} in {}", location, ctx.servers_file));
+
+    let mut warnings = Vec::new();
+    let unit = if req.install_unit {
+        let unit = std::env::var("SERVER_UNIT").unwrap_or_else(|_| SYSTEMD_SERVICE.to_string());
+        let plan = plan_unit(&unit, &location, &UnitOptions::default()).await?;
+        if plan.overwrites() {
+            warnings.push(format!("{} already exists and differs; not replaced. Use /systemd_unit with working_directory={} to switch it.", plan.path, location));
+            None
+        } else {
+            install_unit(&plan).await?;
+            job.log(format!("Installed and enabled {}", plan.path));
+            Some(plan.path)
+        }
+    } else {
+        None
+    };
+
+    Ok(json!({
+        "server_location": location,
+        "pack": file.display_name,
+        "version": version,
+        "file_id": file.id,
+        "server_pack": pack.file_name,
+        "files": files,
+        "installer": installer,
+        "mods": mods,
+        "unit": unit,
+        "warnings": warnings,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+    use std::os::unix::fs::PermissionsExt;
+    use zip::write::SimpleFileOptions;
+    use crate::testutil::{http_stand_in, temp_dir};
+    use super::*;
+
+    fn file_json(id: i64, display_name: &str, file_name: &str, server_pack: bool) -> Value {
+        json!({
+            "id": id, "dateCreated": "2025-01-01T00:00:00Z", "dateModified": "2025-01-01T00:00:00Z",
+            "displayName": display_name, "fileLength": 0, "fileName": file_name, "status": 4, "projectId": 1,
+            "gameVersions": [], "gameVersionTypeIds": [], "releaseType": 1, "totalDownloads": 0,
+            "user": {"id": 1, "username": "author", "twitchAvatarUrl": null, "displayName": "author"},
+            "additionalFilesCount": 1, "hasServerPack": !server_pack, "additionalServerPackFilesCount": 0,
+            "isEarlyAccessContent": false, "isCompatibleWithClient": !server_pack, "isServerPack": server_pack,
+        })
+    }
+
+    /// A server pack wrapped in a top-level directory, like most packs.
+    fn server_pack_zip() -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("Pack-1.0/startserver.sh", SimpleFileOptions::default().unix_permissions(0o755)).unwrap();
+        zip.write_all(b"#!/bin/sh\necho starting\n").unwrap();
+        zip.start_file("Pack-1.0/mods/example.jar", SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"jar").unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    /// A local stand-in for the CurseForge API with two files of project 1:
+    /// file 10, whose server pack 11 is broken, and file 20, whose server
+    /// pack 21 is `server_pack_zip`.
+    fn curseforge_stand_in() -> String {
+        let (url, _) = http_stand_in(|req| match req.path.as_str() {
+            "/mods/1/files/10" => (200, json!({"data": file_json(10, "Pack-0.9", "pack-0.9.zip", false)}).to_string().into_bytes()),
+            "/mods/1/files/10/additional-files" => (200, json!({"data": [file_json(11, "Server-0.9", "server-0.9.zip", true)]}).to_string().into_bytes()),
+            "/mods/1/files/11/download" => (200, b"not a zip".to_vec()),
+            "/mods/1/files/20" => (200, json!({"data": file_json(20, "Pack-1.0", "pack-1.0.zip", false)}).to_string().into_bytes()),
+            "/mods/1/files/20/additional-files" => (200, json!({"data": [file_json(21, "Server-1.0", "server-1.0.zip", true)]}).to_string().into_bytes()),
+            "/mods/1/files/21/download" => (200, server_pack_zip()),
+            _ => (404, Vec::new()),
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn failed_provision_can_be_retried() {
+        let dir = temp_dir("provision");
+        let ctx = ProvisionContext {
+            api_url: curseforge_stand_in(),
+            servers_file: dir.join("servers.json").to_string_lossy().to_string(),
+        };
+        let target = dir.join("server");
+        let mut req = ProvisionRequest {
+            project_id: 1,
+            file_id: Some(10),
+            server_location: Some(target.to_string_lossy().to_string()),
+            accept_eula: true,
+            properties: HashMap::from([("max-players".to_string(), "5".to_string())]),
+            ..Default::default()
+        };
+
+        let err = run_provision(JobHandle::detached("provision"), req.clone(), target.clone(), ctx.clone()).await.unwrap_err();
+        assert!(err.starts_with("Not a valid zip"), "{}", err);
+        assert!(!target.exists());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        req.file_id = Some(20);
+        assert_eq!(check_request(&req, "unused").await, Ok(target.clone()));
+        let result = run_provision(JobHandle::detached("provision"), req, target.clone(), ctx.clone()).await.unwrap();
+        assert_eq!(result["version"], "1.0");
+        assert_eq!(result["files"], 2);
+        assert_eq!(result["mods"], 1);
+        let mode = std::fs::metadata(target.join("startserver.sh")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert!(std::fs::read_to_string(target.join(EULA_FILE)).unwrap().contains("eula=true"));
+        assert!(std::fs::read_to_string(target.join(SERVER_PROPERTIES)).unwrap().contains("max-players=5"));
+        assert_eq!(std::fs::read_to_string(target.join("mods.list")).unwrap(), "example.jar");
+        assert!(!dir.join(".server.provisioning").exists());
+        let servers = read_registered_servers(&ctx.servers_file).await.unwrap();
+        assert_eq!((servers.len(), servers[0].file_id, servers[0].pack_name.as_str()), (1, 20, "Pack-1.0"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
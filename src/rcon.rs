@@ -0,0 +1,182 @@
+use std::future::Future;
+use std::time::Duration;
+use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::TcpStream;
+use rocket::tokio::time::timeout;
+use crate::constants::RCON_TIMEOUT_SECS;
+use crate::properties::read_server_properties;
+
+const PACKET_AUTH: i32 = 3;
+const PACKET_COMMAND: i32 = 2;
+/// Not a request type the server handles; it answers with "Unknown request"
+/// under the same id, which marks the end of the previous reply.
+const PACKET_RESPONSE: i32 = 0;
+/// Replies longer than this are split across several packets.
+const MAX_PAYLOAD: i32 = 4096;
+
+/// Minimal Source RCON client, as spoken by the Minecraft server.
+pub struct RconClient {
+    stream: TcpStream,
+    next_id: i32,
+}
+
+/// Fails with `what` timed out if `fut` takes longer than `RCON_TIMEOUT_SECS`,
+/// so a server that accepts connections but never answers can't hang callers.
+async fn with_timeout<T>(what: &str, fut: impl Future<Output = Result<T, String>>) -> Result<T, String> {
+    timeout(Duration::from_secs(RCON_TIMEOUT_SECS), fut).await
+        .unwrap_or_else(|_| Err(format!("{} timed out after {}s", what, RCON_TIMEOUT_SECS)))
+}
+
+impl RconClient {
+    pub async fn connect(addr: &str, password: &str) -> Result<Self, String> {
+        with_timeout("RCON login", Self::login(addr, password)).await
+    }
+
+    async fn login(addr: &str, password: &str) -> Result<Self, String> {
+        let stream = TcpStream::connect(addr).await
+            .map_err(|e| format!("Failed to connect to RCON at {}: {}", addr, e))?;
+        let mut client = RconClient { stream, next_id: 1 };
+        let id = client.send(PACKET_AUTH, password).await?;
+        // The server answers auth with an id of -1 when the password is wrong.
+        let (resp_id, _) = client.read_packet().await?;
+        if resp_id == -1 || resp_id != id {
+            return Err("RCON authentication failed".to_string());
+        }
+        Ok(client)
+    }
+
+    /// Runs a console command and returns the server's reply, however many
+    /// packets it's split across.
+    pub async fn command(&mut self, command: &str) -> Result<String, String> {
+        with_timeout("RCON command", self.run(command)).await
+    }
+
+    async fn run(&mut self, command: &str) -> Result<String, String> {
+        let id = self.send(PACKET_COMMAND, command).await?;
+        // Packets are answered in order, so everything before the reply to
+        // the sentinel belongs to the command.
+        let sentinel = self.send(PACKET_RESPONSE, "").await?;
+        let mut reply = Vec::new();
+        loop {
+            match self.read_packet().await? {
+                (resp_id, body) if resp_id == id => reply.extend(body),
+                (resp_id, _) if resp_id == sentinel => break,
+                _ => return Err("Unexpected RCON response id".to_string()),
+            }
+        }
+        // The server splits on bytes, which can cut a character in two.
+        Ok(String::from_utf8_lossy(&reply).to_string())
+    }
+
+    async fn send(&mut self, kind: i32, body: &str) -> Result<i32, String> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut packet = Vec::with_capacity(body.len() + 14);
+        packet.extend_from_slice(&((body.len() + 10) as i32).to_le_bytes());
+        packet.extend_from_slice(&id.to_le_bytes());
+        packet.extend_from_slice(&kind.to_le_bytes());
+        packet.extend_from_slice(body.as_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        self.stream.write_all(&packet).await
+            .map_err(|e| format!("Failed to send RCON packet: {}", e))?;
+        Ok(id)
+    }
+
+    async fn read_packet(&mut self) -> Result<(i32, Vec<u8>), String> {
+        let mut len_buf = [0u8; 4];
+        self.stream.read_exact(&mut len_buf).await
+            .map_err(|e| format!("Failed to read RCON packet: {}", e))?;
+        let len = i32::from_le_bytes(len_buf);
+        if !(10..=MAX_PAYLOAD + 10).contains(&len) {
+            return Err(format!("Invalid RCON packet length {}", len));
+        }
+        let mut buf = vec![0u8; len as usize];
+        self.stream.read_exact(&mut buf).await
+            .map_err(|e| format!("Failed to read RCON packet: {}", e))?;
+        let id = i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        Ok((id, buf[8..buf.len() - 2].to_vec()))
+    }
+}
+
+/// Connects using `enable-rcon`, `rcon.port` and `rcon.password` from the
+/// server's `server.properties`.
+pub async fn connect_from_properties(server_location: &str) -> Result<RconClient, String> {
+    let props = read_server_properties(server_location).await?;
+    if props.get("enable-rcon").map(str::trim) != Some("true") {
+        return Err("RCON is not enabled in server.properties".to_string());
+    }
+    let port = props.get("rcon.port").map(str::trim).unwrap_or("25575");
+    let password = props.get("rcon.password").unwrap_or("");
+    if password.is_empty() {
+        return Err("rcon.password is not set in server.properties".to_string());
+    }
+    RconClient::connect(&format!("127.0.0.1:{}", port), password).await
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::tokio::{self, net::TcpListener};
+    use super::*;
+
+    async fn write_packet(stream: &mut TcpStream, id: i32, body: &[u8]) {
+        let mut packet = ((body.len() + 10) as i32).to_le_bytes().to_vec();
+        packet.extend(id.to_le_bytes());
+        packet.extend(0i32.to_le_bytes());
+        packet.extend(body);
+        packet.extend([0, 0]);
+        stream.write_all(&packet).await.unwrap();
+    }
+
+    /// A server that accepts `secret` and answers each command with
+    /// `reply(command)`, split into packets the way Minecraft does.
+    async fn fake_server(reply: fn(&str) -> String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            loop {
+                let mut len = [0u8; 4];
+                if stream.read_exact(&mut len).await.is_err() {
+                    return;
+                }
+                let mut buf = vec![0u8; i32::from_le_bytes(len) as usize];
+                stream.read_exact(&mut buf).await.unwrap();
+                let id = i32::from_le_bytes(buf[0..4].try_into().unwrap());
+                let kind = i32::from_le_bytes(buf[4..8].try_into().unwrap());
+                let body = String::from_utf8(buf[8..buf.len() - 2].to_vec()).unwrap();
+                match kind {
+                    PACKET_AUTH => write_packet(&mut stream, if body == "secret" { id } else { -1 }, b"").await,
+                    PACKET_COMMAND => {
+                        let text = reply(&body);
+                        for chunk in text.as_bytes().chunks(MAX_PAYLOAD as usize) {
+                            write_packet(&mut stream, id, chunk).await;
+                        }
+                    }
+                    other => write_packet(&mut stream, id, format!("Unknown request {:x}", other).as_bytes()).await,
+                }
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn reads_replies_split_across_packets() {
+        let addr = fake_server(|command| match command {
+            // The first split falls inside an "é".
+            "list" => format!("There are 1000 of a max of 10000 players online: {}", vec!["Player\u{e9}"; 1000].join(", ")),
+            other => format!("ran {}", other),
+        }).await;
+        let mut client = RconClient::connect(&addr, "secret").await.unwrap();
+        let list = client.command("list").await.unwrap();
+        assert!(list.len() > 2 * MAX_PAYLOAD as usize);
+        assert!(list.ends_with("Player\u{e9}, Player\u{e9}"));
+        assert!(!list.contains('\u{FFFD}'));
+        assert_eq!(client.command("say hi").await.unwrap(), "ran say hi");
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_password() {
+        let addr = fake_server(|_| String::new()).await;
+        assert_eq!(RconClient::connect(&addr, "wrong").await.err().unwrap(), "RCON authentication failed");
+    }
+}
@@ -1,22 +1,210 @@
-use std::process::Command;
-use crate::constants::SYSTEMD_SERVICE;
-
-#[derive(Debug, Clone, Copy)]
-pub enum ServerAction {
-    Start,
-    Stop,
-    Restart,
-}
-
-pub fn systemctl_server(action: ServerAction) -> bool {
-    let action_str = match action {
-        ServerAction::Start => "start",
-        ServerAction::Stop => "stop",
-        ServerAction::Restart => "restart",
-    };
-    Command::new("systemctl")
-        .args(["--user", action_str, SYSTEMD_SERVICE])
-        .status()
-        .map(|s| s.success())
-        .unwrap_or(false)
+use std::sync::OnceLock;
+use rocket::tokio::process::Command;
+use serde::Serialize;
+use crate::childproc::{ChildBackend, ChildConfig};
+use crate::constants::{DEFAULT_SERVER_LOCATION, SYSTEMD_SERVICE};
+use crate::rcon::connect_from_properties;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UnitState {
+    pub active_state: String,
+    /// `success`, or why the unit last stopped (`exit-code`, `signal`, ...).
+    pub result: String,
+    pub exec_main_status: i32,
+    pub n_restarts: u64,
+    /// The PID of the server's main process, if it is running.
+    pub main_pid: Option<u32>,
+    /// When the server last entered the active state.
+    pub active_since: Option<String>,
+}
+
+impl UnitState {
+    pub fn is_active(&self) -> bool {
+        self.active_state == "active"
+    }
+}
+
+/// Log lines plus an opaque cursor; passing the cursor back returns only
+/// lines logged since.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LogPage {
+    pub lines: Vec<String>,
+    pub cursor: Option<String>,
+}
+
+/// How the Minecraft server process is run and controlled.
+#[rocket::async_trait]
+pub trait ServerBackend: Send + Sync {
+    /// The `SERVER_BACKEND` value that selects this backend.
+    fn name(&self) -> &'static str;
+
+    /// True when the server lives inside this process, so another process
+    /// (e.g. `mc-manager-cli --local`) can neither see nor control it.
+    fn manages_process(&self) -> bool {
+        false
+    }
+
+    /// `journalctl` arguments selecting the server's journal, for backends
+    /// that log to journald.
+    fn journal_args(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    async fn start(&self) -> Result<(), String>;
+    async fn stop(&self) -> Result<(), String>;
+
+    async fn restart(&self) -> Result<(), String> {
+        self.stop().await?;
+        self.start().await
+    }
+
+    async fn status(&self) -> Option<UnitState>;
+
+    async fn is_active(&self) -> bool {
+        self.status().await.is_some_and(|s| s.is_active())
+    }
+
+    /// The last `lines` lines of server output, or everything after `cursor`.
+    async fn logs(&self, lines: usize, cursor: Option<&str>) -> Result<LogPage, String>;
+
+    /// Runs a console command, returning its reply if the backend gets one.
+    async fn send_command(&self, command: &str) -> Result<String, String>;
+
+    /// Runs a console command and returns its output, for callers that parse
+    /// it. Backends whose replies only show up in the server log collect what
+    /// was logged right after the command.
+    async fn command_output(&self, command: &str) -> Result<String, String> {
+        self.send_command(command).await
+    }
+}
+
+/// A systemd unit, either in the user's manager (`systemctl --user`) or the
+/// system manager. Console commands go over RCON.
+pub struct SystemdBackend {
+    unit: String,
+    user: bool,
+    server_location: String,
+}
+
+impl SystemdBackend {
+    pub fn new(unit: &str, user: bool, server_location: &str) -> Self {
+        SystemdBackend { unit: unit.to_string(), user, server_location: server_location.to_string() }
+    }
+
+    fn scoped(&self, program: &str) -> Command {
+        let mut cmd = Command::new(program);
+        if self.user {
+            cmd.arg("--user");
+        }
+        cmd
+    }
+
+    async fn systemctl(&self, action: &str) -> Result<(), String> {
+        let output = self.scoped("systemctl").args([action, &self.unit]).output().await
+            .map_err(|e| format!("Failed to run systemctl: {}", e))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(format!("systemctl {} {} failed: {}", action, self.unit, String::from_utf8_lossy(&output.stderr).trim()))
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl ServerBackend for SystemdBackend {
+    fn name(&self) -> &'static str {
+        if self.user { "systemd-user" } else { "systemd-system" }
+    }
+
+    fn journal_args(&self) -> Option<Vec<String>> {
+        let mut args = vec!["-u".to_string(), self.unit.clone()];
+        if self.user {
+            args.insert(0, "--user".to_string());
+        }
+        Some(args)
+    }
+
+    async fn start(&self) -> Result<(), String> {
+        self.systemctl("start").await
+    }
+
+    async fn stop(&self) -> Result<(), String> {
+        self.systemctl("stop").await
+    }
+
+    async fn restart(&self) -> Result<(), String> {
+        self.systemctl("restart").await
+    }
+
+    async fn status(&self) -> Option<UnitState> {
+        let output = self.scoped("systemctl")
+            .args(["show", "-p", "ActiveState", "-p", "Result", "-p", "ExecMainStatus", "-p", "NRestarts"])
+            .args(["-p", "MainPID", "-p", "ActiveEnterTimestamp", &self.unit])
+            .output()
+            .await
+            .ok()?;
+        let text = String::from_utf8_lossy(&output.stdout);
+        let mut state = UnitState::default();
+        for line in text.lines() {
+            match line.split_once('=') {
+                Some(("ActiveState", v)) => state.active_state = v.to_string(),
+                Some(("Result", v)) => state.result = v.to_string(),
+                Some(("ExecMainStatus", v)) => state.exec_main_status = v.parse().unwrap_or(0),
+                Some(("NRestarts", v)) => state.n_restarts = v.parse().unwrap_or(0),
+                Some(("MainPID", v)) => state.main_pid = v.parse().ok().filter(|pid| *pid != 0),
+                Some(("ActiveEnterTimestamp", v)) if !v.is_empty() => state.active_since = Some(v.to_string()),
+                _ => {}
+            }
+        }
+        if state.active_state.is_empty() { None } else { Some(state) }
+    }
+
+    async fn logs(&self, lines: usize, cursor: Option<&str>) -> Result<LogPage, String> {
+        let mut cmd = self.scoped("journalctl");
+        cmd.args(["-u", &self.unit, "--no-pager", "--output=cat", "--show-cursor"]);
+        match cursor {
+            Some(cursor) => cmd.arg(format!("--after-cursor={}", cursor)),
+            None => cmd.args(["-n", &lines.to_string()]),
+        };
+        let output = cmd.output().await.map_err(|e| format!("Failed to run journalctl: {}", e))?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+        }
+        let mut page = LogPage { lines: Vec::new(), cursor: cursor.map(|c| c.to_string()) };
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            match line.strip_prefix("-- cursor: ") {
+                Some(c) => page.cursor = Some(c.to_string()),
+                None => page.lines.push(line.to_string()),
+            }
+        }
+        Ok(page)
+    }
+
+    async fn send_command(&self, command: &str) -> Result<String, String> {
+        let mut rcon = connect_from_properties(&self.server_location).await
+            .map_err(|e| format!("RCON is unavailable: {}", e))?;
+        rcon.command(command).await
+    }
+}
+
+static BACKEND: OnceLock<Box<dyn ServerBackend>> = OnceLock::new();
+
+fn backend_from_env() -> Box<dyn ServerBackend> {
+    let server_location = std::env::var("SERVER_LOCATION").unwrap_or_else(|_| DEFAULT_SERVER_LOCATION.to_string());
+    let unit = std::env::var("SERVER_UNIT").unwrap_or_else(|_| SYSTEMD_SERVICE.to_string());
+    match std::env::var("SERVER_BACKEND").as_deref() {
+        Ok("child") => Box::new(ChildBackend::new(ChildConfig::from_env(&server_location))),
+        Ok("systemd-system") => Box::new(SystemdBackend::new(&unit, false, &server_location)),
+        Ok("systemd-user") | Err(_) => Box::new(SystemdBackend::new(&unit, true, &server_location)),
+        Ok(other) => {
+            tracing::error!("Unknown SERVER_BACKEND '{}'; using systemd-user", other);
+            Box::new(SystemdBackend::new(&unit, true, &server_location))
+        }
+    }
+}
+
+/// The backend selected by `SERVER_BACKEND`, created on first use. There is
+/// one per process because the child backend owns the server process.
+pub fn server_backend() -> &'static dyn ServerBackend {
+    BACKEND.get_or_init(backend_from_env).as_ref()
 }
@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use regex::Regex;
+use rocket::tokio::{self, fs};
+use rocket::tokio::io::{AsyncReadExt, AsyncSeekExt};
+use rocket::tokio::process::Command;
+use rocket::tokio::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use crate::constants::{DEFAULT_SESSIONS_FILE, LATEST_LOG, SESSION_POLL_INTERVAL_SECS};
+use crate::serverctl::server_backend;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogEvent {
+    Join(String),
+    Leave(String),
+    /// The server started or stopped; anyone still online has been dropped.
+    ServerLifecycle,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub player: String,
+    pub joined: DateTime<FixedOffset>,
+    pub left: Option<DateTime<FixedOffset>>,
+    pub duration_secs: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Playtime {
+    pub player: String,
+    pub sessions: usize,
+    pub total_secs: i64,
+}
+
+pub struct LogParser {
+    join: Regex,
+    leave: Regex,
+    lifecycle: Regex,
+    full_stamp: Regex,
+    time_stamp: Regex,
+}
+
+impl LogParser {
+    pub fn new() -> Self {
+        LogParser {
+            join: Regex::new(r":\s+(\w{1,16}) joined the game\s*$").unwrap(),
+            leave: Regex::new(r":\s+(\w{1,16}) left the game\s*$").unwrap(),
+            lifecycle: Regex::new(r"Stopping (the )?server|Starting minecraft server").unwrap(),
+            full_stamp: Regex::new(r"^\[(\d{2}[A-Za-z]{3}\d{4} \d{2}:\d{2}:\d{2})").unwrap(),
+            time_stamp: Regex::new(r"^\[(\d{2}:\d{2}:\d{2})\]").unwrap(),
+        }
+    }
+
+    pub fn event(&self, line: &str) -> Option<LogEvent> {
+        if let Some(cap) = self.join.captures(line) {
+            Some(LogEvent::Join(cap[1].to_string()))
+        } else if let Some(cap) = self.leave.captures(line) {
+            Some(LogEvent::Leave(cap[1].to_string()))
+        } else if self.lifecycle.is_match(line) {
+            Some(LogEvent::ServerLifecycle)
+        } else {
+            None
+        }
+    }
+
+    /// Parses a `journalctl -o short-iso` line, which starts with the
+    /// journal's own timestamp.
+    pub fn journal_line(&self, line: &str) -> Option<(DateTime<FixedOffset>, LogEvent)> {
+        let (stamp, _) = line.split_once(' ')?;
+        let time = DateTime::parse_from_str(stamp, "%Y-%m-%dT%H:%M:%S%z").ok()?;
+        Some((time, self.event(line)?))
+    }
+
+    /// Parses a `logs/latest.log` line. Older loaders only log the time of
+    /// day, so those lines are dated with `date`.
+    pub fn latest_log_line(&self, line: &str, date: NaiveDate) -> Option<(DateTime<FixedOffset>, LogEvent)> {
+        let event = self.event(line)?;
+        let naive = if let Some(cap) = self.full_stamp.captures(line) {
+            NaiveDateTime::parse_from_str(&cap[1], "%d%b%Y %H:%M:%S").ok()?
+        } else {
+            let cap = self.time_stamp.captures(line)?;
+            date.and_time(NaiveTime::parse_from_str(&cap[1], "%H:%M:%S").ok()?)
+        };
+        let time = Local.from_local_datetime(&naive).single()?.fixed_offset();
+        Some((time, event))
+    }
+}
+
+impl Default for LogParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn playtime_totals(sessions: &[Session]) -> Vec<Playtime> {
+    let mut totals: HashMap<&str, Playtime> = HashMap::new();
+    for session in sessions {
+        let entry = totals.entry(&session.player).or_insert_with(|| Playtime {
+            player: session.player.clone(),
+            sessions: 0,
+            total_secs: 0,
+        });
+        entry.sessions += 1;
+        entry.total_secs += session.duration_secs;
+    }
+    let mut totals: Vec<Playtime> = totals.into_values().collect();
+    totals.sort_by_key(|p| std::cmp::Reverse(p.total_secs));
+    totals
+}
+
+/// How far into `logs/latest.log` we have read. The inode tells a rotated
+/// log apart from the one we were reading, even if it has grown past `offset`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LogPosition {
+    pub inode: Option<u64>,
+    pub offset: u64,
+}
+
+impl LogPosition {
+    /// Positioned at the current end of the file, so only new lines are read.
+    pub async fn at_end(path: &Path) -> Self {
+        match fs::metadata(path).await {
+            Ok(meta) => LogPosition { inode: Some(meta.ino()), offset: meta.len() },
+            Err(_) => LogPosition::default(),
+        }
+    }
+}
+
+/// Reads whatever was appended to the log since `pos`, starting over if the
+/// file was rotated or truncated. A trailing partial line is left for the
+/// next call.
+pub async fn read_new_lines(path: &Path, pos: &mut LogPosition) -> Vec<String> {
+    let Ok(mut file) = fs::File::open(path).await else { return Vec::new() };
+    let Ok(meta) = file.metadata().await else { return Vec::new() };
+    if pos.inode != Some(meta.ino()) || meta.len() < pos.offset {
+        *pos = LogPosition { inode: Some(meta.ino()), offset: 0 };
+    }
+    if file.seek(std::io::SeekFrom::Start(pos.offset)).await.is_err() {
+        return Vec::new();
+    }
+    let mut buf = Vec::new();
+    if file.read_to_end(&mut buf).await.is_err() {
+        return Vec::new();
+    }
+    let complete = buf.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+    pos.offset += complete as u64;
+    String::from_utf8_lossy(&buf[..complete]).lines().map(|l| l.to_string()).collect()
+}
+
+/// Everything the tracker has learned, as persisted to the sessions file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SessionState {
+    /// Finished sessions, oldest first.
+    sessions: Vec<Session>,
+    /// Players online and when they joined.
+    open: HashMap<String, DateTime<FixedOffset>>,
+    journal_cursor: Option<String>,
+    log: LogPosition,
+}
+
+impl SessionState {
+    fn close(&mut self, player: String, joined: DateTime<FixedOffset>, left: DateTime<FixedOffset>) {
+        self.sessions.push(Session { player, joined, left: Some(left), duration_secs: (left - joined).num_seconds() });
+    }
+
+    /// Sessions cut short by a server stop end at the stop; a second join
+    /// without a leave ends the first session.
+    fn apply(&mut self, time: DateTime<FixedOffset>, event: LogEvent) {
+        match event {
+            LogEvent::Join(player) => {
+                if let Some(joined) = self.open.insert(player.clone(), time) {
+                    self.close(player, joined, time);
+                }
+            }
+            LogEvent::Leave(player) => {
+                if let Some(joined) = self.open.remove(&player) {
+                    self.close(player, joined, time);
+                }
+            }
+            LogEvent::ServerLifecycle => {
+                let open: Vec<_> = self.open.drain().collect();
+                for (player, joined) in open {
+                    self.close(player, joined, time);
+                }
+            }
+        }
+    }
+}
+
+/// Session table built incrementally from the server log and persisted, so
+/// history outlives journal vacuuming and `latest.log` rotation.
+pub struct SessionTracker {
+    path: String,
+    parser: LogParser,
+    state: Mutex<SessionState>,
+}
+
+pub fn sessions_path() -> String {
+    std::env::var("SESSIONS_FILE").unwrap_or_else(|_| DEFAULT_SESSIONS_FILE.to_string())
+}
+
+impl SessionTracker {
+    /// Loads the table saved at `path`; a missing or unreadable file starts
+    /// an empty one.
+    pub fn load(path: String) -> Self {
+        let state = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                tracing::warn!("Ignoring unreadable sessions file {}: {}", path, e);
+                SessionState::default()
+            }),
+            Err(_) => SessionState::default(),
+        };
+        SessionTracker { path, parser: LogParser::new(), state: Mutex::new(state) }
+    }
+
+    pub fn from_env() -> Self {
+        Self::load(sessions_path())
+    }
+
+    /// Reads new lines from the backend's journal, or from `logs/latest.log`
+    /// when the backend doesn't log to journald or the journal is empty.
+    pub async fn update(&self, server_location: &str) -> Result<(), String> {
+        self.update_from(server_backend().journal_args(), server_location).await
+    }
+
+    async fn update_from(&self, journal_args: Option<Vec<String>>, server_location: &str) -> Result<(), String> {
+        let mut state = self.state.lock().await;
+        let before = (state.journal_cursor.clone(), state.log.clone());
+        let mut events = Vec::new();
+        let mut from_journal = false;
+        if let Some(args) = journal_args {
+            let mut cmd = Command::new("journalctl");
+            cmd.args(args).args(["--no-pager", "--output=short-iso", "--show-cursor"]);
+            if let Some(cursor) = &state.journal_cursor {
+                cmd.arg(format!("--after-cursor={}", cursor));
+            }
+            if let Ok(output) = cmd.output().await
+                && output.status.success()
+            {
+                for line in String::from_utf8_lossy(&output.stdout).lines() {
+                    match line.strip_prefix("-- cursor: ") {
+                        Some(cursor) => state.journal_cursor = Some(cursor.to_string()),
+                        None => events.extend(self.parser.journal_line(line)),
+                    }
+                }
+                from_journal = state.journal_cursor.is_some();
+            }
+        }
+        if !from_journal {
+            let log_path = Path::new(server_location).join(LATEST_LOG);
+            let date = match fs::metadata(&log_path).await.and_then(|m| m.modified()) {
+                Ok(modified) => DateTime::<Local>::from(modified).date_naive(),
+                Err(_) => Local::now().date_naive(),
+            };
+            let lines = read_new_lines(&log_path, &mut state.log).await;
+            events.extend(lines.iter().filter_map(|line| self.parser.latest_log_line(line, date)));
+        }
+        if events.is_empty() && (state.journal_cursor.clone(), state.log.clone()) == before {
+            return Ok(());
+        }
+        for (time, event) in events {
+            state.apply(time, event);
+        }
+        self.save(&state).await
+    }
+
+    async fn save(&self, state: &SessionState) -> Result<(), String> {
+        let contents = serde_json::to_string(state).map_err(|e| format!("Failed to serialize sessions: {}", e))?;
+        let tmp = format!("{}.tmp", self.path);
+        fs::write(&tmp, contents).await.map_err(|e| format!("Failed to write {}: {}", tmp, e))?;
+        fs::rename(&tmp, &self.path).await.map_err(|e| format!("Failed to write {}: {}", self.path, e))
+    }
+
+    /// All sessions, oldest first. Sessions still open have no `left` time
+    /// and last until now.
+    pub async fn sessions(&self) -> Vec<Session> {
+        let state = self.state.lock().await;
+        let now = Local::now().fixed_offset();
+        let mut sessions = state.sessions.clone();
+        sessions.extend(state.open.iter().map(|(player, joined)| Session {
+            player: player.clone(),
+            joined: *joined,
+            left: None,
+            duration_secs: (now - *joined).num_seconds(),
+        }));
+        sessions.sort_by_key(|s| s.joined);
+        sessions
+    }
+}
+
+/// Keeps the session table current even when nobody is looking at it, so
+/// lines are read before the log rotates.
+pub fn spawn_session_tracker(tracker: Arc<SessionTracker>, server_location: String) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(SESSION_POLL_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            if let Err(e) = tracker.update(&server_location).await {
+                tracing::warn!("{}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::temp_dir;
+    use super::*;
+
+    fn temp_server(name: &str) -> std::path::PathBuf {
+        let dir = temp_dir(&format!("sessions-{}", name));
+        std::fs::create_dir(dir.join("logs")).unwrap();
+        dir
+    }
+
+    fn append(path: &Path, lines: &[&str]) {
+        use std::io::Write;
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path).unwrap();
+        for line in lines {
+            writeln!(file, "{}", line).unwrap();
+        }
+    }
+
+    #[test]
+    fn parses_journal_and_latest_log_lines() {
+        let parser = LogParser::new();
+        let (time, event) = parser.journal_line("2026-10-18T12:00:00+0000 host startserver.sh[123]: [12:00:00] [Server thread/INFO]: Steve joined the game").unwrap();
+        assert_eq!(event, LogEvent::Join("Steve".to_string()));
+        assert_eq!(time.to_rfc3339(), "2026-10-18T12:00:00+00:00");
+
+        let date = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        let (time, event) = parser.latest_log_line("[13:30:00] [Server thread/INFO]: Alex left the game", date).unwrap();
+        assert_eq!(event, LogEvent::Leave("Alex".to_string()));
+        assert_eq!(time.naive_local().to_string(), "2026-10-18 13:30:00");
+        let (time, _) = parser.latest_log_line("[01Jan2025 08:00:00.123] [Server thread/INFO] [minecraft/MinecraftServer]: Alex joined the game", date).unwrap();
+        assert_eq!(time.naive_local().to_string(), "2025-01-01 08:00:00");
+        assert!(parser.latest_log_line("[13:30:00] [Server thread/INFO]: <Alex> I joined the game", date).is_none());
+    }
+
+    #[tokio::test]
+    async fn reads_incrementally_across_rotation_and_restarts() {
+        let dir = temp_server("incremental");
+        let location = dir.to_string_lossy().to_string();
+        let log = dir.join(LATEST_LOG);
+        let table = dir.join("sessions.json").to_string_lossy().to_string();
+
+        append(&log, &["[01Jan2025 08:00:00.000] [Server thread/INFO] [minecraft/MinecraftServer]: Steve joined the game"]);
+        let tracker = SessionTracker::load(table.clone());
+        tracker.update_from(None, &location).await.unwrap();
+        let sessions = tracker.sessions().await;
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].left.is_none());
+
+        append(&log, &[
+            "[01Jan2025 08:30:00.000] [Server thread/INFO] [minecraft/MinecraftServer]: Steve left the game",
+            "[01Jan2025 08:40:00.000] [Server thread/INFO] [minecraft/MinecraftServer]: Alex joined the game",
+        ]);
+        tracker.update_from(None, &location).await.unwrap();
+        tracker.update_from(None, &location).await.unwrap();
+        let sessions = tracker.sessions().await;
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].duration_secs, 1800);
+        assert_eq!(sessions[1].player, "Alex");
+
+        // The server restarts: the old log is rotated away and a new one begins.
+        std::fs::rename(&log, dir.join("logs/2025-01-01-1.log.gz")).unwrap();
+        append(&log, &[
+            "[01Jan2025 09:00:00.000] [main/INFO] [minecraft/DedicatedServer]: Starting minecraft server version 1.21.1",
+            "[01Jan2025 09:05:00.000] [Server thread/INFO] [minecraft/MinecraftServer]: Steve joined the game",
+        ]);
+        let reloaded = SessionTracker::load(table);
+        reloaded.update_from(None, &location).await.unwrap();
+        let sessions = reloaded.sessions().await;
+        let summary: Vec<_> = sessions.iter().map(|s| (s.player.as_str(), s.left.is_some())).collect();
+        assert_eq!(summary, [("Steve", true), ("Alex", true), ("Steve", false)]);
+        assert_eq!(sessions[1].duration_secs, 1200);
+
+        let totals = playtime_totals(&sessions[..2]);
+        assert_eq!(totals[0].player, "Steve");
+        assert_eq!(totals[0].total_secs, 1800);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
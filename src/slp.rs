@@ -0,0 +1,272 @@
+use std::time::{Duration, Instant};
+use rocket::tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::TcpStream;
+use rocket::tokio::time::timeout;
+use serde::{Deserialize, Serialize};
+use crate::constants::DEFAULT_SERVER_PORT;
+use crate::properties::read_server_properties;
+
+/// Protocol version sent in the handshake. Servers answer status requests
+/// regardless of the version, so -1 ("unknown") is fine.
+const HANDSHAKE_PROTOCOL: i32 = -1;
+const SLP_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SamplePlayer {
+    pub name: String,
+    pub id: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerStatus {
+    pub version: String,
+    pub protocol: i32,
+    pub motd: String,
+    pub online: i64,
+    pub max: i64,
+    pub sample: Vec<SamplePlayer>,
+    pub latency_ms: u64,
+}
+
+#[derive(Deserialize)]
+struct StatusVersion {
+    name: String,
+    protocol: i32,
+}
+
+#[derive(Deserialize)]
+struct StatusPlayers {
+    max: i64,
+    online: i64,
+    #[serde(default)]
+    sample: Vec<SamplePlayer>,
+}
+
+#[derive(Deserialize)]
+struct StatusResponse {
+    version: StatusVersion,
+    players: StatusPlayers,
+    #[serde(default)]
+    description: serde_json::Value,
+}
+
+fn write_varint(buf: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value & !0x7f == 0 {
+            buf.push(value as u8);
+            return;
+        }
+        buf.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
+async fn read_varint<R: AsyncRead + Unpin>(reader: &mut R) -> Result<i32, String> {
+    let mut value: u32 = 0;
+    for i in 0..5 {
+        let byte = reader.read_u8().await.map_err(|e| format!("Failed to read from server: {}", e))?;
+        value |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    Err("VarInt is too long".to_string())
+}
+
+fn packet(id: i32, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_varint(&mut body, id);
+    body.extend_from_slice(payload);
+    let mut out = Vec::new();
+    write_varint(&mut out, body.len() as i32);
+    out.extend_from_slice(&body);
+    out
+}
+
+/// Flattens a chat component (plain string or `{"text", "extra"}` object)
+/// into its text, keeping any `§` codes embedded in it.
+fn description_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(parts) => parts.iter().map(description_text).collect(),
+        serde_json::Value::Object(obj) => {
+            let mut text = obj.get("text").map(description_text).unwrap_or_default();
+            if let Some(extra) = obj.get("extra") {
+                text.push_str(&description_text(extra));
+            }
+            text
+        }
+        _ => String::new(),
+    }
+}
+
+async fn query(host: &str, port: u16) -> Result<ServerStatus, String> {
+    let mut stream = TcpStream::connect((host, port)).await
+        .map_err(|e| format!("Failed to connect to {}:{}: {}", host, port, e))?;
+
+    let mut handshake = Vec::new();
+    write_varint(&mut handshake, HANDSHAKE_PROTOCOL);
+    write_varint(&mut handshake, host.len() as i32);
+    handshake.extend_from_slice(host.as_bytes());
+    handshake.extend_from_slice(&port.to_be_bytes());
+    write_varint(&mut handshake, 1); // next state: status
+    let mut request = packet(0x00, &handshake);
+    request.extend(packet(0x00, &[]));
+    stream.write_all(&request).await.map_err(|e| format!("Failed to send status request: {}", e))?;
+
+    let _length = read_varint(&mut stream).await?;
+    if read_varint(&mut stream).await? != 0x00 {
+        return Err("Unexpected status response packet".to_string());
+    }
+    let json_len = read_varint(&mut stream).await?;
+    if !(0..=1 << 20).contains(&json_len) {
+        return Err(format!("Invalid status response length {}", json_len));
+    }
+    let mut json_buf = vec![0u8; json_len as usize];
+    stream.read_exact(&mut json_buf).await.map_err(|e| format!("Failed to read status response: {}", e))?;
+    let status: StatusResponse = serde_json::from_slice(&json_buf)
+        .map_err(|e| format!("Failed to parse status response: {}", e))?;
+
+    let token = chrono::Utc::now().timestamp_millis();
+    let started = Instant::now();
+    stream.write_all(&packet(0x01, &token.to_be_bytes())).await
+        .map_err(|e| format!("Failed to send ping: {}", e))?;
+    let _length = read_varint(&mut stream).await?;
+    if read_varint(&mut stream).await? != 0x01 {
+        return Err("Unexpected ping response packet".to_string());
+    }
+    let pong = stream.read_i64().await.map_err(|e| format!("Failed to read pong: {}", e))?;
+    if pong != token {
+        return Err("Ping response did not match".to_string());
+    }
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    Ok(ServerStatus {
+        version: status.version.name,
+        protocol: status.version.protocol,
+        motd: description_text(&status.description),
+        online: status.players.online,
+        max: status.players.max,
+        sample: status.players.sample,
+        latency_ms,
+    })
+}
+
+/// Runs a Server List Ping against `host:port`, giving up after a few seconds.
+pub async fn ping_server(host: &str, port: u16) -> Result<ServerStatus, String> {
+    timeout(SLP_TIMEOUT, query(host, port)).await
+        .map_err(|_| format!("Timed out querying {}:{}", host, port))?
+}
+
+/// Pings the local server on the `server-port` from its `server.properties`.
+pub async fn ping_local_server(server_location: &str) -> Result<ServerStatus, String> {
+    let port = read_server_properties(server_location).await.ok()
+        .and_then(|props| props.get("server-port").and_then(|p| p.trim().parse().ok()))
+        .unwrap_or(DEFAULT_SERVER_PORT);
+    ping_server("127.0.0.1", port).await
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::tokio::net::TcpListener;
+    use super::*;
+
+    /// How the fake server answers the status request.
+    enum Reply {
+        Status(&'static str),
+        /// Raw bytes after the packet id, e.g. a bogus JSON length, followed
+        /// by closing the connection.
+        Raw(Vec<u8>),
+    }
+
+    /// Accepts one connection, checks the handshake and status request, sends
+    /// `reply` and then echoes the ping. Returns the port it listens on.
+    async fn fake_server(reply: Reply) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let _length = read_varint(&mut stream).await.unwrap();
+            assert_eq!(read_varint(&mut stream).await.unwrap(), 0x00);
+            assert_eq!(read_varint(&mut stream).await.unwrap(), HANDSHAKE_PROTOCOL);
+            let host_len = read_varint(&mut stream).await.unwrap();
+            let mut host = vec![0u8; host_len as usize];
+            stream.read_exact(&mut host).await.unwrap();
+            assert_eq!(host, b"127.0.0.1");
+            assert_eq!(stream.read_u16().await.unwrap(), port);
+            assert_eq!(read_varint(&mut stream).await.unwrap(), 1);
+            assert_eq!(read_varint(&mut stream).await.unwrap(), 1);
+            assert_eq!(read_varint(&mut stream).await.unwrap(), 0x00);
+
+            let json = match reply {
+                Reply::Status(json) => json,
+                Reply::Raw(bytes) => {
+                    let _ = stream.write_all(&packet(0x00, &bytes)).await;
+                    return;
+                }
+            };
+            let mut payload = Vec::new();
+            write_varint(&mut payload, json.len() as i32);
+            payload.extend_from_slice(json.as_bytes());
+            stream.write_all(&packet(0x00, &payload)).await.unwrap();
+
+            let _length = read_varint(&mut stream).await.unwrap();
+            assert_eq!(read_varint(&mut stream).await.unwrap(), 0x01);
+            let token = stream.read_i64().await.unwrap();
+            stream.write_all(&packet(0x01, &token.to_be_bytes())).await.unwrap();
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn reads_status_and_latency() {
+        let port = fake_server(Reply::Status(r#"{
+            "version": {"name": "1.21.1", "protocol": 767},
+            "players": {"max": 20, "online": 2, "sample": [
+                {"name": "Steve", "id": "8667ba71-b85a-4004-af54-457a9734eed7"},
+                {"name": "Alex", "id": "ec561538-f3fd-461d-aff5-086b22154bce"}
+            ]},
+            "description": {"text": "§aAll the Mods", "extra": [{"text": " 10"}]}
+        }"#)).await;
+        let status = ping_server("127.0.0.1", port).await.unwrap();
+        assert_eq!(status.version, "1.21.1");
+        assert_eq!(status.protocol, 767);
+        assert_eq!(status.motd, "§aAll the Mods 10");
+        assert_eq!((status.online, status.max), (2, 20));
+        let names: Vec<_> = status.sample.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["Steve", "Alex"]);
+        assert!(status.latency_ms < SLP_TIMEOUT.as_millis() as u64);
+    }
+
+    #[tokio::test]
+    async fn plain_string_description_and_no_sample() {
+        let port = fake_server(Reply::Status(r#"{"version": {"name": "NeoForge 1.21.1", "protocol": 767},
+            "players": {"max": 5, "online": 0}, "description": "A Minecraft Server"}"#)).await;
+        let status = ping_server("127.0.0.1", port).await.unwrap();
+        assert_eq!(status.motd, "A Minecraft Server");
+        assert!(status.sample.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_lengths() {
+        let mut oversized = Vec::new();
+        write_varint(&mut oversized, (1 << 20) + 1);
+        let err = ping_server("127.0.0.1", fake_server(Reply::Raw(oversized)).await).await.unwrap_err();
+        assert_eq!(err, format!("Invalid status response length {}", (1 << 20) + 1));
+
+        let mut negative = Vec::new();
+        write_varint(&mut negative, -5);
+        let err = ping_server("127.0.0.1", fake_server(Reply::Raw(negative)).await).await.unwrap_err();
+        assert_eq!(err, "Invalid status response length -5");
+
+        let err = ping_server("127.0.0.1", fake_server(Reply::Raw(vec![0xff; 6])).await).await.unwrap_err();
+        assert_eq!(err, "VarInt is too long");
+
+        let mut truncated = Vec::new();
+        write_varint(&mut truncated, 100);
+        truncated.extend_from_slice(b"{\"version\"");
+        let err = ping_server("127.0.0.1", fake_server(Reply::Raw(truncated)).await).await.unwrap_err();
+        assert!(err.starts_with("Failed to read status response"), "{}", err);
+    }
+}
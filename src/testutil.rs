@@ -0,0 +1,87 @@
+//! Fixtures shared by the unit tests.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use rocket::http::Status;
+use rocket::tokio::sync::mpsc;
+use serde_json::Value;
+
+/// A fresh, empty directory under the system temp dir, unique to this test
+/// process and `name`.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mc-manager-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A request received by an HTTP stand-in.
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8(self.body.clone()).unwrap()
+    }
+
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap()
+    }
+}
+
+/// A local HTTP server answering each request with whatever `route` returns
+/// as status and body. Returns its base URL and the requests it received.
+pub fn http_stand_in<F>(mut route: F) -> (String, mpsc::UnboundedReceiver<Request>)
+where
+    F: FnMut(&Request) -> (u16, Vec<u8>) + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(&stream);
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).is_err() {
+                continue;
+            }
+            let mut headers = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                match line.trim_end().split_once(": ") {
+                    Some((name, value)) => headers.push((name.to_string(), value.to_string())),
+                    None => break,
+                }
+            }
+            let mut parts = request_line.split(' ');
+            let mut request = Request {
+                method: parts.next().unwrap_or_default().to_string(),
+                path: parts.next().unwrap_or_default().to_string(),
+                body: Vec::new(),
+                headers,
+            };
+            let len = request.header("Content-Length").map_or(0, |v| v.parse().unwrap());
+            request.body = vec![0; len];
+            reader.read_exact(&mut request.body).unwrap();
+
+            let (status, body) = route(&request);
+            let reason = Status::new(status).reason_lossy();
+            let mut stream = &stream;
+            write!(stream, "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, reason, body.len()).unwrap();
+            stream.write_all(&body).unwrap();
+            // Tests that don't look at the requests drop the receiver.
+            let _ = tx.send(request);
+        }
+    });
+    (url, rx)
+}
@@ -0,0 +1,277 @@
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use rocket::tokio;
+use serde::Serialize;
+use crate::constants::{DEFAULT_MSPT_WARN_ABOVE, DEFAULT_TPS_HISTORY, DEFAULT_TPS_INTERVAL_SECS, DEFAULT_TPS_WARN_BELOW, TPS_COMMAND_MAX_MISSES, TPS_COMMANDS};
+use crate::history::{History, Sample};
+use crate::serverctl::server_backend;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DimensionTps {
+    pub dimension: String,
+    pub tps: f64,
+    pub mspt: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TpsSample {
+    pub time: DateTime<Utc>,
+    pub overall: Option<DimensionTps>,
+    pub dimensions: Vec<DimensionTps>,
+    pub warnings: Vec<String>,
+}
+
+impl Sample for TpsSample {
+    fn time(&self) -> DateTime<Utc> {
+        self.time
+    }
+}
+
+pub type TpsHistory = History<TpsSample>;
+
+pub fn tps_history_from_env() -> TpsHistory {
+    History::from_env("TPS_INTERVAL_SECS", DEFAULT_TPS_INTERVAL_SECS, "TPS_HISTORY", DEFAULT_TPS_HISTORY)
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct LagThresholds {
+    pub tps_below: f64,
+    pub mspt_above: f64,
+}
+
+impl LagThresholds {
+    /// Reads `TPS_WARN_BELOW` and `MSPT_WARN_ABOVE`.
+    pub fn from_env() -> Self {
+        let read = |var: &str, default: f64| std::env::var(var).ok().and_then(|v| v.parse().ok()).unwrap_or(default);
+        LagThresholds {
+            tps_below: read("TPS_WARN_BELOW", DEFAULT_TPS_WARN_BELOW),
+            mspt_above: read("MSPT_WARN_ABOVE", DEFAULT_MSPT_WARN_ABOVE),
+        }
+    }
+
+    pub fn check(&self, dims: &[DimensionTps]) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for dim in dims {
+            if dim.tps < self.tps_below {
+                warnings.push(format!("{}: TPS {:.1} is below {:.1}", dim.dimension, dim.tps, self.tps_below));
+            }
+            if dim.mspt > self.mspt_above {
+                warnings.push(format!("{}: MSPT {:.1} is above {:.1}", dim.dimension, dim.mspt, self.mspt_above));
+            }
+        }
+        warnings
+    }
+}
+
+/// Parses the per-dimension output of `forge tps` / `neoforge tps`, or the
+/// overall figures from `spark tps`.
+pub struct TpsParser {
+    forge: Regex,
+    neoforge: Regex,
+    spark_tps: Regex,
+    spark_mspt: Regex,
+    format_codes: Regex,
+}
+
+impl TpsParser {
+    pub fn new() -> Self {
+        TpsParser {
+            // Dim minecraft:overworld (minecraft:overworld): Mean tick time: 1.234 ms. Mean TPS: 20.000
+            forge: Regex::new(r"(?m)^\s*(?:Dim\s+)?(.+?)(?:\s+\([^)]*\))?\s*: Mean tick time: ([\d.]+) ms\. Mean TPS: ([\d.]+)").unwrap(),
+            // minecraft:overworld: 20.000 TPS (1.234 ms/tick)
+            neoforge: Regex::new(r"(?m)^\s*(.+?)\s*: ([\d.]+) TPS \(([\d.]+) ms/tick\)").unwrap(),
+            // [⚡] TPS from last 5s, 10s, 1m, 5m, 15m:
+            // [⚡]  *20.0, 20.0, 20.0, 20.0, 20.0
+            spark_tps: Regex::new(r"TPS from last[^\n]*\n(?:\[[^\]\n]*\])?\s*\*?([\d.]+)").unwrap(),
+            // [⚡] Tick durations (min/med/95%ile/max ms) from last 10s, 1m:
+            // [⚡]  1.2/3.4/5.6/7.8; ...
+            spark_mspt: Regex::new(r"Tick durations[^\n]*\n(?:\[[^\]\n]*\])?\s*[\d.]+/([\d.]+)/").unwrap(),
+            format_codes: Regex::new(r"§.").unwrap(),
+        }
+    }
+
+    /// Returns the overall figures (if reported) and the per-dimension ones.
+    pub fn parse(&self, output: &str) -> (Option<DimensionTps>, Vec<DimensionTps>) {
+        let output = self.format_codes.replace_all(output, "");
+        let mut dims: Vec<DimensionTps> = self.forge.captures_iter(&output)
+            .filter_map(|c| Some(DimensionTps { dimension: c[1].to_string(), mspt: c[2].parse().ok()?, tps: c[3].parse().ok()? }))
+            .collect();
+        if dims.is_empty() {
+            dims = self.neoforge.captures_iter(&output)
+                .filter_map(|c| Some(DimensionTps { dimension: c[1].to_string(), tps: c[2].parse().ok()?, mspt: c[3].parse().ok()? }))
+                .collect();
+        }
+        if dims.is_empty()
+            && let Some(tps) = self.spark_tps.captures(&output).and_then(|c| c[1].parse().ok())
+        {
+            let mspt = self.spark_mspt.captures(&output).and_then(|c| c[1].parse().ok()).unwrap_or(0.0);
+            return (Some(DimensionTps { dimension: "Overall".to_string(), tps, mspt }), Vec::new());
+        }
+        let overall = dims.iter().position(|d| d.dimension.eq_ignore_ascii_case("overall")).map(|i| dims.remove(i));
+        (overall, dims)
+    }
+}
+
+impl Default for TpsParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The console commands to try, in order. `TPS_COMMAND` pins a single one.
+fn tps_commands() -> Vec<String> {
+    match std::env::var("TPS_COMMAND") {
+        Ok(cmd) if !cmd.trim().is_empty() => vec![cmd],
+        _ => TPS_COMMANDS.iter().map(|c| c.to_string()).collect(),
+    }
+}
+
+/// Which TPS command to send. The first one that produces parseable output
+/// is remembered, and forgotten again after `TPS_COMMAND_MAX_MISSES` samples
+/// in a row that don't parse, since the pack may have changed underneath.
+struct CommandPicker {
+    commands: Vec<String>,
+    working: Option<String>,
+    misses: u32,
+}
+
+impl CommandPicker {
+    fn new(commands: Vec<String>) -> Self {
+        CommandPicker { commands, working: None, misses: 0 }
+    }
+
+    fn candidates(&self) -> Vec<String> {
+        match &self.working {
+            Some(cmd) => vec![cmd.clone()],
+            None => self.commands.clone(),
+        }
+    }
+
+    fn parsed(&mut self, cmd: &str) {
+        self.working = Some(cmd.to_string());
+        self.misses = 0;
+    }
+
+    /// None of this sample's candidates parsed.
+    fn missed(&mut self) {
+        if self.working.is_some() {
+            self.misses += 1;
+            if self.misses >= TPS_COMMAND_MAX_MISSES {
+                self.forget();
+            }
+        }
+    }
+
+    fn forget(&mut self) {
+        self.working = None;
+        self.misses = 0;
+    }
+}
+
+/// Polls TPS through the server console while the server is running. The
+/// working command is looked up again whenever the server (re)starts.
+pub fn spawn_tps_sampler(history: Arc<TpsHistory>, thresholds: LagThresholds) {
+    tokio::spawn(async move {
+        let parser = TpsParser::new();
+        let mut picker = CommandPicker::new(tps_commands());
+        let mut started: Option<String> = None;
+        let mut interval = tokio::time::interval(Duration::from_secs(history.interval_secs));
+        loop {
+            interval.tick().await;
+            let backend = server_backend();
+            let since = backend.status().await.filter(|s| s.active_state == "active").and_then(|s| s.active_since);
+            if since != started {
+                picker.forget();
+                started = since;
+            }
+            if !backend.is_active().await {
+                continue;
+            }
+            let mut sampled = false;
+            for cmd in picker.candidates() {
+                let Ok(output) = backend.command_output(&cmd).await else { break };
+                let (overall, dimensions) = parser.parse(&output);
+                if overall.is_none() && dimensions.is_empty() {
+                    continue;
+                }
+                picker.parsed(&cmd);
+                sampled = true;
+                let mut checked: Vec<DimensionTps> = overall.iter().cloned().collect();
+                checked.extend(dimensions.iter().cloned());
+                let warnings = thresholds.check(&checked);
+                for warning in &warnings {
+                    tracing::warn!("{}", warning);
+                }
+                history.push(TpsSample { time: Utc::now(), overall, dimensions, warnings });
+                break;
+            }
+            if !sampled {
+                picker.missed();
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_forge_tps() {
+        let output = "\
+Dim minecraft:overworld (minecraft:overworld): Mean tick time: 12.345 ms. Mean TPS: 20.000
+Dim minecraft:the_nether (minecraft:the_nether): Mean tick time: 61.500 ms. Mean TPS: 16.260
+Overall: Mean tick time: 40.000 ms. Mean TPS: 18.500";
+        let (overall, dims) = TpsParser::new().parse(output);
+        let overall = overall.unwrap();
+        assert_eq!((overall.tps, overall.mspt), (18.5, 40.0));
+        assert_eq!(dims.len(), 2);
+        assert_eq!((dims[0].dimension.as_str(), dims[0].tps, dims[0].mspt), ("minecraft:overworld", 20.0, 12.345));
+        assert_eq!((dims[1].dimension.as_str(), dims[1].tps, dims[1].mspt), ("minecraft:the_nether", 16.26, 61.5));
+    }
+
+    #[test]
+    fn parses_spark_tps() {
+        let output = "\
+§8[§e⚡§8] §7TPS from last 5s, 10s, 1m, 5m, 15m:
+§8[§e⚡§8]  §a*20.0, §a19.9, §a19.8, §e17.5, §a20.0
+§8[§e⚡§8] §7Tick durations (min/med/95%ile/max ms) from last 10s, 1m:
+§8[§e⚡§8]  §a1.2§7/§a48.6§7/§e60.1§7/§c120.4§7;  §a1.0§7/§a8.3§7/§a12.0§7/§c200.3
+§8[§e⚡§8] §7CPU usage from last 10s, 1m, 15m:";
+        let (overall, dims) = TpsParser::new().parse(output);
+        let overall = overall.unwrap();
+        assert_eq!((overall.dimension.as_str(), overall.tps, overall.mspt), ("Overall", 20.0, 48.6));
+        assert!(dims.is_empty());
+
+        let plain = "TPS from last 5s, 10s, 1m, 5m, 15m:\n 19.5, 19.9, 20.0, 20.0, 20.0\nTick durations (min/med/95%ile/max ms) from last 10s, 1m:\n 2.0/10.0/20.0/30.0";
+        let overall = TpsParser::new().parse(plain).0.unwrap();
+        assert_eq!((overall.tps, overall.mspt), (19.5, 10.0));
+    }
+
+    #[test]
+    fn parses_neither_from_other_output() {
+        let (overall, dims) = TpsParser::new().parse("Unknown or incomplete command, see below for error");
+        assert!(overall.is_none() && dims.is_empty());
+    }
+
+    #[test]
+    fn forgets_a_command_that_stops_parsing() {
+        let mut picker = CommandPicker::new(vec!["forge tps".to_string(), "spark tps".to_string()]);
+        picker.parsed("spark tps");
+        assert_eq!(picker.candidates(), ["spark tps"]);
+        for _ in 1..TPS_COMMAND_MAX_MISSES {
+            picker.missed();
+            assert_eq!(picker.candidates(), ["spark tps"]);
+        }
+        picker.parsed("spark tps");
+        for _ in 0..TPS_COMMAND_MAX_MISSES {
+            picker.missed();
+        }
+        assert_eq!(picker.candidates(), ["forge tps", "spark tps"]);
+        picker.parsed("forge tps");
+        picker.forget();
+        assert_eq!(picker.candidates().len(), 2);
+    }
+}
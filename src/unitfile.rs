@@ -0,0 +1,244 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use rocket::tokio::fs;
+use rocket::tokio::process::Command;
+use serde::{Deserialize, Serialize};
+use crate::constants::{DEFAULT_RESTART_DELAY_SECS, DEFAULT_STOP_TIMEOUT_SECS};
+
+const RESTART_VALUES: [&str; 7] = ["no", "on-success", "on-failure", "on-abnormal", "on-watchdog", "on-abort", "always"];
+
+/// Settings for the generated unit. Anything left out gets a default based
+/// on the server directory.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UnitOptions {
+    pub description: Option<String>,
+    pub working_directory: Option<String>,
+    /// Program to run; defaults to `<working directory>/startserver.sh`.
+    pub exec_start: Option<String>,
+    /// Arguments passed to `exec_start`.
+    pub exec_args: Vec<String>,
+    /// A systemd `Restart=` value; defaults to `on-failure`.
+    pub restart: Option<String>,
+    pub restart_sec: Option<u64>,
+    pub stop_timeout_secs: Option<u64>,
+    /// `MemoryMax=`, e.g. `12G`.
+    pub memory_max: Option<String>,
+    /// `MemoryHigh=`, where the kernel starts reclaiming memory.
+    pub memory_high: Option<String>,
+    pub environment: BTreeMap<String, String>,
+}
+
+fn check_value(name: &str, value: &str) -> Result<(), String> {
+    if value.is_empty() || value.contains(['\n', '\r']) {
+        return Err(format!("{} must be a non-empty single line.", name));
+    }
+    Ok(())
+}
+
+fn check_memory(name: &str, value: &str) -> Result<(), String> {
+    let digits = value.trim_end_matches(['K', 'M', 'G', 'T', '%']);
+    let suffixes = value.len() - digits.len();
+    if value == "infinity" || (!digits.is_empty() && suffixes <= 1 && digits.chars().all(|c| c.is_ascii_digit())) {
+        Ok(())
+    } else {
+        Err(format!("{} must be a byte size like 12G, a percentage or 'infinity'.", name))
+    }
+}
+
+/// Doubles `%` so systemd doesn't read it as a specifier.
+fn escape_specifiers(value: &str) -> String {
+    value.replace('%', "%%")
+}
+
+/// Quotes one word of an `ExecStart=` command line. systemd splits the line
+/// on whitespace, unquotes C-style escapes and expands `$VAR`, so each word
+/// is double-quoted with `\`, `"` and `$` escaped.
+fn quote_exec_word(word: &str) -> String {
+    let escaped = word.replace('\\', "\\\\").replace('"', "\\\"").replace('$', "$$");
+    format!("\"{}\"", escape_specifiers(&escaped))
+}
+
+/// Renders a user unit for the server in `server_location`. Java exits with
+/// 143 on SIGTERM, which counts as a clean stop rather than a failure.
+pub fn render_unit(server_location: &str, opts: &UnitOptions) -> Result<String, String> {
+    let working_directory = match &opts.working_directory {
+        Some(dir) => dir.clone(),
+        None => std::path::absolute(server_location)
+            .map_err(|e| format!("Failed to resolve {}: {}", server_location, e))?
+            .to_string_lossy()
+            .to_string(),
+    };
+    let exec_start = opts.exec_start.clone()
+        .unwrap_or_else(|| Path::new(&working_directory).join("startserver.sh").to_string_lossy().to_string());
+    let description = opts.description.clone().unwrap_or_else(|| format!("Minecraft server ({})", working_directory));
+    let restart = opts.restart.clone().unwrap_or_else(|| "on-failure".to_string());
+
+    check_value("description", &description)?;
+    check_value("working_directory", &working_directory)?;
+    check_value("exec_start", &exec_start)?;
+    for arg in &opts.exec_args {
+        if arg.contains(['\n', '\r']) {
+            return Err("exec_args must be single lines.".to_string());
+        }
+    }
+    if !Path::new(&working_directory).is_absolute() {
+        return Err("working_directory must be an absolute path.".to_string());
+    }
+    if !RESTART_VALUES.contains(&restart.as_str()) {
+        return Err(format!("restart must be one of: {}.", RESTART_VALUES.join(", ")));
+    }
+
+    let command: Vec<String> = std::iter::once(&exec_start).chain(&opts.exec_args).map(|word| quote_exec_word(word)).collect();
+    // WorkingDirectory= takes the rest of the line as the path, spaces and
+    // all; it only expands specifiers.
+    let mut unit = format!(
+        "[Unit]\nDescription={}\nAfter=network.target\n\n[Service]\nType=simple\nWorkingDirectory={}\nExecStart={}\n",
+        escape_specifiers(&description), escape_specifiers(&working_directory), command.join(" "),
+    );
+    unit += &format!("Restart={}\nRestartSec={}\n", restart, opts.restart_sec.unwrap_or(DEFAULT_RESTART_DELAY_SECS));
+    unit += &format!("TimeoutStopSec={}\nSuccessExitStatus=0 143\n", opts.stop_timeout_secs.unwrap_or(DEFAULT_STOP_TIMEOUT_SECS));
+    if let Some(max) = &opts.memory_max {
+        check_memory("memory_max", max)?;
+        unit += &format!("MemoryMax={}\n", max);
+    }
+    if let Some(high) = &opts.memory_high {
+        check_memory("memory_high", high)?;
+        unit += &format!("MemoryHigh={}\n", high);
+    }
+    for (key, value) in &opts.environment {
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("Invalid environment variable name: {}", key));
+        }
+        check_value(key, value)?;
+        unit += &format!("Environment=\"{}={}\"\n", key, escape_specifiers(&value.replace('\\', "\\\\").replace('"', "\\\"")));
+    }
+    unit += "\n[Install]\nWantedBy=default.target\n";
+    Ok(unit)
+}
+
+/// `~/.config/systemd/user/<unit>`, honouring `XDG_CONFIG_HOME`.
+pub fn user_unit_path(unit: &str) -> Result<PathBuf, String> {
+    let config = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var("HOME").map_err(|_| "HOME is not set".to_string())?).join(".config"),
+    };
+    Ok(config.join("systemd/user").join(unit))
+}
+
+/// A line diff of `old` against `new`, with `-`/`+`/space prefixes. Unit files
+/// are short, so the whole file is shown rather than hunks.
+pub fn line_diff(old: &str, new: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    // lcs[i][j]: longest common subsequence of a[i..] and b[j..].
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut out = String::new();
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            out += &format!(" {}\n", a[i]);
+            i += 1;
+            j += 1;
+        } else if j < b.len() && (i == a.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            out += &format!("+{}\n", b[j]);
+            j += 1;
+        } else {
+            out += &format!("-{}\n", a[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+/// What installing the unit would write, compared with what's there now.
+#[derive(Debug, Clone, Serialize)]
+pub struct UnitPlan {
+    pub unit: String,
+    pub path: String,
+    pub content: String,
+    pub exists: bool,
+    pub changed: bool,
+    /// Diff against the existing unit, if there is one and it differs.
+    pub diff: Option<String>,
+}
+
+impl UnitPlan {
+    /// Installing would overwrite a unit someone may have edited by hand.
+    pub fn overwrites(&self) -> bool {
+        self.exists && self.changed
+    }
+}
+
+pub async fn plan_unit(unit: &str, server_location: &str, opts: &UnitOptions) -> Result<UnitPlan, String> {
+    let content = render_unit(server_location, opts)?;
+    let path = user_unit_path(unit)?;
+    let existing = match fs::read_to_string(&path).await {
+        Ok(existing) => Some(existing),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    let changed = existing.as_deref() != Some(content.as_str());
+    Ok(UnitPlan {
+        unit: unit.to_string(),
+        path: path.to_string_lossy().to_string(),
+        exists: existing.is_some(),
+        diff: existing.filter(|_| changed).map(|old| line_diff(&old, &content)),
+        changed,
+        content,
+    })
+}
+
+async fn systemctl_user(args: &[&str]) -> Result<(), String> {
+    let output = Command::new("systemctl").arg("--user").args(args).output().await
+        .map_err(|e| format!("Failed to run systemctl: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("systemctl --user {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
+
+/// Writes the planned unit, reloads the user manager and enables the unit.
+pub async fn install_unit(plan: &UnitPlan) -> Result<(), String> {
+    let path = Path::new(&plan.path);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).await.map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    fs::write(path, &plan.content).await.map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    systemctl_user(&["daemon-reload"]).await?;
+    systemctl_user(&["enable", &plan.unit]).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_with_spaces_are_quoted() {
+        let opts = UnitOptions { exec_args: vec!["--mem".to_string(), "50%".to_string(), "say \"hi\" $USER".to_string()], ..Default::default() };
+        let unit = render_unit("/srv/my server", &opts).unwrap();
+        assert!(unit.contains("\nDescription=Minecraft server (/srv/my server)\n"));
+        assert!(unit.contains("\nWorkingDirectory=/srv/my server\n"));
+        assert!(unit.contains("\nExecStart=\"/srv/my server/startserver.sh\" \"--mem\" \"50%%\" \"say \\\"hi\\\" $$USER\"\n"));
+    }
+
+    #[test]
+    fn specifiers_are_escaped() {
+        let opts = UnitOptions {
+            working_directory: Some("/srv/100%".to_string()),
+            exec_start: Some("/opt/a\\b.sh".to_string()),
+            environment: BTreeMap::from([("JAVA_OPTS".to_string(), "-Xmx80%".to_string())]),
+            ..Default::default()
+        };
+        let unit = render_unit("/ignored", &opts).unwrap();
+        assert!(unit.contains("\nWorkingDirectory=/srv/100%%\n"));
+        assert!(unit.contains("\nExecStart=\"/opt/a\\\\b.sh\"\n"));
+        assert!(unit.contains("\nEnvironment=\"JAVA_OPTS=-Xmx80%%\"\n"));
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use rocket::tokio::fs;
+use rocket::tokio::task::spawn_blocking;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use zip::write::{SimpleFileOptions, ZipWriter};
+use crate::constants::{DEFAULT_LEVEL_NAME, DEFAULT_WORLD_ARCHIVE_DIR, LEVEL_DAT};
+use crate::jobs::JobHandle;
+use crate::nbt::{self, Tag};
+use crate::operations::tree_totals;
+use crate::properties::{ServerProperties, read_server_properties, write_server_properties};
+use crate::provision::unpack;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WorldInfo {
+    pub name: String,
+    pub files: u64,
+    pub size_bytes: u64,
+    /// When `level.dat` was last written, i.e. the last save.
+    pub last_saved: Option<DateTime<Utc>>,
+    /// This is the `level-name` in `server.properties`.
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorldReset {
+    /// Seed for the new world; random if empty or missing.
+    pub seed: Option<String>,
+}
+
+/// The world the server loads: `level-name` from `server.properties`.
+pub async fn active_world(server_location: &str) -> String {
+    read_server_properties(server_location).await.ok()
+        .and_then(|props| props.get("level-name").map(|name| name.trim().to_string()))
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| DEFAULT_LEVEL_NAME.to_string())
+}
+
+/// World names are directories directly inside the server directory.
+pub fn check_world_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | ' ' | '.'));
+    if valid { Ok(()) } else { Err(format!("Invalid world name '{}'.", name)) }
+}
+
+/// Directories in the server directory that contain a `level.dat`.
+pub async fn list_worlds(server_location: &str) -> Result<Vec<WorldInfo>, String> {
+    let active = active_world(server_location).await;
+    let mut entries = fs::read_dir(server_location).await
+        .map_err(|e| format!("Failed to read {}: {}", server_location, e))?;
+    let mut worlds = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        let Ok(level) = fs::metadata(path.join(LEVEL_DAT)).await else { continue };
+        let name = entry.file_name().to_string_lossy().to_string();
+        let (files, size_bytes) = tree_totals(&path).await;
+        worlds.push(WorldInfo {
+            active: name == active,
+            last_saved: level.modified().ok().map(DateTime::<Utc>::from),
+            name,
+            files,
+            size_bytes,
+        });
+    }
+    worlds.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(worlds)
+}
+
+/// Zips the files in `dir` into `out` with entries under `<prefix>/`. Writes
+/// in streaming mode, so `out` can be a pipe. Directories are implied by the
+/// file paths: in streaming mode the zip crate flags directory entries as
+/// having a data descriptor without writing one, which `unzip` rejects.
+fn write_zip<W: Write>(dir: &Path, prefix: &str, out: W, job: Option<&JobHandle>) -> Result<(), String> {
+    let mut zip = ZipWriter::new_stream(out);
+    let options = SimpleFileOptions::default().large_file(true);
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(path) = stack.pop() {
+        let meta = std::fs::symlink_metadata(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if meta.is_dir() {
+            for entry in std::fs::read_dir(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))? {
+                stack.push(entry.map_err(|e| e.to_string())?.path());
+            }
+        } else if meta.is_file() {
+            let relative = path.strip_prefix(dir).unwrap_or(&path).to_string_lossy().to_string();
+            zip.start_file(format!("{}/{}", prefix, relative), options).map_err(|e| e.to_string())?;
+            let mut file = std::fs::File::open(&path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+            let bytes = std::io::copy(&mut file, &mut zip).map_err(|e| format!("Failed to zip {}: {}", path.display(), e))?;
+            if let Some(job) = job {
+                job.advance(1, bytes);
+            }
+        }
+    }
+    zip.finish().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Starts zipping a world on a blocking thread and returns the read end of
+/// the pipe it writes to, so the download starts without buffering the world.
+pub async fn world_zip_stream(server_location: &str, name: &str) -> Result<fs::File, String> {
+    check_world_name(name)?;
+    let dir = Path::new(server_location).join(name);
+    if fs::metadata(dir.join(LEVEL_DAT)).await.is_err() {
+        return Err(format!("No world named '{}'.", name));
+    }
+    let (reader, writer) = std::io::pipe().map_err(|e| format!("Failed to create pipe: {}", e))?;
+    let prefix = name.to_string();
+    spawn_blocking(move || {
+        if let Err(e) = write_zip(&dir, &prefix, writer, None) {
+            // Usually the client going away mid-download.
+            tracing::warn!("Stopped zipping {}: {}", dir.display(), e);
+        }
+    });
+    Ok(fs::File::from_std(std::fs::File::from(std::os::fd::OwnedFd::from(reader))))
+}
+
+/// Unpacks an uploaded world zip as `<server>/<name>`. The zip may hold the
+/// world's files directly or inside one top-level directory, and must
+/// contain a gzipped `level.dat`.
+pub async fn install_world(server_location: &str, name: &str, archive: &Path) -> Result<u64, String> {
+    check_world_name(name)?;
+    let dest = Path::new(server_location).join(name);
+    if fs::metadata(&dest).await.is_ok() {
+        return Err(format!("'{}' already exists.", name));
+    }
+    let staging = Path::new(server_location).join(format!(".{}.upload", name));
+    let _ = fs::remove_dir_all(&staging).await;
+    let (archive_path, staging_path) = (archive.to_path_buf(), staging.clone());
+    let unpacked = spawn_blocking(move || unpack(&archive_path, &staging_path)).await.map_err(|e| e.to_string())?;
+    let checked = match unpacked {
+        Ok(files) => check_level_dat(&staging).await.map(|_| files),
+        Err(e) => Err(e),
+    };
+    let files = match checked {
+        Ok(files) => files,
+        Err(e) => {
+            let _ = fs::remove_dir_all(&staging).await;
+            return Err(e);
+        }
+    };
+    fs::rename(&staging, &dest).await.map_err(|e| format!("Failed to move world into place: {}", e))?;
+    Ok(files)
+}
+
+async fn check_level_dat(world: &Path) -> Result<(), String> {
+    let bytes = fs::read(world.join(LEVEL_DAT)).await
+        .map_err(|_| "The zip has no level.dat at its top level.".to_string())?;
+    let (_, root) = nbt::from_gzip(&bytes).map_err(|e| format!("level.dat is invalid: {}", e))?;
+    match root.get("Data") {
+        Some(Tag::Compound(_)) => Ok(()),
+        _ => Err("level.dat has no Data compound.".to_string()),
+    }
+}
+
+/// Points `level-name` at an existing world. Takes effect on the next start.
+pub async fn switch_world(server_location: &str, name: &str) -> Result<(), String> {
+    check_world_name(name)?;
+    if fs::metadata(Path::new(server_location).join(name).join(LEVEL_DAT)).await.is_err() {
+        return Err(format!("No world named '{}'.", name));
+    }
+    let mut props = read_server_properties(server_location).await?;
+    props.set("level-name", name);
+    write_server_properties(server_location, &props).await
+}
+
+fn archive_dir(server_location: &str) -> PathBuf {
+    let dir = std::env::var("WORLD_ARCHIVE_DIR").unwrap_or_else(|_| DEFAULT_WORLD_ARCHIVE_DIR.to_string());
+    Path::new(server_location).join(dir)
+}
+
+/// Zips the active world into the archive directory, deletes it and sets
+/// `level-seed`, so the server generates a new world on its next start. An
+/// empty or missing seed lets the server pick one.
+pub async fn run_reset_world(job: JobHandle, server_location: String, seed: Option<String>) -> Result<Value, String> {
+    let name = active_world(&server_location).await;
+    let world = Path::new(&server_location).join(&name);
+    let mut archive = None;
+    if fs::metadata(&world).await.is_ok() {
+        let dir = archive_dir(&server_location);
+        fs::create_dir_all(&dir).await.map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let path = dir.join(format!("{}-{}.zip", name, Utc::now().format("%Y%m%d-%H%M%S")));
+        let (files, bytes) = tree_totals(&world).await;
+        job.set_totals(files, bytes);
+        job.log(format!("Archiving {} to {}", name, path.display()));
+        let (world_path, zip_path, prefix, zip_job) = (world.clone(), path.clone(), name.clone(), job.clone());
+        spawn_blocking(move || {
+            let out = std::fs::File::create(&zip_path).map_err(|e| format!("Failed to create {}: {}", zip_path.display(), e))?;
+            write_zip(&world_path, &prefix, std::io::BufWriter::new(out), Some(&zip_job))
+        }).await.map_err(|e| e.to_string())??;
+        fs::remove_dir_all(&world).await.map_err(|e| format!("Failed to remove {}: {}", world.display(), e))?;
+        job.log(format!("Removed {}", name));
+        archive = Some(path.to_string_lossy().to_string());
+    }
+
+    let seed = seed.unwrap_or_default();
+    let mut props: ServerProperties = read_server_properties(&server_location).await.unwrap_or_default();
+    props.set("level-seed", &seed);
+    write_server_properties(&server_location, &props).await?;
+    job.log(if seed.is_empty() { "Cleared level-seed".to_string() } else { format!("Set level-seed to {}", seed) });
+
+    Ok(json!({"world": name, "archive": archive, "seed": seed}))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SpawnPoint {
+    pub x: i64,
+    pub y: i64,
+    pub z: i64,
+    pub angle: Option<f64>,
+    /// Only recorded by versions that allow spawning outside the overworld.
+    pub dimension: Option<String>,
+}
+
+/// The parts of `level.dat` worth showing an operator.
+#[derive(Debug, Clone, Serialize)]
+pub struct LevelInfo {
+    pub world: String,
+    pub level_name: Option<String>,
+    pub version: Option<String>,
+    pub data_version: Option<i64>,
+    pub seed: Option<i64>,
+    pub spawn: Option<SpawnPoint>,
+    /// Ticks since the world was created.
+    pub time: Option<i64>,
+    /// Time of day in ticks; 24000 per day, so this also counts days.
+    pub day_time: Option<i64>,
+    pub day: Option<i64>,
+    pub game_rules: BTreeMap<String, String>,
+    pub enabled_data_packs: Vec<String>,
+    pub disabled_data_packs: Vec<String>,
+}
+
+/// Game rules are strings in older saves and typed tags in newer ones.
+fn rule_value(tag: &Tag) -> String {
+    match tag {
+        Tag::String(s) => s.clone(),
+        Tag::Byte(b) => (*b != 0).to_string(),
+        other => other.as_i64().map(|v| v.to_string()).unwrap_or_else(|| format!("{:?}", other)),
+    }
+}
+
+fn string_list(tag: Option<&Tag>) -> Vec<String> {
+    tag.and_then(|t| t.as_list())
+        .map(|items| items.iter().filter_map(|i| i.as_str().map(|s| s.to_string())).collect())
+        .unwrap_or_default()
+}
+
+fn spawn_point(data: &Tag) -> Option<SpawnPoint> {
+    // 1.21.9+ keeps the spawn in a compound; older versions use SpawnX/Y/Z.
+    if let Some(Tag::IntArray(pos)) = data.path("spawn/pos") && pos.len() == 3 {
+        return Some(SpawnPoint {
+            x: pos[0].into(),
+            y: pos[1].into(),
+            z: pos[2].into(),
+            angle: data.path("spawn/yaw").and_then(|t| t.as_f64()),
+            dimension: data.path("spawn/dimension").and_then(|t| t.as_str()).map(|s| s.to_string()),
+        });
+    }
+    Some(SpawnPoint {
+        x: data.get("SpawnX")?.as_i64()?,
+        y: data.get("SpawnY")?.as_i64()?,
+        z: data.get("SpawnZ")?.as_i64()?,
+        angle: data.get("SpawnAngle").and_then(|t| t.as_f64()),
+        dimension: None,
+    })
+}
+
+fn level_dat_path(server_location: &str, world: &str) -> Result<PathBuf, String> {
+    check_world_name(world)?;
+    Ok(Path::new(server_location).join(world).join(LEVEL_DAT))
+}
+
+async fn read_level_dat(path: &Path) -> Result<(String, Tag), String> {
+    let bytes = fs::read(path).await.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    nbt::from_gzip(&bytes).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+}
+
+pub async fn read_level_info(server_location: &str, world: &str) -> Result<LevelInfo, String> {
+    let (_, root) = read_level_dat(&level_dat_path(server_location, world)?).await?;
+    let data = root.get("Data").ok_or("level.dat has no Data compound.")?;
+    let day_time = data.get("DayTime").and_then(|t| t.as_i64());
+    Ok(LevelInfo {
+        world: world.to_string(),
+        level_name: data.get("LevelName").and_then(|t| t.as_str()).map(|s| s.to_string()),
+        version: data.path("Version/Name").and_then(|t| t.as_str()).map(|s| s.to_string()),
+        data_version: data.get("DataVersion").and_then(|t| t.as_i64()),
+        seed: data.path("WorldGenSettings/seed").or_else(|| data.get("RandomSeed")).and_then(|t| t.as_i64()),
+        spawn: spawn_point(data),
+        time: data.get("Time").and_then(|t| t.as_i64()),
+        day: day_time.map(|t| t / 24000),
+        day_time,
+        game_rules: data.get("GameRules").and_then(|t| t.as_compound())
+            .map(|rules| rules.iter().map(|(name, tag)| (name.clone(), rule_value(tag))).collect())
+            .unwrap_or_default(),
+        enabled_data_packs: string_list(data.path("DataPacks/Enabled")),
+        disabled_data_packs: string_list(data.path("DataPacks/Disabled")),
+    })
+}
+
+/// The new tag for a rule, keeping the type the save already uses for it.
+fn parse_rule(name: &str, current: &Tag, value: &str) -> Result<Tag, String> {
+    let as_bool = || match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("{} must be true or false.", name)),
+    };
+    let as_int = || value.parse::<i64>().map_err(|_| format!("{} must be an integer.", name));
+    match current {
+        Tag::String(s) if s == "true" || s == "false" => as_bool().map(|b| Tag::String(b.to_string())),
+        Tag::String(_) => as_int().map(|v| Tag::String(v.to_string())),
+        Tag::Byte(_) => as_bool().map(|b| Tag::Byte(b as i8)),
+        Tag::Int(_) => as_int().and_then(|v| i32::try_from(v).map_err(|_| format!("{} is out of range.", name))).map(Tag::Int),
+        Tag::Long(_) => as_int().map(Tag::Long),
+        _ => Err(format!("{} has a type that can't be edited.", name)),
+    }
+}
+
+/// Changes existing game rules in a world's `level.dat`. The server must be
+/// stopped, or it overwrites the file on its next save. The previous file is
+/// kept as `level.dat_old`, as Minecraft does.
+pub async fn set_game_rules(server_location: &str, world: &str, changes: &HashMap<String, String>) -> Result<BTreeMap<String, String>, Vec<String>> {
+    let path = level_dat_path(server_location, world).map_err(|e| vec![e])?;
+    let (name, mut root) = read_level_dat(&path).await.map_err(|e| vec![e])?;
+    let Some(Tag::Compound(rules)) = root.get_mut("Data").and_then(|data| data.get_mut("GameRules")) else {
+        return Err(vec!["level.dat has no GameRules.".to_string()]);
+    };
+    let mut errors = Vec::new();
+    for (rule, value) in changes {
+        match rules.iter_mut().find(|(n, _)| n == rule) {
+            Some((_, tag)) => match parse_rule(rule, tag, value.trim()) {
+                Ok(new) => *tag = new,
+                Err(e) => errors.push(e),
+            },
+            None => errors.push(format!("Unknown game rule: {}", rule)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let updated = rules.iter().map(|(n, tag)| (n.clone(), rule_value(tag))).collect();
+
+    let bytes = nbt::to_gzip(&name, &root).map_err(|e| vec![e])?;
+    let io_error = |e: std::io::Error| vec![format!("Failed to write {}: {}", path.display(), e)];
+    let tmp = path.with_extension("dat_new");
+    fs::write(&tmp, bytes).await.map_err(io_error)?;
+    fs::copy(&path, path.with_extension("dat_old")).await.map_err(io_error)?;
+    fs::rename(&tmp, &path).await.map_err(io_error)?;
+    Ok(updated)
+}